@@ -0,0 +1,33 @@
+//! Applies build-time overrides of the crate's constants.
+
+use std::{env, fs, path::Path};
+
+/// Environment variable overriding `MAX_ACTIVE_FILE_SIZE`, as a number of bytes
+const MAX_ACTIVE_FILE_SIZE_VAR: &str = "BITASK_MAX_ACTIVE_FILE_SIZE";
+
+/// `MAX_ACTIVE_FILE_SIZE` when the environment variable isn't set (4MB)
+const DEFAULT_MAX_ACTIVE_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed={}", MAX_ACTIVE_FILE_SIZE_VAR);
+
+    let max_active_file_size = match env::var(MAX_ACTIVE_FILE_SIZE_VAR) {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(size) if size > 0 => size,
+            _ => panic!(
+                "{} must be a positive number of bytes, got {:?}",
+                MAX_ACTIVE_FILE_SIZE_VAR, value
+            ),
+        },
+        Err(env::VarError::NotPresent) => DEFAULT_MAX_ACTIVE_FILE_SIZE,
+        Err(e) => panic!("{} can't be read: {}", MAX_ACTIVE_FILE_SIZE_VAR, e),
+    };
+
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR for build scripts");
+    fs::write(
+        Path::new(&out_dir).join("max_active_file_size.rs"),
+        max_active_file_size.to_string(),
+    )
+    .expect("build script can write to OUT_DIR");
+}
@@ -1,7 +1,23 @@
+use std::process::ExitCode;
+
 use bitask::Bitask;
 use clap::Parser;
 
-fn main() -> anyhow::Result<()> {
+/// Exit code when another process holds the database lock (`EX_TEMPFAIL`), so
+/// scripts can tell it apart from other failures and retry
+const EXIT_LOCKED: u8 = 75;
+
+fn main() -> ExitCode {
     let cli = Bitask::parse();
-    cli.exec()
+    match cli.exec() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if matches!(e.downcast_ref(), Some(bitask::db::Error::WriterLock)) => {
+            eprintln!("Error: database is locked by another process");
+            ExitCode::from(EXIT_LOCKED)
+        }
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -17,26 +17,32 @@ pub struct Bitask {
     #[clap(short, long, global = true)]
     pub verbose: bool,
 
+    /// Directory of the store, overriding the BITASK_PATH environment variable
+    #[clap(long, global = true)]
+    pub path: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub command: Command,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
-    /// Get a value from the store
+    /// Get values from the store, one per line
     ///
-    /// Returns an error if the key doesn't exist
+    /// Keys that don't exist are reported on stderr and the others are still printed,
+    /// but the command fails
+    #[clap(visible_alias = "get")]
     Ask {
-        /// The key to look up
-        #[clap(long)]
-        key: String,
+        /// The key to look up, repeat to look up several keys
+        #[clap(long = "key", required = true, value_parser = parse_key)]
+        keys: Vec<String>,
     },
     /// Put a value into the store
     ///
     /// Creates a new entry or updates an existing one
     Put {
         /// The key to store
-        #[clap(long)]
+        #[clap(long, value_parser = parse_key)]
         key: String,
 
         /// The value to store
@@ -48,13 +54,73 @@ pub enum Command {
     /// Returns success even if the key doesn't exist
     Remove {
         /// The key to remove
-        #[clap(long)]
+        #[clap(long, value_parser = parse_key)]
         key: String,
     },
     /// Compact the database
     ///
     /// Merges multiple log files into one and removes deleted entries
-    Compact,
+    Compact {
+        /// Print the files that would be merged and the bytes reclaimed, without
+        /// compacting
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Rebuild the store into a new directory, salvaging every intact record
+    ///
+    /// Reads the data files record by record without opening the store, so it works
+    /// when the store can't be opened anymore
+    Repair {
+        /// Directory to write the rebuilt store to
+        #[clap(long)]
+        dest: PathBuf,
+    },
+    /// List keys in order, one per line
+    ///
+    /// Pass the last key printed as --start to get the next page
+    List {
+        /// List keys after this one
+        #[clap(long)]
+        start: Option<String>,
+
+        /// Maximum number of keys to list
+        #[clap(long, default_value_t = 100)]
+        count: usize,
+
+        /// List keys in descending order
+        #[clap(long)]
+        reverse: bool,
+    },
+}
+
+/// A key given on the command line that the database would reject.
+///
+/// Keys are checked while parsing the arguments, so a bad key fails before the
+/// database is opened and its lock taken.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum InvalidKey {
+    /// The key is empty
+    #[error("empty key not allowed")]
+    Empty,
+    /// The key is longer than [`db::MAX_KEY_SIZE`]
+    #[error("key is {size} bytes, the maximum is {max}")]
+    TooLarge { size: usize, max: usize },
+}
+
+/// Parses a key argument, rejecting keys the database would reject.
+///
+/// Arguments that aren't valid UTF-8 are already rejected by clap.
+fn parse_key(key: &str) -> Result<String, InvalidKey> {
+    if key.is_empty() {
+        return Err(InvalidKey::Empty);
+    }
+    if key.len() > db::MAX_KEY_SIZE {
+        return Err(InvalidKey::TooLarge {
+            size: key.len(),
+            max: db::MAX_KEY_SIZE,
+        });
+    }
+    Ok(key.to_string())
 }
 
 impl Bitask {
@@ -66,26 +132,87 @@ impl Bitask {
         }
         env_logger::init();
 
-        let db_path = env::var("BITASK_PATH")
-            .map(PathBuf::from)
-            .map_err(|_| anyhow::anyhow!("BITASK_PATH environment variable is required"))?;
+        let db_path = self
+            .path
+            .clone()
+            .or_else(|| env::var_os("BITASK_PATH").map(PathBuf::from))
+            .ok_or_else(|| {
+                anyhow::anyhow!("The store directory is required, pass --path or set BITASK_PATH")
+            })?;
+
+        let mut options = db::Options::default();
+        if let Ok(max_file_size) = env::var("BITASK_MAX_FILE_SIZE") {
+            options.max_file_size = max_file_size.parse().map_err(|e| {
+                anyhow::anyhow!(
+                    "BITASK_MAX_FILE_SIZE must be a size in bytes, got '{}': {}",
+                    max_file_size,
+                    e
+                )
+            })?;
+        }
 
-        let mut db = db::Bitask::open(&db_path)?;
+        // Repairing must not open the store, opening is what may be failing
+        if let Command::Repair { dest } = &self.command {
+            let report = db::Bitask::repair(&db_path, dest)?;
+            println!(
+                "{} records salvaged from {} files, {} keys recovered, {} bytes skipped in {} damaged regions",
+                report.records_salvaged,
+                report.files_scanned,
+                report.keys_recovered,
+                report.bytes_skipped,
+                report.corrupt_regions
+            );
+            return Ok(());
+        }
+
+        // As an `Error`, so a locked database gets its own exit code
+        let mut db = db::Bitask::open_with_options(&db_path, options).map_err(db::Error::from)?;
 
         match self.command {
-            Command::Ask { key } => {
-                let value = db.ask(key.as_bytes())?;
-                println!("{}", String::from_utf8_lossy(&value));
+            Command::Ask { keys } => {
+                let mut missing = 0;
+                for key in &keys {
+                    match db.ask(key.as_bytes()) {
+                        Ok(value) => println!("{}", String::from_utf8_lossy(&value)),
+                        Err(db::Error::KeyNotFound) => {
+                            eprintln!("Key not found: {}", key);
+                            missing += 1;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                if missing > 0 {
+                    anyhow::bail!("{} of {} keys not found", missing, keys.len());
+                }
             }
             Command::Put { key, value } => {
                 db.put(key.as_bytes().to_vec(), value.as_bytes().to_vec())?;
             }
-            Command::Compact => {
+            Command::Compact { dry_run: true } => {
+                let plan = db.compact_dry_run()?;
+                println!(
+                    "{} files to merge, {} bytes to write, {} bytes reclaimable",
+                    plan.file_ids.len(),
+                    plan.estimated_output_bytes,
+                    plan.reclaimable_bytes
+                );
+            }
+            Command::Compact { dry_run: false } => {
                 db.compact()?;
             }
             Command::Remove { key } => {
                 db.remove(key.as_bytes().to_vec())?;
             }
+            Command::Repair { .. } => unreachable!("repair runs without opening the store"),
+            Command::List {
+                start,
+                count,
+                reverse,
+            } => {
+                for key in db.list_keys(start.as_deref().map(str::as_bytes), count, reverse)? {
+                    println!("{}", String::from_utf8_lossy(&key));
+                }
+            }
         }
 
         Ok(())
@@ -8,7 +8,7 @@
 //! - Single-writer, multiple-reader architecture
 //! - Process-safe file locking
 //! - Automatic log file rotation
-//! - CRC32 checksums for data integrity
+//! - Checksums for data integrity (CRC32 by default, CRC32C or xxHash32 selectable)
 //! - Efficient in-memory key directory
 //! - Crash recovery through log replay
 //!
@@ -22,24 +22,88 @@
 //!
 //! # Durability Guarantees
 //!
-//! - Atomic single-key operations
+//! - Atomic single-key operations, and multi-key batches with `Bitask::write_batch`
 //! - Crash recovery through log replay
-//! - Data integrity verification via CRC32
+//! - Data integrity verification via checksums on every read
 //!
 //! # Limitations
 //!
 //! - All keys must fit in memory
 //! - Single writer at a time
-//! - No multi-key transactions
+//! - No multi-key transactions beyond atomic write batches
 
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs::{self, File, OpenOptions},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
     io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::{Bound, RangeBounds},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use fs2::FileExt;
+#[cfg(feature = "async")]
+mod async_bitask;
+mod batch;
+mod changes;
+mod checksum;
+mod clock;
+mod compaction;
+mod compression;
+pub mod consts;
+mod entry;
+mod eviction;
+#[cfg(test)]
+mod fault;
+mod hint;
+mod key_hash;
+mod layout;
+mod manifest;
+mod metrics;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod options;
+mod read_only;
+mod record;
+mod repair;
+mod shared;
+mod snapshot;
+mod storage;
+
+#[cfg(feature = "async")]
+pub use async_bitask::AsyncBitask;
+pub use batch::Op;
+pub use changes::{EventLog, LogEvent, RecordsSince};
+pub use checksum::ChecksumKind;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use compaction::Compaction;
+pub use compression::CompressionKind;
+pub use consts::{MAX_ACTIVE_FILE_SIZE, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use metrics::Metrics;
+#[cfg(feature = "mmap")]
+pub use mmap::MmapGuard;
+pub use options::{BitaskBuilder, Durability, EvictionPolicy, Options, SizeLimitPolicy};
+pub use read_only::{OpenedBitask, ReadOnlyBitask};
+pub use record::RecordHeader;
+pub use repair::RepairReport;
+pub use shared::SharedBitask;
+pub use snapshot::{Snapshot, SnapshotIter};
+pub use storage::{FsStorage, MemStorage, Segment, Storage, StorageLock};
+
+use batch::{Marker, PendingBatch};
+use eviction::AccessOrder;
+use layout::{DataFile, Layout};
+use manifest::Manifest;
+use metrics::Counters;
+use snapshot::FilePins;
+
+/// Buffered reader over a data file
+type LogReader = BufReader<Box<dyn Segment>>;
 
 /// Errors that can occur during database operations.
 #[derive(Debug, thiserror::Error)]
@@ -95,13 +159,249 @@ pub enum Error {
     /// Invalid data deserialization encountered
     #[error("Invalid data deserialization: {0}")]
     InvalidDataDeserialize(#[from] std::array::TryFromSliceError),
+
+    /// Builder was asked to open a database without a path
+    #[error("Database path not set")]
+    MissingPath,
+
+    /// Key is longer than the record format can represent
+    #[error("Key size {size} exceeds the maximum of {max} bytes")]
+    KeyTooLarge { size: usize, max: usize },
+
+    /// Value is longer than the record format can represent
+    #[error("Value size {size} exceeds the maximum of {max} bytes")]
+    ValueTooLarge { size: usize, max: usize },
+
+    /// Stored checksum doesn't match the record read from disk
+    #[error("Checksum mismatch for record at {position} in file {file_id}")]
+    ChecksumMismatch { file_id: u64, position: u64 },
+
+    /// Data file ends before the value the key directory points at
+    #[error(
+        "Value for key '{}' in file {file_id} is truncated: expected {expected} bytes, {available} available",
+        String::from_utf8_lossy(.key)
+    )]
+    TruncatedValue {
+        key: Vec<u8>,
+        file_id: u64,
+        expected: u32,
+        available: u64,
+    },
+
+    /// No record starts at the requested offset
+    #[error("No record at offset {offset} in file {file_id}")]
+    NoRecordAt { file_id: u64, offset: u64 },
+
+    /// Database name can't be used to prefix file names
+    #[error("Invalid database name '{name}'")]
+    InvalidName { name: String },
+
+    /// The database path, or one of its parents, is a file rather than a directory
+    #[error("{} is not a directory", path.display())]
+    NotADirectory { path: PathBuf },
+
+    /// Record header uses a checksum algorithm this version doesn't know
+    #[error("Unknown checksum algorithm in record flags {flags:#04x}")]
+    UnknownChecksum { flags: u8 },
+
+    /// Record uses a compression codec this build doesn't support, either unknown or
+    /// behind a disabled cargo feature
+    #[error("Unsupported compression in record flags {flags:#04x}")]
+    UnsupportedCompression { flags: u8 },
+
+    /// Record on disk doesn't match the key directory entry pointing at it
+    #[error("Record at {position} in file {file_id} doesn't match the key directory")]
+    RecordMismatch { file_id: u64, position: u64 },
+
+    /// The thread running operations for an asynchronous handle has stopped
+    #[error("Database worker thread stopped")]
+    WorkerStopped,
+
+    /// A write would grow the data files past [`Options::max_total_size`]
+    #[error("Writing would grow the database to {size} bytes, over the limit of {limit}")]
+    StorageFull { size: u64, limit: u64 },
+
+    /// The operation needs the keys in order, which the key directory doesn't hold
+    /// with [`Options::hash_keys`]
+    #[error("Keys are hashed, their order isn't known")]
+    KeysHashed,
+
+    /// The operation only applies to sealed files, not the active one
+    #[error("File {file_id} is the active file")]
+    ActiveFile { file_id: u64 },
+
+    /// A data file holds damaged data where a record was expected: compaction found a
+    /// record not matching the key directory entry pointing at it, or data follows a
+    /// zeroed header in the active file
+    #[error("Corrupted entry at {position} in file {file_id}")]
+    CorruptedEntry { file_id: u64, position: u64 },
+
+    /// A compaction begun with [`Bitask::begin_compaction`] must be finished or
+    /// dropped first
+    #[error("A compaction is pending")]
+    CompactionPending,
+}
+
+/// Broad category of an [`Error`], returned by [`Error::kind`].
+///
+/// Lets callers branch on the kind of failure without matching every variant, which
+/// may grow between versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A key, file or record doesn't exist
+    NotFound,
+    /// Another writer holds the database
+    Locked,
+    /// Data on disk is damaged or doesn't match the key directory
+    Corrupt,
+    /// Data on disk uses a format this build can't read
+    Unsupported,
+    /// An argument was rejected before anything was done
+    InvalidInput,
+    /// An IO operation failed
+    Io,
+    /// The clock couldn't be read
+    Clock,
+    /// The handle can no longer run operations
+    Closed,
+    /// The database reached its size limit
+    Full,
+    /// Another operation must complete first
+    Busy,
+}
+
+impl Error {
+    /// Returns the broad category of the error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::ErrorKind;
+    ///
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// match db.ask(b"my_key") {
+    ///     Ok(value) => println!("{:?}", value),
+    ///     Err(e) if e.kind() == ErrorKind::NotFound => println!("missing"),
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::KeyNotFound
+            | Error::FileNotFound(_)
+            | Error::ActiveFileNotFound
+            | Error::NoRecordAt { .. } => ErrorKind::NotFound,
+            Error::WriterLock => ErrorKind::Locked,
+            Error::InvalidLogFileName { .. }
+            | Error::TimestampParse { .. }
+            | Error::InvalidDataDeserialize(_)
+            | Error::ChecksumMismatch { .. }
+            | Error::TruncatedValue { .. }
+            | Error::RecordMismatch { .. }
+            | Error::CorruptedEntry { .. } => ErrorKind::Corrupt,
+            Error::UnknownChecksum { .. } | Error::UnsupportedCompression { .. } => {
+                ErrorKind::Unsupported
+            }
+            Error::InvalidEmptyValue
+            | Error::InvalidEmptyKey
+            | Error::MissingPath
+            | Error::KeyTooLarge { .. }
+            | Error::ValueTooLarge { .. }
+            | Error::InvalidName { .. }
+            | Error::NotADirectory { .. }
+            | Error::KeysHashed
+            | Error::ActiveFile { .. } => ErrorKind::InvalidInput,
+            Error::Io(_) => ErrorKind::Io,
+            Error::TimestampError(_) | Error::TimestampOverflow(_) => ErrorKind::Clock,
+            Error::WorkerStopped => ErrorKind::Closed,
+            Error::StorageFull { .. } => ErrorKind::Full,
+            Error::CompactionPending => ErrorKind::Busy,
+        }
+    }
+}
+
+/// Errors that can occur while opening a database, returned by [`Bitask::open`] and
+/// every other way of opening a [`Bitask`], including [`BitaskBuilder::open`].
+///
+/// Keeps the failures worth handling at open time apart from those of later
+/// operations. Converts into an [`Error`] with `?`, giving back the variant the
+/// failure would have as an [`Error`].
+#[derive(Debug, thiserror::Error)]
+pub enum OpenError {
+    /// Another writer holds the database lock
+    #[error("Only one writer allowed at a time")]
+    Locked,
+
+    /// The database path, or one of its parents, is a file rather than a directory
+    #[error("{} is not a directory", path.display())]
+    NotADirectory { path: PathBuf },
+
+    /// The directory holds database files but neither an active nor a sealed file
+    #[error("Active file not found in non empty path")]
+    ActiveFileNotFound,
+
+    /// Data files or the manifest are damaged, malformed or in an unsupported format
+    #[error("Corrupt database: {0}")]
+    Corrupt(#[source] Error),
+
+    /// Underlying IO operation failed
+    #[error("IO error: {0}")]
+    Io(#[source] std::io::Error),
+
+    /// Any other failure, such as the clock not being readable
+    #[error(transparent)]
+    Other(Error),
+}
+
+impl OpenError {
+    /// Returns the broad category of the error, as [`Error::kind`] does.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OpenError::Locked => ErrorKind::Locked,
+            OpenError::NotADirectory { .. } => ErrorKind::InvalidInput,
+            OpenError::ActiveFileNotFound => ErrorKind::NotFound,
+            OpenError::Io(_) => ErrorKind::Io,
+            OpenError::Corrupt(e) | OpenError::Other(e) => e.kind(),
+        }
+    }
+
+    /// Sorts an [`Error`] raised while opening into the matching variant.
+    fn from_error(error: Error) -> Self {
+        match error {
+            Error::WriterLock => OpenError::Locked,
+            Error::NotADirectory { path } => OpenError::NotADirectory { path },
+            Error::ActiveFileNotFound => OpenError::ActiveFileNotFound,
+            Error::Io(e) => OpenError::Io(e),
+            error => match error.kind() {
+                ErrorKind::Corrupt | ErrorKind::Unsupported | ErrorKind::NotFound => {
+                    OpenError::Corrupt(error)
+                }
+                _ => OpenError::Other(error),
+            },
+        }
+    }
 }
 
-/// The name of the file lock. Used to ensure only one writer at a time and process safety.
-const FILE_LOCK_PATH: &str = "db.lock";
+impl From<OpenError> for Error {
+    fn from(error: OpenError) -> Self {
+        match error {
+            OpenError::Locked => Error::WriterLock,
+            OpenError::NotADirectory { path } => Error::NotADirectory { path },
+            OpenError::ActiveFileNotFound => Error::ActiveFileNotFound,
+            OpenError::Io(e) => Error::Io(e),
+            OpenError::Corrupt(e) | OpenError::Other(e) => e,
+        }
+    }
+}
+
+/// Number of entries copied between two calls of a compaction progress callback
+const COMPACTION_PROGRESS_INTERVAL: usize = 1024;
 
-/// Maximum size of active log file before rotation (4MB)
-pub const MAX_ACTIVE_FILE_SIZE: u64 = 4 * 1024 * 1024;
+/// Largest capacity of the buffer records are serialized into that's kept between
+/// writes, so one large value doesn't hold on to its memory.
+const MAX_SCRATCH_CAPACITY: usize = 64 * 1024;
 
 /// A Bitcask-style key-value store implementation.
 ///
@@ -113,13 +413,13 @@ pub const MAX_ACTIVE_FILE_SIZE: u64 = 4 * 1024 * 1024;
 /// - Process-safe file locking
 /// - Append-only log structure
 /// - In-memory key directory
-/// - Automatic log rotation at 4MB
+/// - Automatic log rotation at 4MB, configurable through [`Options`]
 ///
 /// # Thread Safety
 ///
 /// The database ensures process-level safety through file locking, but is not
 /// thread-safe internally. Concurrent access from multiple threads requires
-/// appropriate synchronization mechanisms at the application level.
+/// appropriate synchronization, such as the [`SharedBitask`] wrapper.
 ///
 /// # Examples
 ///
@@ -155,22 +455,158 @@ pub const MAX_ACTIVE_FILE_SIZE: u64 = 4 * 1024 * 1024;
 /// 3. All existing data remains accessible
 #[derive(Debug)]
 pub struct Bitask {
-    /// Base directory path where all database files are stored
-    path: PathBuf,
-    /// File lock handle to ensure single-writer access
-    _file_lock: File,
+    /// Paths of the database files inside their directory
+    layout: Layout,
+    /// Lock handle to ensure single-writer access
+    _file_lock: StorageLock,
     /// Timestamp identifier of the current active file
     writer_id: u64,
     /// Buffered writer for the active log file
-    writer: BufWriter<File>,
+    writer: BufWriter<Box<dyn Segment>>,
+    /// Offset right after the last record in the active file, counting records still
+    /// buffered in `writer`. Record positions are always computed from it, never by
+    /// seeking the buffered writer.
+    write_position: u64,
+    /// Time of the last sync of the active file, in milliseconds since UNIX epoch
+    last_sync: u64,
+    /// IDs of the sealed files, as recorded in the manifest
+    sealed_files: BTreeSet<u64>,
+    /// Total size of the sealed files, kept up to date as they change so checking
+    /// [`Options::max_total_size`] doesn't read their sizes on every write
+    sealed_size: u64,
     /// Map of file IDs to their respective buffered readers
-    readers: HashMap<u64, BufReader<File>>,
+    readers: HashMap<u64, LogReader>,
     /// In-memory index mapping keys to their latest value locations
     keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+    /// Options the database was opened with
+    options: Options,
+    /// Files referenced by live snapshots, which compaction must not delete
+    pins: FilePins,
+    /// Set while a [`Compaction`] begun with [`Bitask::begin_compaction`] is pending
+    compacting: Arc<AtomicBool>,
+    /// Buffer set commands are serialized into, reused across writes
+    scratch: Vec<u8>,
+    /// First failure of [`Bitask::extend`], returned by the next [`Bitask::sync`] or
+    /// [`Bitask::close`]
+    extend_error: Option<Error>,
+    /// Set once [`Bitask::close`] did the cleanup `Drop` would do
+    closed: bool,
+    /// Counters of the operations performed since opening
+    metrics: Arc<Counters>,
+    /// Order in which keys were accessed, for [`EvictionPolicy::LeastRecentlyUsed`]
+    access: AccessOrder,
+    /// Memory mappings of sealed files, created on first use by [`Bitask::ask_mmap`]
+    #[cfg(feature = "mmap")]
+    mmaps: std::sync::Mutex<HashMap<u64, std::sync::Arc<memmap2::Mmap>>>,
+    /// Fault injected into writes to the active file
+    #[cfg(test)]
+    fault: Option<fault::FailAfter>,
+}
+
+/// A record as stored on disk, returned by [`Bitask::read_record_at`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawRecord {
+    /// Checksum stored in the header
+    pub crc: u32,
+    /// Flags stored in the header, including the checksum algorithm
+    pub flags: u8,
+    /// Write time in milliseconds since UNIX epoch
+    pub timestamp: u64,
+    /// The record's key
+    pub key: Vec<u8>,
+    /// The record's value as stored, so still compressed if the flags say so, or
+    /// `None` for a tombstone
+    pub value: Option<Vec<u8>>,
+    /// Whether the stored checksum matches the key and value
+    pub checksum_valid: bool,
+    /// Offset of the record following this one in the same file
+    pub next_offset: u64,
+}
+
+/// Size and live data of a sealed file, returned by [`Bitask::segment_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStat {
+    /// Identifier (timestamp) of the file
+    pub file_id: u64,
+    /// Size of the file in bytes
+    pub total_bytes: u64,
+    /// Bytes of the records the key directory still points at
+    pub live_bytes: u64,
+    /// Number of keys whose current value is in the file
+    pub live_entries: usize,
+}
+
+/// Records and live data of one data file, returned by [`Bitask::file_summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSummary {
+    /// Identifier (timestamp) of the file
+    pub file_id: u64,
+    /// Number of records in the file, sets and removals
+    pub total_records: usize,
+    /// Number of records the key directory still points at
+    pub live_records: usize,
+    /// Bytes of all the records in the file
+    pub total_bytes: u64,
+    /// Bytes of the live records
+    pub live_bytes: u64,
+}
+
+/// Outcome of a compaction, returned by [`Bitask::compact_file`] and [`Bitask::merge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionStats {
+    /// Number of sealed files merged
+    pub files_compacted: usize,
+    /// Number of live entries copied into the new file
+    pub entries_written: usize,
+    /// Bytes written to the new file
+    pub bytes_written: u64,
+    /// Size of the merged files minus the bytes written
+    pub bytes_reclaimed: u64,
+}
+
+/// How far a compaction got, passed to the callback of
+/// [`Bitask::compact_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionProgress {
+    /// Number of live entries copied so far
+    pub entries_processed: usize,
+    /// Bytes written to the new file so far
+    pub bytes_written: u64,
+    /// Number of merged files still holding live entries not copied yet
+    pub files_remaining: usize,
+}
+
+/// What [`Bitask::compact`] would do, returned by [`Bitask::compact_dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CompactionPlan {
+    /// Sealed files that would be merged, empty if compaction would be skipped
+    pub file_ids: Vec<u64>,
+    /// Size of the merged file, the live bytes of the files merged
+    pub estimated_output_bytes: u64,
+    /// Bytes compaction would free, the dead bytes of the files merged
+    pub reclaimable_bytes: u64,
+}
+
+/// A version of a key returned by [`Bitask::history`]: the timestamp of the record
+/// and its value, `None` for a removal.
+pub type KeyVersion = (u64, Option<Vec<u8>>);
+
+/// A key with its value, as returned by [`Bitask::oldest`] and [`Bitask::newest`].
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// Where a record was written, returned by [`Bitask::put_located`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteLocation {
+    /// Identifier (timestamp) of the data file holding the record
+    pub file_id: u64,
+    /// Offset of the record header within the file
+    pub offset: u64,
+    /// Timestamp stored in the record (milliseconds since UNIX epoch)
+    pub timestamp: u64,
 }
 
 /// Entry in the key directory mapping a key to its location on disk
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct KeyDirEntry {
     /// File ID (timestamp) containing the value
     file_id: u64,
@@ -180,6 +616,9 @@ struct KeyDirEntry {
     value_position: u64,
     /// Timestamp when the entry was written
     timestamp: u64,
+    /// Whether the record is a tombstone, only kept in the key directory while
+    /// replaying files
+    tombstone: bool,
 }
 
 impl Bitask {
@@ -198,55 +637,302 @@ impl Bitask {
     ///
     /// # Errors
     ///
-    /// Returns an [`Error`] if:
-    /// * Another process has write access ([`Error::WriterLock`])
-    /// * Filesystem operations fail ([`Error::Io`])
-    /// * No active file is found when opening existing DB ([`Error::ActiveFileNotFound`])
+    /// Returns an [`OpenError`] if:
+    /// * Another process has write access ([`OpenError::Locked`]), still holding it
+    ///   after [`Options::lock_timeout`] if one is set
+    /// * `path` or one of its parents is a file ([`OpenError::NotADirectory`])
+    /// * Filesystem operations fail ([`OpenError::Io`])
+    /// * An existing DB has neither an active nor a sealed file ([`OpenError::ActiveFileNotFound`])
+    /// * Data files or the manifest contain invalid or corrupted data ([`OpenError::Corrupt`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::{Bitask, OpenError};
+    ///
+    /// match Bitask::open("my_db") {
+    ///     Ok(mut db) => db.put(b"key".to_vec(), b"value".to_vec())?,
+    ///     Err(OpenError::Locked) => println!("my_db is in use"),
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, OpenError> {
+        Self::open_with_options(path, Options::default())
+    }
+
+    /// Opens a Bitcask database at the specified path with the given [`Options`].
+    ///
+    /// Behaves like [`Bitask::open`] but allows tuning rotation size, durability
+    /// and automatic compaction.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Path where the database files will be stored
+    /// * `options` - Configuration applied for the lifetime of the instance
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`Bitask`] instance if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::{Bitask, Durability, Options};
+    ///
+    /// let options = Options {
+    ///     durability: Durability::Sync,
+    ///     ..Options::default()
+    /// };
+    /// let mut db = Bitask::open_with_options("my_db", options)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open_with_options(path: impl AsRef<Path>, options: Options) -> Result<Self, OpenError> {
+        Self::open_layout(Layout::new(path), options).map_err(OpenError::from_error)
+    }
+
+    /// Opens the database at `path` for writing if possible, and for reading only if
+    /// another writer holds the lock.
+    ///
+    /// For tools that prefer writing but can do with reading. Only
+    /// [`OpenError::Locked`] falls back to a [`ReadOnlyBitask`], which takes no lock
+    /// and sees the other writer's records as of opening, see
+    /// [`ReadOnlyBitask::refresh`].
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Path where the database files are stored
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle obtained as an [`OpenedBitask`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`] other than [`OpenError::Locked`],
+    /// and those of [`ReadOnlyBitask::open`] sorted into an [`OpenError`].
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// let mut db = bitask::db::Bitask::open("my_db")?;
+    /// use bitask::db::{Bitask, OpenedBitask};
+    ///
+    /// match Bitask::open_rw_or_ro("my_db")? {
+    ///     OpenedBitask::ReadWrite(mut db) => db.put(b"key".to_vec(), b"value".to_vec())?,
+    ///     OpenedBitask::ReadOnly(mut db) => println!("{:?}", db.ask(b"key")?),
+    /// }
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
-        fs::create_dir_all(&path)?;
-        let lock_path = path.as_ref().join(FILE_LOCK_PATH);
-
-        let lock_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .truncate(false)
-            .append(false)
-            .open(lock_path)?;
-
-        lock_file
-            .try_lock_exclusive()
-            .map_err(|_| Error::WriterLock)?;
-
-        let is_empty = match fs::read_dir(&path)?.next() {
-            None => true,
-            Some(Ok(entry)) if entry.file_name() == FILE_LOCK_PATH => {
-                // If first entry is db.lock, check if there's a second entry
-                fs::read_dir(&path)?.nth(1).is_none()
+    pub fn open_rw_or_ro(path: impl AsRef<Path>) -> Result<OpenedBitask, OpenError> {
+        match Self::open(path.as_ref()) {
+            Ok(db) => Ok(OpenedBitask::ReadWrite(Box::new(db))),
+            Err(OpenError::Locked) => {
+                log::debug!(
+                    "{} is locked by another writer, opening it read-only",
+                    path.as_ref().display()
+                );
+                let db = ReadOnlyBitask::open(path, Options::default())
+                    .map_err(OpenError::from_error)?;
+                Ok(OpenedBitask::ReadOnly(Box::new(db)))
             }
-            Some(_) => false,
-        };
+            Err(e) => Err(e),
+        }
+    }
 
-        if is_empty {
-            Self::open_new(path, lock_file)
-        } else {
-            Self::open_existing(path, lock_file)
+    /// Opens a database using a lock file opened, and possibly locked, by the caller.
+    ///
+    /// For sandboxed or privilege-separated setups where the process using the
+    /// database can't open the lock file itself. `lock` must be the database's lock
+    /// file, `<path>/db.lock`, so other writers opening the database normally still
+    /// see the lock. It is locked exclusively if it doesn't hold the lock already,
+    /// kept until the database is dropped, and the lock file is removed then, as with
+    /// [`Bitask::open`]. [`Options::lock_timeout`] is ignored.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Path where the database files are stored
+    /// * `lock` - Handle to the lock file of the database
+    /// * `options` - Configuration applied for the lifetime of the instance
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`Bitask`] instance if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OpenError`] if:
+    /// * Another handle to the lock file holds the lock ([`OpenError::Locked`])
+    /// * Opening the database fails, see [`Bitask::open`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::{Bitask, Options};
+    ///
+    /// // Opened by a more privileged process and handed over
+    /// let lock = std::fs::OpenOptions::new()
+    ///     .create(true)
+    ///     .truncate(false)
+    ///     .write(true)
+    ///     .open("my_db/db.lock")?;
+    /// let mut db = Bitask::open_with_lock("my_db", lock, Options::default())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open_with_lock(
+        path: impl AsRef<Path>,
+        lock: File,
+        options: Options,
+    ) -> Result<Self, OpenError> {
+        // Locking again through the handle holding the lock succeeds
+        if fs2::FileExt::try_lock_exclusive(&lock).is_err() {
+            return Err(OpenError::Locked);
         }
+
+        let layout = Layout::new(path).with_data_subdir(options.data_subdir.as_deref());
+        create_dirs(&*options.storage, &layout)
+            .and_then(|()| Self::open_locked(layout, Box::new(lock), options))
+            .map_err(OpenError::from_error)
+    }
+
+    /// Opens a named database inside a directory that may be shared with others.
+    ///
+    /// Every file of the database is prefixed with `<name>.`, e.g. `<name>.db.lock`
+    /// and `<name>.<timestamp>.log`, so several named databases, and one unnamed
+    /// database opened with [`Bitask::open`], can live in the same directory without
+    /// seeing each other's keys or contending for the same lock.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Directory where the database files will be stored
+    /// * `name` - Name of the database within the directory
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`Bitask`] instance if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OpenError`] if:
+    /// * `name` is empty, numeric, or contains a dot or path separator
+    ///   ([`OpenError::Other`] holding [`Error::InvalidName`])
+    /// * Opening the database fails, see [`Bitask::open`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut users = bitask::db::Bitask::open_named("my_dir", "users")?;
+    /// let mut orders = bitask::db::Bitask::open_named("my_dir", "orders")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open_named(path: impl AsRef<Path>, name: &str) -> Result<Self, OpenError> {
+        Layout::named(path, name)
+            .and_then(|layout| Self::open_layout(layout, Options::default()))
+            .map_err(OpenError::from_error)
+    }
+
+    /// Opens the database laid out as `layout` with the given [`Options`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`].
+    fn open_layout(layout: Layout, options: Options) -> Result<Self, Error> {
+        let layout = layout.with_data_subdir(options.data_subdir.as_deref());
+        let storage = &*options.storage;
+        create_dirs(storage, &layout)?;
+        let lock_file = lock_database(storage, &layout, options.lock_timeout)?;
+        Self::open_locked(layout, lock_file, options)
+    }
+
+    /// Opens the database laid out as `layout`, whose lock `lock_file` already holds.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`].
+    fn open_locked(
+        layout: Layout,
+        lock_file: StorageLock,
+        options: Options,
+    ) -> Result<Self, Error> {
+        let storage = &*options.storage;
+        let is_empty =
+            !storage.exists(&layout.manifest_path()) && !Self::has_data_files(storage, &layout)?;
+
+        let db = if is_empty {
+            Self::open_new(layout, lock_file, options)?
+        } else {
+            Self::open_existing(layout, lock_file, options)?
+        };
+
+        log::info!(
+            "Opened database at {} with {} keys, active file {}",
+            db.layout.dir().display(),
+            db.keydir.len(),
+            db.writer_id
+        );
+        Ok(db)
+    }
+
+    /// Returns a [`BitaskBuilder`] for configuring and opening a database fluently.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut db = bitask::db::Bitask::builder()
+    ///     .path("my_db")
+    ///     .max_file_size(1024 * 1024)
+    ///     .open()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn builder() -> BitaskBuilder {
+        BitaskBuilder::default()
+    }
+
+    /// Returns `true` if a database exists at `path`, without taking its lock.
+    ///
+    /// Looks for an active or sealed data file of an unnamed database kept directly
+    /// in `path` on the filesystem, so a caller can tell creating a database from
+    /// opening one. Nothing is created or changed, and a missing or unreadable
+    /// directory holds no database.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Directory to look for a database in
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::Bitask;
+    ///
+    /// if !Bitask::exists("my_db") {
+    ///     println!("Creating a new database");
+    /// }
+    /// let mut db = Bitask::open("my_db")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn exists(path: impl AsRef<Path>) -> bool {
+        let layout = Layout::new(path);
+        let Ok(names) = FsStorage.list(layout.data_dir()) else {
+            return false;
+        };
+        names.iter().any(|name| {
+            matches!(
+                layout.parse(name),
+                Ok(Some(DataFile::Active(_) | DataFile::Sealed(_)))
+            )
+        })
     }
 
     /// Creates a new database at the specified path.
     ///
     /// # Parameters
     ///
-    /// * `path` - Path where the database files will be stored
+    /// * `layout` - Paths of the database files
     /// * `lock_file` - The exclusive lock file for this database
+    /// * `options` - Configuration for this instance
     ///
     /// # Returns
     ///
@@ -257,35 +943,44 @@ impl Bitask {
     /// Returns an [`Error`] if:
     /// * Filesystem operations fail ([`Error::Io`])
     /// * System time operations fail ([`Error::TimestampError`])
-    fn open_new(path: impl AsRef<Path>, lock_file: File) -> Result<Self, Error> {
-        let timestamp = timestamp_as_u64()?;
-
-        let writer_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .truncate(false)
-            .append(true)
-            .open(file_active_log_path(path.as_ref(), timestamp))?;
-
-        let reader_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .truncate(false)
-            .append(true)
-            .open(file_active_log_path(path.as_ref(), timestamp))?;
-
-        let writer = BufWriter::new(writer_file);
+    fn open_new(layout: Layout, lock_file: StorageLock, options: Options) -> Result<Self, Error> {
+        let timestamp = options.clock.now()?;
+
+        let writer = create_active_writer(&layout, timestamp, &options)?;
+
         let mut readers = HashMap::new();
-        let reader = BufReader::new(reader_file);
-        readers.insert(timestamp, reader);
+        let reader = options.storage.open(&layout.active_log_path(timestamp))?;
+        readers.insert(timestamp, value_reader(reader, &options));
+
+        let manifest = Manifest {
+            active: timestamp,
+            sealed: BTreeSet::new(),
+        };
+        manifest.write(&*options.storage, &layout)?;
 
         Ok(Self {
-            path: path.as_ref().to_path_buf(),
+            layout,
             _file_lock: lock_file,
             writer_id: timestamp,
             writer,
+            write_position: 0,
+            last_sync: timestamp,
+            sealed_files: manifest.sealed,
+            sealed_size: 0,
             readers,
             keydir: BTreeMap::new(),
+            pins: FilePins::new(options.storage.clone()),
+            compacting: Arc::default(),
+            scratch: Vec::new(),
+            extend_error: None,
+            closed: false,
+            access: AccessOrder::new(options.eviction, options.hash_keys),
+            options,
+            metrics: Arc::new(Counters::default()),
+            #[cfg(feature = "mmap")]
+            mmaps: Default::default(),
+            #[cfg(test)]
+            fault: None,
         })
     }
 
@@ -293,8 +988,9 @@ impl Bitask {
     ///
     /// # Parameters
     ///
-    /// * `path` - Path where the database files are stored
+    /// * `layout` - Paths of the database files
     /// * `lock_file` - The exclusive lock file for this database
+    /// * `options` - Configuration for this instance
     ///
     /// # Returns
     ///
@@ -306,612 +1002,3918 @@ impl Bitask {
     /// * Filesystem operations fail ([`Error::Io`])
     /// * Log file names are malformed ([`Error::InvalidLogFileName`])
     /// * Timestamps in filenames are invalid ([`Error::TimestampParse`])
-    /// * No active log file exists ([`Error::ActiveFileNotFound`])
-    fn open_existing(path: impl AsRef<Path>, lock_file: File) -> Result<Self, Error> {
-        let mut active_timestamp = None;
-        let mut active_file = None;
-        let mut files: BTreeMap<u64, PathBuf> = BTreeMap::new();
-
-        for entry in fs::read_dir(&path)? {
-            let entry = entry?;
-            let name = entry.file_name().to_string_lossy().to_string();
-            if name == FILE_LOCK_PATH {
-                continue;
+    /// * Neither an active nor a sealed log file exists ([`Error::ActiveFileNotFound`])
+    /// * Data follows a zeroed header in the active file ([`Error::CorruptedEntry`])
+    fn open_existing(
+        layout: Layout,
+        lock_file: StorageLock,
+        options: Options,
+    ) -> Result<Self, Error> {
+        let storage = &*options.storage;
+        Self::remove_compaction_leftovers(storage, &layout)?;
+        // The manifest lists the data files, so large databases don't need to check the
+        // name of every file. The directory is scanned when it's missing or out of date.
+        let manifest = match Manifest::read(storage, &layout)? {
+            Some(manifest) if manifest.is_current(storage, &layout) => {
+                log::debug!(
+                    "Opening from manifest with {} sealed files",
+                    manifest.sealed.len()
+                );
+                manifest
             }
-
-            let timestamp = name
-                .split('.')
-                .next()
-                .ok_or_else(|| Error::InvalidLogFileName {
-                    filename: name.to_string(),
-                })?
-                .parse()
-                .map_err(|e| Error::TimestampParse {
-                    value: name.to_string(),
-                    source: e,
-                })?;
-
-            if name.ends_with(".active.log") {
-                active_file = Some(entry.path());
-                active_timestamp = Some(timestamp);
-            } else if name.ends_with(".log") {
-                files.insert(timestamp, entry.path());
+            _ => {
+                log::debug!("No usable manifest, scanning {}", layout.dir().display());
+                let manifest = Self::scan_files(storage, &layout)?;
+                manifest.write(storage, &layout)?;
+                manifest
             }
-        }
-
-        let active_timestamp = active_timestamp.ok_or(Error::ActiveFileNotFound)?;
-
-        let writer = {
-            let active_file = active_file.clone().ok_or(Error::ActiveFileNotFound)?;
-            let writer_file = OpenOptions::new()
-                .create(true)
-                .read(true)
-                .truncate(false)
-                .append(true)
-                .open(active_file)?;
-            BufWriter::new(writer_file)
-        };
-
-        let mut reader = {
-            let active_file = active_file.ok_or(Error::ActiveFileNotFound)?;
-            let reader_file = OpenOptions::new()
-                .create(true)
-                .read(true)
-                .truncate(false)
-                .append(true)
-                .open(active_file)?;
-            BufReader::new(reader_file)
         };
+        let active_timestamp = manifest.active;
+        let active_file = layout.active_log_path(active_timestamp);
 
-        let keydir = Self::rebuild_keydir(&mut reader, active_timestamp)?;
+        let mut writer = BufWriter::new(storage.create(&active_file)?);
+        let mut reader = BufReader::new(storage.open(&active_file)?);
 
+        // Replay sealed files before the active file. Tombstones are kept while
+        // replaying so an older value in another file can't resurrect a removed key,
+        // and dropped once every file has been seen.
+        let mut keydir = BTreeMap::new();
         let mut readers = HashMap::new();
-        readers.insert(active_timestamp, reader);
+        let mut sealed_size = 0;
+        for &file_id in &manifest.sealed {
+            let sealed_reader = Self::replay_sealed(storage, &layout, file_id, &mut keydir)?;
+            sealed_size += sealed_reader.get_ref().size()?;
+            readers.insert(file_id, value_reader(sealed_reader.into_inner(), &options));
+        }
+        let write_position = Self::rebuild_keydir(&mut reader, active_timestamp, &mut keydir)?;
+        keydir.retain(|_, entry| !entry.tombstone);
+        if options.hash_keys {
+            keydir = key_hash::hash_keydir(keydir)?;
+        }
+
+        // Give back the space reserved past the last record before reserving it again,
+        // and drop a record cut short by a crash so new records follow the last one
+        let file_len = reader.get_ref().size()?;
+        if write_position < file_len {
+            let reserved = Self::is_reserved_tail(&mut reader, active_timestamp, write_position)?;
+            if !reserved {
+                log::warn!(
+                    "Dropping {} bytes past {} in active file {}, replay stopped there",
+                    file_len - write_position,
+                    write_position,
+                    active_timestamp
+                );
+            }
+            if !reserved || options.preallocate {
+                writer.get_ref().set_len(write_position)?;
+            }
+        }
+        if options.preallocate && write_position < options.max_file_size {
+            writer.get_ref().set_len(options.max_file_size)?;
+        }
+        writer.seek(SeekFrom::Start(write_position))?;
+
+        readers.insert(
+            active_timestamp,
+            value_reader(reader.into_inner(), &options),
+        );
 
         Ok(Self {
-            path: path.as_ref().to_path_buf(),
+            layout,
             _file_lock: lock_file,
             writer_id: active_timestamp,
             writer,
+            write_position,
+            last_sync: options.clock.now()?,
+            sealed_files: manifest.sealed,
+            sealed_size,
             readers,
             keydir,
+            pins: FilePins::new(options.storage.clone()),
+            compacting: Arc::default(),
+            scratch: Vec::new(),
+            extend_error: None,
+            closed: false,
+            access: AccessOrder::new(options.eviction, options.hash_keys),
+            options,
+            metrics: Arc::new(Counters::default()),
+            #[cfg(feature = "mmap")]
+            mmaps: Default::default(),
+            #[cfg(test)]
+            fault: None,
         })
     }
 
-    /// Rebuilds the in-memory key directory from a log file.
+    /// Finds the data files of a database by scanning its directory.
     ///
-    /// Scans through the given log file and reconstructs the key directory by:
-    /// - Reading each command header
-    /// - Processing key-value entries
-    /// - Updating the keydir with the latest value positions
+    /// Leftovers of a compaction that crashed before completing are skipped. If the
+    /// active file is missing but sealed files exist, the returned manifest names a new
+    /// active file, which the caller creates.
     ///
-    /// # Arguments
+    /// # Parameters
     ///
-    /// * `reader` - Buffered reader for the log file
-    /// * `file_id` - Timestamp identifier of the log file
+    /// * `storage` - Storage holding the database files
+    /// * `layout` - Paths of the database files
     ///
     /// # Returns
     ///
-    /// Returns a [`BTreeMap`] containing the rebuilt key directory mapping keys to their latest positions
+    /// Returns a [`Manifest`] describing the files found.
     ///
     /// # Errors
     ///
     /// Returns an [`Error`] if:
-    /// * IO operations fail while reading the file ([`Error::Io`])
-    /// * Log file contains invalid or corrupted data
-    fn rebuild_keydir(
-        reader: &mut BufReader<File>,
-        file_id: u64,
-    ) -> Result<BTreeMap<Vec<u8>, KeyDirEntry>, Error> {
-        let mut keydir: BTreeMap<Vec<u8>, KeyDirEntry> = BTreeMap::new();
-        let mut position = 0u64;
+    /// * Filesystem operations fail ([`Error::Io`])
+    /// * Log file names are malformed ([`Error::InvalidLogFileName`])
+    /// * Timestamps in filenames are invalid ([`Error::TimestampParse`])
+    /// * Neither an active nor a sealed log file exists ([`Error::ActiveFileNotFound`])
+    fn scan_files(storage: &dyn Storage, layout: &Layout) -> Result<Manifest, Error> {
+        let mut active_timestamp = None;
+        let mut sealed = BTreeSet::new();
 
-        loop {
-            // Read just the header
-            let mut header_buf = vec![0u8; CommandHeader::SIZE];
-            match reader.read_exact(&mut header_buf) {
-                Ok(_) => (),
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
+        for name in storage.list(layout.data_dir())? {
+            match layout.parse(&name)? {
+                Some(DataFile::Active(timestamp)) => active_timestamp = Some(timestamp),
+                Some(DataFile::Sealed(timestamp)) => {
+                    sealed.insert(timestamp);
+                }
+                // Removed by `remove_compaction_leftovers` before the scan
+                Some(DataFile::Compacting(_) | DataFile::Retired(_)) | None => (),
+            }
+        }
+
+        // Without an active file the sealed files are still readable, so a new empty
+        // active file is started after the newest of them
+        let active_timestamp = match (active_timestamp, sealed.last()) {
+            (Some(timestamp), _) => timestamp,
+            (None, Some(&newest)) => {
+                log::warn!(
+                    "No active file found, starting {} after {} sealed files",
+                    newest + 1,
+                    sealed.len()
+                );
+                newest + 1
+            }
+            (None, None) => return Err(Error::ActiveFileNotFound),
+        };
+
+        Ok(Manifest {
+            active: active_timestamp,
+            sealed,
+        })
+    }
+
+    /// Removes the files of compactions that crashed before completing, and the
+    /// merged files a snapshot still held when the process stopped.
+    ///
+    /// The records of the former are still in the files they were merging, and the
+    /// latter were replaced by a merged file, so they're removed on every open, whether
+    /// the data files are found from the manifest or a scan. Replaying a merged file
+    /// again would bring back keys whose tombstones the merge dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the directory can't be listed or a file removed.
+    fn remove_compaction_leftovers(storage: &dyn Storage, layout: &Layout) -> Result<(), Error> {
+        for name in storage.list(layout.data_dir())? {
+            // Unparsable names are reported by the scan when opening the database
+            match layout.parse(&name) {
+                Ok(Some(DataFile::Compacting(_))) => {
+                    log::warn!("Removing incomplete compaction file {}", name);
+                }
+                Ok(Some(DataFile::Retired(_))) => {
+                    log::warn!("Removing merged file {} left by a snapshot", name);
+                }
+                _ => continue,
+            }
+            storage.remove(&layout.data_dir().join(&name))?;
+        }
+        Ok(())
+    }
+
+    /// Re-reads the data files in the directory while keeping the writer lock.
+    ///
+    /// Picks up sealed files created outside this handle, for instance by an
+    /// out-of-process compaction or a restore, without a close and open cycle. The key
+    /// directory is rebuilt from every sealed file found and the active file, so keys
+    /// in new files become visible, keys whose files disappeared are resolved again,
+    /// and records with newer timestamps still win. Buffered writes are flushed first,
+    /// so nothing written through this handle is lost.
+    ///
+    /// Files compaction already replaced but a live [`Snapshot`] still holds are not
+    /// picked up again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * Filesystem operations fail ([`Error::Io`])
+    /// * Timestamps in filenames are invalid ([`Error::TimestampParse`])
+    /// * Log files contain invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// // ... another process drops a sealed file into the directory ...
+    /// db.reopen()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn reopen(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        let mut sealed = BTreeSet::new();
+        let storage = &*self.options.storage;
+        for name in storage.list(self.layout.data_dir())? {
+            if let Some(DataFile::Sealed(file_id)) = self.layout.parse(&name)? {
+                if file_id != self.writer_id {
+                    sealed.insert(file_id);
+                }
+            }
+        }
+
+        let mut keydir = BTreeMap::new();
+        let mut readers = HashMap::new();
+        let mut sealed_size = 0;
+        for &file_id in &sealed {
+            let sealed_reader = Self::replay_sealed(storage, &self.layout, file_id, &mut keydir)?;
+            sealed_size += sealed_reader.get_ref().size()?;
+            readers.insert(
+                file_id,
+                value_reader(sealed_reader.into_inner(), &self.options),
+            );
+        }
+        let mut reader =
+            BufReader::new(storage.open(&self.layout.active_log_path(self.writer_id))?);
+        Self::rebuild_keydir(&mut reader, self.writer_id, &mut keydir)?;
+        keydir.retain(|_, entry| !entry.tombstone);
+        if self.options.hash_keys {
+            keydir = key_hash::hash_keydir(keydir)?;
+        }
+        readers.insert(
+            self.writer_id,
+            value_reader(reader.into_inner(), &self.options),
+        );
+
+        log::debug!(
+            "Reopened with {} sealed files ({} before), {} keys ({} before)",
+            sealed.len(),
+            self.sealed_files.len(),
+            keydir.len(),
+            self.keydir.len()
+        );
+        self.sealed_files = sealed;
+        self.sealed_size = sealed_size;
+        self.readers = readers;
+        self.keydir = keydir;
+        #[cfg(feature = "mmap")]
+        self.mmaps
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clear();
+        self.write_manifest()
+    }
+
+    /// Opens a sealed file and replays it into the key directory, from its hint file
+    /// when there is a valid one.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - Storage holding the database files
+    /// * `layout` - Paths of the database files
+    /// * `file_id` - Identifier (timestamp) of the sealed file
+    /// * `keydir` - Key directory built from the files replayed so far
+    ///
+    /// # Returns
+    ///
+    /// Returns a reader over the sealed file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail ([`Error::Io`])
+    /// * The file contains invalid or corrupted data
+    fn replay_sealed(
+        storage: &dyn Storage,
+        layout: &Layout,
+        file_id: u64,
+        keydir: &mut BTreeMap<Vec<u8>, KeyDirEntry>,
+    ) -> Result<LogReader, Error> {
+        let mut reader = BufReader::new(storage.open(&layout.log_path(file_id))?);
+        let data_len = reader.get_ref().size()?;
+        match hint::read(storage, layout, file_id, data_len)? {
+            Some(entries) => {
+                for (key, entry) in entries {
+                    index_entry(keydir, key, entry);
+                }
+            }
+            None => {
+                let end = Self::rebuild_keydir(&mut reader, file_id, keydir)?;
+                if end < data_len {
+                    log::warn!(
+                        "Ignoring {} bytes past {} in file {}, replay stopped there",
+                        data_len - end,
+                        end,
+                        file_id
+                    );
+                }
+            }
+        }
+        Ok(reader)
+    }
+
+    /// Returns `true` if the directory holds any data file of the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the directory can't be read.
+    fn has_data_files(storage: &dyn Storage, layout: &Layout) -> Result<bool, Error> {
+        for name in storage.list(layout.data_dir())? {
+            // Unparsable names are reported by the scan when opening the database
+            if !matches!(layout.parse(&name), Ok(None)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Replays a log file into the in-memory key directory.
+    ///
+    /// Scans through the given log file and updates the key directory by:
+    /// - Reading each command header
+    /// - Processing key-value entries and tombstones
+    /// - Keeping whichever record has the latest timestamp for each key
+    ///
+    /// Tombstones are inserted as entries flagged as tombstones so they mask older
+    /// values replayed from other files. The caller removes them once every file has
+    /// been replayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Buffered reader for the log file, replayed from the start of the
+    ///   file wherever it's positioned
+    /// * `file_id` - Timestamp identifier of the log file
+    /// * `keydir` - Key directory built from the files replayed so far
+    ///
+    /// # Returns
+    ///
+    /// Returns the offset right after the last record in the file
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::scan_log`].
+    fn rebuild_keydir(
+        reader: &mut LogReader,
+        file_id: u64,
+        keydir: &mut BTreeMap<Vec<u8>, KeyDirEntry>,
+    ) -> Result<u64, Error> {
+        // A handle's initial position depends on how the storage opened it
+        reader.seek(SeekFrom::Start(0))?;
+        Self::scan_log(reader, file_id, |key, entry| {
+            let position = entry.value_position - key.len() as u64 - RecordHeader::SIZE as u64;
+            if !index_entry(keydir, key, entry) {
+                log::debug!(
+                    "Skipping record at {} in file {} older than the indexed value",
+                    position,
+                    file_id
+                );
+            }
+        })
+    }
+
+    /// Tells whether the bytes of the active file past `position`, where its replay
+    /// stopped, are space reserved by [`Options::preallocate`].
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if they're all zeros, or `false` if they're a record cut short by
+    /// a crash or an uncommitted batch, which replay skips.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * Data follows a zeroed header, which no crash leaves behind
+    ///   ([`Error::CorruptedEntry`])
+    /// * Reading the file fails ([`Error::Io`])
+    fn is_reserved_tail(
+        reader: &mut LogReader,
+        file_id: u64,
+        position: u64,
+    ) -> Result<bool, Error> {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = Vec::with_capacity(RecordHeader::SIZE);
+        let mut zeroed = true;
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            let missing = (RecordHeader::SIZE - header.len()).min(read);
+            header.extend_from_slice(&chunk[..missing]);
+            if chunk[..read].iter().any(|&byte| byte != 0) {
+                zeroed = false;
+                if header.len() == RecordHeader::SIZE {
+                    break;
+                }
+            }
+        }
+        if zeroed {
+            return Ok(true);
+        }
+
+        // Replay stops at a header without a key, what follows it may be records
+        if header.len() == RecordHeader::SIZE && RecordHeader::deserialize(&header)?.key_len == 0 {
+            log::warn!(
+                "Found data past the zeroed header at {} in file {}",
+                position,
+                file_id
+            );
+            return Err(Error::CorruptedEntry { file_id, position });
+        }
+        Ok(false)
+    }
+
+    /// Visits every record of a log file in the order they were written.
+    ///
+    /// Each record is passed to `visit` as its key and the key directory entry that
+    /// would point at it, flagged as a tombstone for removals. Values are skipped, not
+    /// read.
+    ///
+    /// Scanning stops at the end of the file, at a zeroed header, which marks the
+    /// start of space reserved by [`Options::preallocate`], or at a record cut short
+    /// by a crash while it was being written.
+    ///
+    /// The records of a batch written by [`Bitask::write_batch`] are only visited once
+    /// its commit marker is read and matches them; the markers themselves aren't
+    /// visited. A batch without its commit marker at the end of the file is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Buffered reader for the log file, positioned at the first record
+    ///   to visit, usually the start of the file
+    /// * `file_id` - Timestamp identifier of the log file
+    /// * `visit` - Called for every record
+    ///
+    /// # Returns
+    ///
+    /// Returns the offset right after the last record in the file, or the offset of
+    /// the begin marker of an uncommitted batch ending the file
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while reading the file ([`Error::Io`])
+    /// * Log file contains invalid or corrupted data
+    fn scan_log(
+        reader: &mut LogReader,
+        file_id: u64,
+        mut visit: impl FnMut(Vec<u8>, KeyDirEntry),
+    ) -> Result<u64, Error> {
+        let file_len = reader.get_ref().size()?;
+        let mut position = reader.stream_position()?;
+        let mut batch: Option<PendingBatch> = None;
+
+        loop {
+            // Read just the header
+            let mut header_buf = vec![0u8; RecordHeader::SIZE];
+            match reader.read_exact(&mut header_buf) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let header = RecordHeader::deserialize(&header_buf)?;
+            if header.key_len == 0 {
+                // Keys are never empty, this is reserved space past the last record
+                break;
             }
 
-            let header = CommandHeader::deserialize(&header_buf)?;
+            let value_position = position + RecordHeader::SIZE as u64 + header.key_len as u64;
+            if value_position + header.value_size as u64 > file_len {
+                log::warn!(
+                    "Ignoring torn record at {} in file {}, the file ends before it",
+                    position,
+                    file_id
+                );
+                break;
+            }
 
             // Read just the key
             let mut key = vec![0u8; header.key_len as usize];
             reader.read_exact(&mut key)?;
 
+            if header.is_batch_marker() {
+                let mut value = vec![0u8; header.value_size as usize];
+                reader.read_exact(&mut value)?;
+                let start = position;
+                position = value_position + header.value_size as u64;
+                match (Marker::decode(&key, &value), batch.take()) {
+                    (Some(Marker::Begin { count }), previous) => {
+                        if let Some(previous) = previous {
+                            log::warn!(
+                                "Ignoring uncommitted batch at {} in file {}",
+                                previous.start,
+                                file_id
+                            );
+                        }
+                        batch = Some(PendingBatch::new(start, count));
+                    }
+                    (Some(Marker::Commit { count, crc }), Some(pending))
+                        if pending.matches(count, crc) =>
+                    {
+                        for (key, entry) in pending.records {
+                            visit(key, entry);
+                        }
+                    }
+                    (_, pending) => {
+                        log::warn!(
+                            "Ignoring unexpected batch marker at {} in file {}",
+                            start,
+                            file_id
+                        );
+                        batch = pending;
+                    }
+                }
+                continue;
+            }
+
             // Skip the value bytes
             reader.seek(SeekFrom::Current(header.value_size as i64))?;
+            let start = position;
+            position = value_position + header.value_size as u64;
 
-            if header.value_size == 0 {
-                // Remove command
-                keydir.remove(&key);
-            } else {
-                // Set command
-                match keydir.get(&key) {
-                    Some(existing) if existing.timestamp >= header.timestamp => {
-                        // Skip older or same-age entries
-                        continue;
-                    }
-                    _ => {
-                        let value_position =
-                            position + CommandHeader::SIZE as u64 + header.key_len as u64;
-                        keydir.insert(
-                            key,
-                            KeyDirEntry {
-                                file_id,
-                                value_size: header.value_size,
-                                value_position,
-                                timestamp: header.timestamp,
-                            },
+            // Set commands and tombstones are resolved the same way
+            let entry = KeyDirEntry {
+                file_id,
+                value_size: header.value_size,
+                value_position,
+                timestamp: header.timestamp,
+                tombstone: header.is_tombstone(),
+            };
+            match &mut batch {
+                Some(pending) if !pending.is_full() => pending.push(&header_buf, key, entry),
+                _ => {
+                    if let Some(pending) = batch.take() {
+                        log::warn!(
+                            "Ignoring batch at {} in file {}, its commit marker is missing before {}",
+                            pending.start,
+                            file_id,
+                            start
                         );
                     }
+                    visit(key, entry);
                 }
             }
+        }
+
+        // Records of a batch cut short by a crash are dropped along with its markers
+        match batch {
+            Some(pending) => {
+                log::warn!(
+                    "Ignoring uncommitted batch at {} in file {}",
+                    pending.start,
+                    file_id
+                );
+                Ok(pending.start)
+            }
+            None => Ok(position),
+        }
+    }
+
+    /// Rotates the active log file when it reaches the size limit.
+    ///
+    /// This process:
+    /// 1. Renames the current active file to a sealed log file
+    /// 2. Creates a new active file with current timestamp
+    /// 3. Updates internal writer and reader references
+    ///
+    /// # Returns
+    ///
+    /// Returns `()` if rotation was successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// * File operations fail (`Error::Io`)
+    /// * System time operations fail (`Error::TimestampError`)
+    /// * IO operations fail (`Error::Io`)
+    fn rotate_active_file(&mut self) -> Result<(), Error> {
+        let timestamp = self.next_file_id()?;
+
+        // Records may still be buffered when rotating in the middle of a batch
+        self.writer.flush()?;
+
+        // Give back any space reserved past the last record
+        if self.options.preallocate {
+            self.writer.get_ref().set_len(self.write_position)?;
+        }
+
+        // Close the reader of the active file, the sealed file gets a fresh one under
+        // its new name on first read
+        self.readers.remove(&self.writer_id);
+
+        // Rename current active file to regular log file
+        let old_path = self.layout.active_log_path(self.writer_id);
+        let new_path = self.layout.log_path(self.writer_id);
+        self.options.storage.rename(&old_path, &new_path)?;
+
+        // Create new active file
+        let writer = create_active_writer(&self.layout, timestamp, &self.options)?;
+        let reader = self
+            .options
+            .storage
+            .open(&self.layout.active_log_path(timestamp))?;
+
+        // Update writer and readers
+        self.writer = writer;
+        let old_size = std::mem::take(&mut self.write_position);
+        self.readers
+            .insert(timestamp, value_reader(reader, &self.options));
+        log::debug!(
+            "Rotated active file {} to sealed, new active file {}",
+            self.writer_id,
+            timestamp
+        );
+        self.sealed_files.insert(self.writer_id);
+        self.sealed_size += old_size;
+        self.writer_id = timestamp;
+        // The rename and the new file must be on disk before the manifest naming them
+        self.sync_dir(self.layout.data_dir())?;
+        self.write_manifest()?;
+        self.metrics.rotations.add(1);
+
+        Ok(())
+    }
+
+    /// Retrieves the value associated with the given key.
+    ///
+    /// Performs an O(1) lookup in the in-memory index followed by a single disk read.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns the value as a [`Vec<u8>`] if the key exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The data file ends before the value ([`Error::TruncatedValue`])
+    /// * Paranoid reads are on and the record doesn't match ([`Error::RecordMismatch`])
+    /// * The stored checksum doesn't match the record ([`Error::ChecksumMismatch`])
+    /// * The key doesn't exist ([`Error::KeyNotFound`])
+    /// * The data file is missing ([`Error::FileNotFound`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if let Ok(value) = db.ask(b"my_key") {
+    ///     println!("Found value: {:?}", value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn ask(&mut self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut value = Vec::new();
+        self.ask_into(key, &mut value)?;
+        Ok(value)
+    }
+
+    /// Retrieves the value associated with `key` into a caller-provided buffer.
+    ///
+    /// Same as [`Bitask::ask`], but `buf` is cleared and refilled instead of returning
+    /// a new [`Vec<u8>`], so hot read paths can reuse one allocation across lookups.
+    /// Compressed values still allocate while being decompressed.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    /// * `buf` - Buffer receiving the value. Its contents are unspecified after an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let mut buf = Vec::new();
+    /// for key in [&b"key1"[..], b"key2"] {
+    ///     db.ask_into(key, &mut buf)?;
+    ///     println!("{:?}", buf);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn ask_into(&mut self, key: &[u8], buf: &mut Vec<u8>) -> Result<(), Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+        self.sync_if_due()?;
+
+        self.metrics.gets.add(1);
+        let Some(entry) = self.lookup(key)? else {
+            self.metrics.get_misses.add(1);
+            return Err(Error::KeyNotFound);
+        };
+        self.metrics.get_hits.add(1);
+        self.access.touch(key);
+        self.read_value_into(key, &entry, buf)
+    }
 
-            position +=
-                CommandHeader::SIZE as u64 + header.key_len as u64 + header.value_size as u64;
+    /// Retrieves the value associated with `key` as a reader, to stream it.
+    ///
+    /// Meant for values too large to hold in memory comfortably: the reader is
+    /// positioned at the start of the value in its data file and limited to its size,
+    /// so it can be copied to a socket or another file in chunks. Since the value
+    /// isn't read whole, its checksum isn't verified, unlike with [`Bitask::ask`].
+    /// Compressed values are decompressed into memory first and verified then. The
+    /// reader borrows the database, so it can't change while the value is read.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], except for
+    /// [`Error::ChecksumMismatch`] on uncompressed values.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let mut file = std::fs::File::create("video.mp4")?;
+    /// std::io::copy(&mut db.ask_reader(b"video")?, &mut file)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn ask_reader(&mut self, key: &[u8]) -> Result<impl Read + '_, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+        self.sync_if_due()?;
+
+        self.metrics.gets.add(1);
+        let Some(entry) = self.lookup(key)? else {
+            self.metrics.get_misses.add(1);
+            return Err(Error::KeyNotFound);
+        };
+        self.metrics.get_hits.add(1);
+        self.access.touch(key);
+
+        let paranoid = self.options.paranoid_reads;
+        let reader = self.reader(entry.file_id)?;
+        let header = read_record_header(reader, key, &entry, paranoid)?;
+        if CompressionKind::from_flags(header.flags)?.is_some() {
+            let value = self.read_value(key, &entry)?;
+            return Ok(ValueReader::Decompressed(io::Cursor::new(value)));
+        }
+
+        let size = reader.get_ref().size()?;
+        if size < entry.value_position + entry.value_size as u64 {
+            return Err(read_error(
+                reader,
+                key,
+                &entry,
+                io::ErrorKind::UnexpectedEof.into(),
+            ));
+        }
+        self.metrics
+            .bytes_read
+            .add(RecordHeader::SIZE as u64 + key.len() as u64 + entry.value_size as u64);
+        let reader = self
+            .readers
+            .get_mut(&entry.file_id)
+            .ok_or(Error::FileNotFound(format!("{}", entry.file_id)))?;
+        // The header was just read, so the reader is at the start of the value
+        Ok(ValueReader::Stored(Read::take(
+            reader,
+            entry.value_size as u64,
+        )))
+    }
+
+    /// Retrieves the value associated with `key` without copying it, when possible.
+    ///
+    /// Sealed files never change, so they are memory-mapped on first use and values
+    /// stored uncompressed in them are returned as slices of the mapping. Values in
+    /// the active file, compressed values and files of a [`Storage`] that can't be
+    /// mapped are read into memory instead, see [`MmapGuard::is_mapped`]. The guard
+    /// borrows the database, so it can't change while the value is in use. Writes
+    /// still buffered with [`Durability::Buffered`] are flushed first, as the active
+    /// file is read through a new reader.
+    ///
+    /// Unlike [`Bitask::ask`], lookups made this way aren't counted in [`Metrics`].
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let value = db.ask_mmap(b"my_key")?;
+    /// assert_eq!(&*value, b"my_value");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "mmap")]
+    pub fn ask_mmap(&mut self, key: &[u8]) -> Result<MmapGuard<'_>, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+        self.flush_buffered()?;
+
+        // With hashed keys, the record of every slot sharing the hash of `key` is read
+        // until one holds `key`
+        let hashed = self.options.hash_keys;
+        let paranoid = self.options.paranoid_reads || hashed;
+        for (_, entry) in key_hash::candidates(&self.keydir, key, hashed) {
+            match self.read_mmap_entry(key, entry, paranoid) {
+                Err(Error::RecordMismatch { .. }) if hashed => continue,
+                result => return result,
+            }
+        }
+        Err(Error::KeyNotFound)
+    }
+
+    /// Reads the value of `key` at `entry` for [`Bitask::ask_mmap`].
+    #[cfg(feature = "mmap")]
+    fn read_mmap_entry(
+        &self,
+        key: &[u8],
+        entry: &KeyDirEntry,
+        paranoid: bool,
+    ) -> Result<MmapGuard<'_>, Error> {
+        if entry.file_id != self.writer_id {
+            if let Some(map) = self.mmap(entry.file_id)? {
+                return mmap::read_mapped(map, key, entry, paranoid);
+            }
+        }
+
+        // The active file is still written to, so it's read through a new reader
+        let path = self
+            .layout
+            .path_for(entry.file_id, entry.file_id == self.writer_id);
+        let mut reader = BufReader::new(self.options.storage.open(&path)?);
+        let value = read_record(&mut reader, key, entry, paranoid)?;
+        Ok(MmapGuard::owned(value))
+    }
+
+    /// Returns the memory mapping of sealed file `file_id`, mapping it on first use.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the [`Storage`] can't map files.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file can't be opened or mapped.
+    #[cfg(feature = "mmap")]
+    fn mmap(&self, file_id: u64) -> Result<Option<std::sync::Arc<memmap2::Mmap>>, Error> {
+        let mut mmaps = self
+            .mmaps
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(map) = mmaps.get(&file_id) {
+            return Ok(Some(map.clone()));
+        }
+
+        let segment = self.options.storage.open(&self.layout.log_path(file_id))?;
+        let Some(map) = segment.map()? else {
+            return Ok(None);
+        };
+        let map = std::sync::Arc::new(map);
+        mmaps.insert(file_id, map.clone());
+        Ok(Some(map))
+    }
+
+    /// Retrieves the values for several keys in one call.
+    ///
+    /// Reads are grouped by file and ordered by offset to improve locality, and the
+    /// open readers are reused across the whole batch. Each key gets its own result,
+    /// so a missing key doesn't fail the others.
+    ///
+    /// # Parameters
+    ///
+    /// * `keys` - The keys to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns one result per key, in the same order as `keys`. Each result fails
+    /// for the same reasons as [`Bitask::ask`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for value in db.ask_many(&[b"key1", b"key2"]) {
+    ///     println!("{:?}", value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn ask_many(&mut self, keys: &[&[u8]]) -> Vec<Result<Vec<u8>, Error>> {
+        let mut results: Vec<Option<Result<Vec<u8>, Error>>> = Vec::with_capacity(keys.len());
+        let mut pending = Vec::new();
+
+        for (index, key) in keys.iter().enumerate() {
+            if key.is_empty() {
+                results.push(Some(Err(Error::InvalidEmptyKey)));
+                continue;
+            }
+
+            self.metrics.gets.add(1);
+            match self.lookup(key) {
+                Ok(Some(entry)) => {
+                    self.metrics.get_hits.add(1);
+                    self.access.touch(key);
+                    results.push(None);
+                    pending.push((index, entry));
+                }
+                Ok(None) => {
+                    self.metrics.get_misses.add(1);
+                    results.push(Some(Err(Error::KeyNotFound)));
+                }
+                Err(e) => results.push(Some(Err(e))),
+            }
+        }
+
+        pending.sort_by_key(|(_, entry)| (entry.file_id, entry.value_position));
+        for (index, entry) in pending {
+            results[index] = Some(self.read_value(keys[index], &entry));
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every key has a result"))
+            .collect()
+    }
+
+    /// Returns the smallest key in the database.
+    ///
+    /// Keys are ordered lexicographically by their bytes, so this is useful as a
+    /// starting point for range pagination or to pick shard boundaries.
+    ///
+    /// # Returns
+    ///
+    /// Returns the first key, or `None` if the database is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeysHashed`] if [`Options::hash_keys`] is set, as keys aren't
+    /// kept in memory then.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if let Some(key) = db.first_key()? {
+    ///     println!("First key: {:?}", key);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn first_key(&self) -> Result<Option<&[u8]>, Error> {
+        if self.options.hash_keys {
+            return Err(Error::KeysHashed);
+        }
+        Ok(self.keydir.keys().next().map(Vec::as_slice))
+    }
+
+    /// Returns the largest key in the database.
+    ///
+    /// # Returns
+    ///
+    /// Returns the last key, or `None` if the database is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeysHashed`] if [`Options::hash_keys`] is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if let Some(key) = db.last_key()? {
+    ///     println!("Last key: {:?}", key);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn last_key(&self) -> Result<Option<&[u8]>, Error> {
+        if self.options.hash_keys {
+            return Err(Error::KeysHashed);
+        }
+        Ok(self.keydir.keys().next_back().map(Vec::as_slice))
+    }
+
+    /// Returns the smallest key in the database with its value.
+    ///
+    /// Same as [`Bitask::first_key`] followed by [`Bitask::ask`], for queue-like use
+    /// of ordered keys.
+    ///
+    /// # Returns
+    ///
+    /// Returns the first key-value pair, or `None` if the database is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], or [`Error::KeysHashed`] if
+    /// [`Options::hash_keys`] is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// while let Some((key, job)) = db.oldest()? {
+    ///     println!("Processing {:?}", job);
+    ///     db.remove(key)?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn oldest(&mut self) -> Result<Option<KeyValue>, Error> {
+        let Some(key) = self.first_key()?.map(<[u8]>::to_vec) else {
+            return Ok(None);
+        };
+        let value = self.ask(&key)?;
+        Ok(Some((key, value)))
+    }
+
+    /// Returns the largest key in the database with its value.
+    ///
+    /// Same as [`Bitask::last_key`] followed by [`Bitask::ask`].
+    ///
+    /// # Returns
+    ///
+    /// Returns the last key-value pair, or `None` if the database is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], or [`Error::KeysHashed`] if
+    /// [`Options::hash_keys`] is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if let Some((key, value)) = db.newest()? {
+    ///     println!("Last entry {:?} = {:?}", key, value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn newest(&mut self) -> Result<Option<KeyValue>, Error> {
+        let Some(key) = self.last_key()?.map(<[u8]>::to_vec) else {
+            return Ok(None);
+        };
+        let value = self.ask(&key)?;
+        Ok(Some((key, value)))
+    }
+
+    /// Returns the oldest and newest write timestamps among the live keys.
+    ///
+    /// Useful to see how old the oldest live data is, for instance to decide on
+    /// retention. Overwritten and removed values aren't counted. Takes a single pass
+    /// over the key directory.
+    ///
+    /// # Returns
+    ///
+    /// Returns `(oldest, newest)` in milliseconds since the UNIX epoch, or `None` if
+    /// the database is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// if let Some((oldest, newest)) = db.timestamp_range() {
+    ///     println!("Live data spans {} ms", newest - oldest);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn timestamp_range(&self) -> Option<(u64, u64)> {
+        self.keydir.values().fold(None, |range, entry| match range {
+            None => Some((entry.timestamp, entry.timestamp)),
+            Some((oldest, newest)) => {
+                Some((oldest.min(entry.timestamp), newest.max(entry.timestamp)))
+            }
+        })
+    }
+
+    /// Returns an estimate of the memory used by the key directory, in bytes.
+    ///
+    /// Counts the allocated capacity of every key plus the size of its key directory
+    /// entry. The overhead of the tree nodes isn't included, so the real usage is
+    /// somewhat higher. With [`Options::hash_keys`], every key takes 12 bytes
+    /// whatever its length.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// println!("Key directory uses about {} bytes", db.keydir_memory_bytes());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn keydir_memory_bytes(&self) -> usize {
+        let per_entry = std::mem::size_of::<Vec<u8>>() + std::mem::size_of::<KeyDirEntry>();
+        self.keydir
+            .keys()
+            .map(|key| key.capacity() + per_entry)
+            .sum()
+    }
+
+    /// Releases memory the key directory no longer needs.
+    ///
+    /// Keys are stored as they were passed to [`Bitask::put`], so a key built with
+    /// spare capacity keeps it for as long as it lives. This shrinks every key to its
+    /// length and rebuilds the key directory, dropping tree nodes left half empty by
+    /// heavy churn. Useful in long-running processes with high key turnover; takes a
+    /// pass over every key.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.compact_keydir_memory();
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn compact_keydir_memory(&mut self) {
+        let before = self.keydir_memory_bytes();
+        self.keydir = std::mem::take(&mut self.keydir)
+            .into_iter()
+            .map(|(mut key, entry)| {
+                key.shrink_to_fit();
+                (key, entry)
+            })
+            .collect();
+        log::debug!(
+            "Compacted key directory memory from {} to {} bytes",
+            before,
+            self.keydir_memory_bytes()
+        );
+    }
+
+    /// Lists up to `count` keys in key order, for cursor-style pagination.
+    ///
+    /// Pass the last key of a page as `start` to get the next one: `start` itself is
+    /// excluded, so pages never overlap.
+    ///
+    /// # Parameters
+    ///
+    /// * `start` - Key to continue after, or `None` to start from the first key (or
+    ///   the last one when `reverse` is set)
+    /// * `count` - Maximum number of keys to return
+    /// * `reverse` - Whether to list keys in descending order
+    ///
+    /// # Returns
+    ///
+    /// Returns the keys of the page, fewer than `count` once the end is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeysHashed`] if [`Options::hash_keys`] is set, as keys aren't
+    /// kept in memory then.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// let mut page = db.list_keys(None, 100, false)?;
+    /// while !page.is_empty() {
+    ///     println!("{} keys", page.len());
+    ///     page = db.list_keys(page.last().map(Vec::as_slice), 100, false)?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn list_keys(
+        &self,
+        start: Option<&[u8]>,
+        count: usize,
+        reverse: bool,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        if self.options.hash_keys {
+            return Err(Error::KeysHashed);
+        }
+        let after = start.map_or(Bound::Unbounded, Bound::Excluded);
+        let range = if reverse {
+            (Bound::Unbounded, after)
+        } else {
+            (after, Bound::Unbounded)
+        };
+
+        let keys = self.keydir.range::<[u8], _>(range).map(|(key, _)| key);
+        Ok(if reverse {
+            keys.rev().take(count).cloned().collect()
+        } else {
+            keys.take(count).cloned().collect()
+        })
+    }
+
+    /// Takes a point-in-time snapshot of the database.
+    ///
+    /// The snapshot copies the key directory and opens its own reader for every file the
+    /// copy references, so later writes, removals and rotations don't affect reads through
+    /// it. Memory usage grows with the number of keys, as the whole index is cloned.
+    /// Writes still buffered with [`Durability::Buffered`] are flushed first, so the
+    /// snapshot sees every write made through this handle.
+    ///
+    /// The referenced files are pinned while the snapshot is alive: [`Bitask::compact`]
+    /// still merges their live entries but leaves the files in place, and they are
+    /// deleted once the last snapshot referencing them is dropped.
+    ///
+    /// # Returns
+    ///
+    /// Returns a [`Snapshot`] of the current state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * [`Options::hash_keys`] is set ([`Error::KeysHashed`])
+    /// * Flushing buffered writes or opening a referenced data file fails ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let mut snapshot = db.snapshot()?;
+    /// db.remove(b"my_key".to_vec())?;
+    /// // The snapshot still sees the removed key
+    /// let value = snapshot.ask(b"my_key")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn snapshot(&mut self) -> Result<Snapshot, Error> {
+        if self.options.hash_keys {
+            return Err(Error::KeysHashed);
+        }
+        self.flush_buffered()?;
+        let mut readers = HashMap::new();
+        for entry in self.keydir.values() {
+            if readers.contains_key(&entry.file_id) {
+                continue;
+            }
+
+            let path = self
+                .layout
+                .path_for(entry.file_id, entry.file_id == self.writer_id);
+            let reader = self.options.storage.open(&path)?;
+            readers.insert(entry.file_id, value_reader(reader, &self.options));
+        }
+
+        Ok(Snapshot::new(
+            self.keydir.clone(),
+            readers,
+            self.pins.clone(),
+            self.options.paranoid_reads,
+        ))
+    }
+
+    /// Returns every record written after `after_timestamp`, file by file.
+    ///
+    /// Intended for change data capture: a consumer replicates the mutations yielded
+    /// and passes the greatest timestamp it saw as the cursor of the next call. Sets
+    /// are yielded as `(key, Some(value))` and removals as `(key, None)`. Every log
+    /// file is scanned, so overwritten values still present in uncompacted files are
+    /// yielded too, while records compaction discarded are gone.
+    ///
+    /// Files are scanned lazily as the iterator advances, the sealed files oldest
+    /// first, then the active file, so only the records of one file are held in
+    /// memory. Within a file, records are yielded in timestamp order, and those with
+    /// the same timestamp in the order they were written. A file written by
+    /// compaction holds records older than those of the files before it.
+    ///
+    /// # Parameters
+    ///
+    /// * `after_timestamp` - Only records with a greater timestamp are yielded
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * Log files contain invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let cursor = 0;
+    /// for record in db.records_since(cursor)? {
+    ///     match record? {
+    ///         (key, Some(value)) => println!("set {:?} = {:?}", key, value),
+    ///         (key, None) => println!("removed {:?}", key),
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn records_since(&mut self, after_timestamp: u64) -> Result<RecordsSince, Error> {
+        let files = self.open_all_files()?;
+        Ok(RecordsSince::new(
+            after_timestamp,
+            files,
+            self.options.paranoid_reads,
+        ))
+    }
+
+    /// Returns every record still on disk in physical order, for audit consumers.
+    ///
+    /// Unlike [`Bitask::records_since`], there is no cursor and records aren't sorted
+    /// by timestamp: the sealed files are read oldest first, then the active file,
+    /// each front to back. Overwritten values and removals are yielded like any other
+    /// record, and each [`LogEvent`] links to the record of the same key it supersedes,
+    /// so downstream materialized views can follow overwrite chains. Records
+    /// compaction discarded are gone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * Log files contain invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for event in db.event_log()? {
+    ///     let event = event?;
+    ///     match (&event.value, event.previous) {
+    ///         (Some(_), None) => println!("inserted {:?}", event.key),
+    ///         (Some(_), Some(_)) => println!("overwrote {:?}", event.key),
+    ///         (None, _) => println!("removed {:?}", event.key),
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn event_log(&mut self) -> Result<EventLog, Error> {
+        let mut records = Vec::new();
+        let readers = self.scan_all_files(|key, entry| records.push((key, entry)))?;
+        Ok(EventLog::new(records, readers, self.options.paranoid_reads))
+    }
+
+    /// Visits every record of every data file, as [`Bitask::scan_log`] does: the sealed
+    /// files oldest first, then the active file, each front to back. Buffered writes
+    /// are flushed first so the active file holds them.
+    ///
+    /// # Returns
+    ///
+    /// Returns the readers the files were scanned with, to read the values of the
+    /// records visited.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * Log files contain invalid or corrupted data
+    fn scan_all_files(
+        &mut self,
+        mut visit: impl FnMut(Vec<u8>, KeyDirEntry),
+    ) -> Result<HashMap<u64, LogReader>, Error> {
+        let mut readers = HashMap::new();
+        for (file_id, mut reader) in self.open_all_files()? {
+            Self::scan_log(&mut reader, file_id, &mut visit)?;
+            readers.insert(file_id, reader);
+        }
+        Ok(readers)
+    }
+
+    /// Opens a reader at the start of every data file: the sealed files oldest first,
+    /// then the active file. Buffered writes are flushed first so the active file
+    /// holds them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if flushing or opening a file fails.
+    fn open_all_files(&mut self) -> Result<Vec<(u64, LogReader)>, Error> {
+        self.writer.flush()?;
+
+        let files = self.sealed_files.iter().map(|&file_id| (file_id, false));
+        files
+            .chain([(self.writer_id, true)])
+            .map(|(file_id, active)| {
+                let path = self.layout.path_for(file_id, active);
+                Ok((file_id, BufReader::new(self.options.storage.open(&path)?)))
+            })
+            .collect()
+    }
+
+    /// Returns every version of `key` still on disk, oldest first.
+    ///
+    /// The log is append-only, so overwritten values and removals stay in the files
+    /// until compaction discards them. Every log file is scanned, which makes this a
+    /// debugging and auditing tool rather than something for the hot path. Each version
+    /// is its timestamp with `Some(value)` for a set or `None` for a removal. Versions
+    /// with the same timestamp are returned in file order.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns the versions found, empty if the key was never written or compaction
+    /// discarded all of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * A version's checksum doesn't match ([`Error::ChecksumMismatch`])
+    /// * Log files contain invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for (timestamp, value) in db.history(b"my_key")? {
+    ///     println!("{}: {:?}", timestamp, value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn history(&mut self, key: &[u8]) -> Result<Vec<KeyVersion>, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+        let mut entries = Vec::new();
+        let mut readers = self.scan_all_files(|record_key, entry| {
+            if record_key == key {
+                entries.push(entry);
+            }
+        })?;
+
+        let mut versions = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let value = match (entry.tombstone, readers.get_mut(&entry.file_id)) {
+                (true, _) => None,
+                (false, Some(reader)) => Some(read_record(
+                    reader,
+                    key,
+                    &entry,
+                    self.options.paranoid_reads,
+                )?),
+                (false, None) => return Err(Error::FileNotFound(format!("{}", entry.file_id))),
+            };
+            versions.push((entry.timestamp, value));
+        }
+
+        // Sorting is stable, so equal timestamps keep the order they were scanned in
+        versions.sort_by_key(|(timestamp, _)| *timestamp);
+        Ok(versions)
+    }
+
+    /// Returns the keys whose latest record on disk is a removal, with the timestamp
+    /// of the removal.
+    ///
+    /// Every log file is replayed as on open, without dropping tombstones at the end,
+    /// so a removal is only listed if no later set masks it. Compacting every sealed
+    /// file drops tombstones, so removals from before the last full compaction are
+    /// gone. Meant for auditing deletes, as it scans every file.
+    ///
+    /// # Returns
+    ///
+    /// Returns the removed keys in key order, each with its removal timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * Log files contain invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for (key, removed_at) in db.tombstones()? {
+    ///     println!("{:?} removed at {}", key, removed_at);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn tombstones(&mut self) -> Result<Vec<(Vec<u8>, u64)>, Error> {
+        let mut keydir = BTreeMap::new();
+        self.scan_all_files(|key, entry| {
+            index_entry(&mut keydir, key, entry);
+        })?;
+
+        Ok(keydir
+            .into_iter()
+            .filter(|(_, entry)| entry.tombstone)
+            .map(|(key, entry)| (key, entry.timestamp))
+            .collect())
+    }
+
+    /// Iterates over every key-value pair in on-disk order, file by file and offset by
+    /// offset.
+    ///
+    /// Reads each data file front to back instead of jumping around as key order
+    /// would, so full dumps make fewer seeks, which matters most on spinning disks.
+    /// Only the current value of each key is yielded. Values are read as the iterator
+    /// advances, and the database can't be modified meanwhile. Use
+    /// [`Snapshot::iter`] for key order.
+    ///
+    /// # Returns
+    ///
+    /// Returns an iterator over `(key, value)` pairs. Each item fails for the same
+    /// reasons as [`Bitask::ask`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for pair in db.scan_by_file() {
+    ///     let (key, value) = pair?;
+    ///     println!("{:?} = {:?}", key, value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn scan_by_file(&mut self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> + '_ {
+        let mut entries: Vec<_> = self
+            .keydir
+            .iter()
+            .map(|(key, entry)| (key.clone(), *entry))
+            .collect();
+        entries.sort_by_key(|(_, entry)| (entry.file_id, entry.value_position));
+
+        entries.into_iter().map(move |(key, entry)| {
+            let key = self.stored_key(key, &entry)?;
+            let value = self.read_value(&key, &entry)?;
+            Ok((key, value))
+        })
+    }
+
+    /// Returns the operation counters accumulated since the database was opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put(b"key".to_vec(), b"value".to_vec())?;
+    /// assert_eq!(db.metrics().puts, 1);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot()
+    }
+
+    /// Registers the operation counters of [`Bitask::metrics`] with a Prometheus
+    /// registry, with the `prometheus` feature.
+    ///
+    /// The counters are read when the registry is gathered, so every scrape sees the
+    /// operations performed up to then. They are named `bitask_<counter>_total`, e.g.
+    /// `bitask_puts_total`; to register several databases with one registry, give
+    /// each its own registry with [`prometheus::Registry::new_custom`] labels.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`prometheus::Error`] if the registry already holds counters with
+    /// the same names.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let registry = prometheus::Registry::new();
+    /// db.register_metrics(&registry)?;
+    /// db.put(b"key".to_vec(), b"value".to_vec())?;
+    /// let families = registry.gather();
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "prometheus")]
+    pub fn register_metrics(&self, registry: &prometheus::Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(metrics::MetricsCollector::new(
+            self.metrics.clone(),
+        )))
+    }
+
+    /// Reads the value a key directory entry points at.
+    ///
+    /// Lazily opens a reader for sealed files that haven't been read yet. The record
+    /// header is read along with the value so its checksum can be verified, using
+    /// whichever algorithm the record was written with.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key the entry belongs to
+    /// * `entry` - Location of the value on disk
+    ///
+    /// # Returns
+    ///
+    /// Returns the value bytes as a [`Vec<u8>`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The data file is missing ([`Error::FileNotFound`])
+    /// * The file ends before the value does ([`Error::TruncatedValue`])
+    /// * Paranoid reads are on and the record doesn't match ([`Error::RecordMismatch`])
+    /// * The stored checksum doesn't match ([`Error::ChecksumMismatch`])
+    /// * IO operations fail ([`Error::Io`])
+    fn read_value(&mut self, key: &[u8], entry: &KeyDirEntry) -> Result<Vec<u8>, Error> {
+        let mut value = Vec::new();
+        self.read_value_into(key, entry, &mut value)?;
+        Ok(value)
+    }
+
+    /// Same as [`Bitask::read_value`], but reads the value into `value`, reusing its
+    /// allocation.
+    fn read_value_into(
+        &mut self,
+        key: &[u8],
+        entry: &KeyDirEntry,
+        value: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let paranoid = self.options.paranoid_reads;
+        let reader = self.reader(entry.file_id)?;
+        read_record_into(reader, key, entry, paranoid, value)?;
+        self.metrics
+            .bytes_read
+            .add(RecordHeader::SIZE as u64 + key.len() as u64 + entry.value_size as u64);
+        Ok(())
+    }
+
+    /// Returns the reader for a data file, opening it on first use.
+    ///
+    /// The active file is opened under its `.active.log` name and sealed files under
+    /// their `.log` name. Buffered writes are flushed before the active file is read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The data file is missing ([`Error::FileNotFound`])
+    /// * IO operations fail ([`Error::Io`])
+    fn reader(&mut self, file_id: u64) -> Result<&mut LogReader, Error> {
+        if file_id == self.writer_id {
+            self.flush_buffered()?;
+        }
+
+        if let std::collections::hash_map::Entry::Vacant(e) = self.readers.entry(file_id) {
+            let path = self.layout.path_for(file_id, file_id == self.writer_id);
+            let reader = self.options.storage.open(&path)?;
+            e.insert(value_reader(reader, &self.options));
+        }
+
+        self.readers
+            .get_mut(&file_id)
+            .ok_or(Error::FileNotFound(format!("{}", file_id)))
+    }
+
+    /// Reads the raw record starting at `offset` in a data file.
+    ///
+    /// Intended for debugging and log inspection tools: records can be walked in
+    /// order by starting at offset 0 and following [`RawRecord::next_offset`] until
+    /// [`Error::NoRecordAt`] is returned. The checksum is reported rather than
+    /// enforced, so corrupted records can still be displayed.
+    ///
+    /// # Parameters
+    ///
+    /// * `file_id` - Identifier (timestamp) of the data file, active or sealed
+    /// * `offset` - Offset of the record header within the file
+    ///
+    /// # Returns
+    ///
+    /// Returns the parsed [`RawRecord`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * No record starts at `offset`, e.g. it's past the last record ([`Error::NoRecordAt`])
+    /// * The data file is missing ([`Error::FileNotFound`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// # let file_id = 0;
+    /// let mut offset = 0;
+    /// while let Ok(record) = db.read_record_at(file_id, offset) {
+    ///     println!("{:?} at {}", record.key, offset);
+    ///     offset = record.next_offset;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn read_record_at(&mut self, file_id: u64, offset: u64) -> Result<RawRecord, Error> {
+        if file_id == self.writer_id && offset >= self.write_position {
+            // Don't read buffered or preallocated space of the active file
+            return Err(Error::NoRecordAt { file_id, offset });
+        }
+
+        let reader = self.reader(file_id)?;
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut header_buf = [0u8; RecordHeader::SIZE];
+        match reader.read_exact(&mut header_buf) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::NoRecordAt { file_id, offset });
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let header = RecordHeader::deserialize(&header_buf)?;
+        if header.key_len == 0 {
+            return Err(Error::NoRecordAt { file_id, offset });
+        }
+
+        let mut key = vec![0u8; header.key_len as usize];
+        let mut value = vec![0u8; header.value_size as usize];
+        reader.read_exact(&mut key)?;
+        reader.read_exact(&mut value)?;
+
+        let checksum_valid = ChecksumKind::from_flags(header.flags)
+            .is_some_and(|checksum| checksum.checksum(&key, &value) == header.crc);
+        let next_offset =
+            offset + RecordHeader::SIZE as u64 + header.key_len as u64 + header.value_size as u64;
+
+        Ok(RawRecord {
+            crc: header.crc,
+            flags: header.flags,
+            timestamp: header.timestamp,
+            key,
+            value: if header.is_tombstone() {
+                None
+            } else {
+                Some(value)
+            },
+            checksum_valid,
+            next_offset,
+        })
+    }
+
+    /// Stores a key-value pair in the database.
+    ///
+    /// If the key already exists, it will be updated with the new value.
+    /// The operation is atomic and durable (synced to disk).
+    ///
+    /// Performance: Requires one disk write (append-only) and one in-memory index update.
+    /// Rotates the active file first if the record would take it past the size limit
+    /// ([`Options::max_file_size`], 4MB by default).
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to store
+    /// * `value` - The value to associate with the key
+    ///
+    /// # Returns
+    ///
+    /// Returns `()` if the operation was successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * The value is empty without [`Options::allow_empty_values`]
+    ///   ([`Error::InvalidEmptyValue`])
+    /// * The value is longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put(b"my_key".to_vec(), b"my_value".to_vec())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.put_located(key, value)?;
+        Ok(())
+    }
+
+    /// Stores a key-value pair like [`Bitask::put`] and returns where its record landed.
+    ///
+    /// Useful to build external indexes pointing at physical locations. The record can
+    /// be read back with [`Bitask::read_record_at`] until compaction moves it.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to store
+    /// * `value` - The value to associate with the key
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`WriteLocation`] of the record.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let location = db.put_located(b"my_key".to_vec(), b"my_value".to_vec())?;
+    /// let record = db.read_record_at(location.file_id, location.offset)?;
+    /// assert_eq!(record.key, b"my_key");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put_located(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<WriteLocation, Error> {
+        check_set(&key, &value, self.options.allow_empty_values)?;
+        let slot = self.slot_of(&key)?;
+        let command = CommandSet::new(
+            key,
+            value,
+            &*self.options.clock,
+            self.options.checksum,
+            self.options.compression,
+        )?;
+        let entry = self.write_set(&command)?;
+        let location = WriteLocation {
+            file_id: entry.file_id,
+            offset: entry.value_position - command.key.len() as u64 - RecordHeader::SIZE as u64,
+            timestamp: entry.timestamp,
+        };
+        self.keydir.insert(slot.unwrap_or(command.key), entry);
+        Ok(location)
+    }
+
+    /// Stores a key-value pair only if the key isn't already present.
+    ///
+    /// The key directory is checked before writing, so an existing key is left
+    /// untouched and nothing is appended to the log. Useful when the first writer
+    /// should win.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to store
+    /// * `value` - The value to associate with the key
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the pair was stored, `false` if the key already existed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if !db.put_if_absent(b"my_key".to_vec(), b"my_value".to_vec())? {
+    ///     println!("my_key was already set");
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put_if_absent(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<bool, Error> {
+        if self.lookup(&key)?.is_some() {
+            return Ok(false);
+        }
+
+        self.put(key, value)?;
+        Ok(true)
+    }
+
+    /// Stores the result of merging `incoming` with the current value of `key`.
+    ///
+    /// `merge` is called with the current value, or `None` if the key is missing, and
+    /// the incoming value, and returns the value to store. Useful to resolve conflicts
+    /// when importing or replicating records, e.g. keeping the larger of two counters
+    /// or the union of two sets.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to store
+    /// * `incoming` - The value to merge into the current one
+    /// * `merge` - Computes the stored value from the current and incoming values
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], except [`Error::KeyNotFound`], and
+    /// [`Bitask::put`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// // Keep the longest value
+    /// db.put_with_merge(b"key".to_vec(), b"value".to_vec(), |current, incoming| {
+    ///     match current {
+    ///         Some(current) if current.len() > incoming.len() => current.to_vec(),
+    ///         _ => incoming.to_vec(),
+    ///     }
+    /// })?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put_with_merge<F: Fn(Option<&[u8]>, &[u8]) -> Vec<u8>>(
+        &mut self,
+        key: Vec<u8>,
+        incoming: Vec<u8>,
+        merge: F,
+    ) -> Result<(), Error> {
+        let current = match self.ask(&key) {
+            Ok(current) => Some(current),
+            Err(Error::KeyNotFound) => None,
+            Err(e) => return Err(e),
+        };
+
+        let value = merge(current.as_deref(), &incoming);
+        self.put(key, value)
+    }
+
+    /// Stores several key-value pairs in one batch.
+    ///
+    /// Every pair is validated before anything is written, so an invalid pair rejects
+    /// the whole batch. The records are then appended back to back to the same file,
+    /// rotating beforehand if needed, and the active file is flushed once at the end,
+    /// instead of once per pair as with [`Bitask::put`]. Later pairs win over earlier
+    /// pairs with the same key. Use [`Bitask::bulk_ingest`] for loads larger than
+    /// [`Options::max_file_size`].
+    ///
+    /// # Parameters
+    ///
+    /// * `pairs` - The key-value pairs to store
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of pairs written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * A key is empty ([`Error::InvalidEmptyKey`])
+    /// * A key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * A value is empty without [`Options::allow_empty_values`]
+    ///   ([`Error::InvalidEmptyValue`])
+    /// * A value is longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+    /// * The pairs don't fit under [`Options::max_total_size`] ([`Error::StorageFull`])
+    /// * IO operations fail ([`Error::Io`]), in which case none of the pairs are
+    ///   stored
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let pairs = vec![
+    ///     (b"key1".to_vec(), b"value1".to_vec()),
+    ///     (b"key2".to_vec(), b"value2".to_vec()),
+    /// ];
+    /// db.put_many(pairs)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put_many<I>(&mut self, pairs: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let timestamp = self.options.clock.now()?;
+        let commands = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                check_set(&key, &value, self.options.allow_empty_values)?;
+                CommandSet::with_timestamp(
+                    key,
+                    value,
+                    timestamp,
+                    self.options.checksum,
+                    self.options.compression,
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let slots = self.slots_of(commands.iter().map(|command| command.key.as_slice()))?;
+
+        // The whole batch goes to the same file, rotating beforehand if needed, so no
+        // rotation or compaction runs before it's indexed
+        let batch_size = commands
+            .iter()
+            .map(|command| (RecordHeader::SIZE + command.key.len() + command.value.len()) as u64)
+            .sum();
+        self.reserve_space(batch_size)?;
+        self.rotate_before(batch_size)?;
+
+        // Pairs are only indexed once all of them are written and flushed, a failure
+        // drops those already written
+        let start = self.write_position;
+        let mut entries = Vec::with_capacity(commands.len());
+        for command in &commands {
+            match self.append_to_active(command) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    self.truncate_active(start);
+                    return Err(e);
+                }
+            }
+        }
+        self.flush_from(start)?;
+
+        let written = commands.len();
+        for ((command, slot), entry) in commands.into_iter().zip(slots).zip(entries) {
+            self.keydir.insert(slot.unwrap_or(command.key), entry);
+        }
+        Ok(written)
+    }
+
+    /// Loads a stream of key-value pairs, for filling a database before it's read.
+    ///
+    /// Unlike [`Bitask::put_many`], pairs are written as the iterator yields them
+    /// rather than collected first, so loading doesn't hold the whole input in
+    /// memory. Records go through the active file's buffer and the file is flushed
+    /// once at the end, instead of once per pair as with [`Bitask::put`]. Later pairs
+    /// win over earlier pairs with the same key.
+    ///
+    /// # Parameters
+    ///
+    /// * `pairs` - The key-value pairs to store
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of pairs written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put_many`]. Pairs are checked and written
+    /// as they come, so a failure stops the load with the pairs before it stored.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let pairs = (0..100_000u32).map(|i| (i.to_be_bytes().to_vec(), b"value".to_vec()));
+    /// assert_eq!(db.bulk_ingest(pairs)?, 100_000);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn bulk_ingest<I>(&mut self, pairs: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let timestamp = self.options.clock.now()?;
+        let mut written = 0;
+        for (key, value) in pairs {
+            check_set(&key, &value, self.options.allow_empty_values)?;
+            let command = CommandSet::with_timestamp(
+                key,
+                value,
+                timestamp,
+                self.options.checksum,
+                self.options.compression,
+            )?;
+            // Indexed right away, as a rotation may compact the files being loaded
+            let slot = self.slot_of(&command.key)?;
+            let entry = self.append_set(&command)?;
+            self.keydir.insert(slot.unwrap_or(command.key), entry);
+            written += 1;
+        }
+        self.flush_writer()?;
+
+        Ok(written)
+    }
+
+    /// Stores a key-value pair using a caller-supplied timestamp.
+    ///
+    /// Intended for importing or replicating data from another store while keeping
+    /// the original write times. The record is always appended to the log, but the
+    /// key directory only points at it if `timestamp` is not older than the timestamp
+    /// of the currently indexed value, so "last write wins" merges are deterministic
+    /// and replay on reopen resolves to the same value.
+    ///
+    /// Timestamps are milliseconds since UNIX epoch and must not be ahead of the
+    /// system clock: a later [`Bitask::put`] stamped with the current time would
+    /// otherwise be considered older than the imported record once the log is replayed.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to store
+    /// * `value` - The value to associate with the key
+    /// * `timestamp` - Original write time of the record
+    ///
+    /// # Returns
+    ///
+    /// Returns `()` if the record was written, even when an existing newer value wins.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * The value is empty without [`Options::allow_empty_values`]
+    ///   ([`Error::InvalidEmptyValue`])
+    /// * The value is longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put_with_timestamp(b"my_key".to_vec(), b"my_value".to_vec(), 1_700_000_000_000)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn put_with_timestamp(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: u64,
+    ) -> Result<(), Error> {
+        let command = CommandSet::with_timestamp(
+            key,
+            value,
+            timestamp,
+            self.options.checksum,
+            self.options.compression,
+        )?;
+        let slot = self.slot_of(&command.key)?;
+        let entry = self.write_set(&command)?;
+        let key = slot.unwrap_or(command.key);
+        match self.keydir.get(&key) {
+            Some(existing) if existing.timestamp > entry.timestamp => {}
+            _ => {
+                self.keydir.insert(key, entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value for `key`, computing and storing it with `f` if absent.
+    ///
+    /// `f` is only called when the key is missing. Since a [`Bitask`] instance is the
+    /// single writer for its directory, no other write can slip in between the lookup
+    /// and the insert.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    /// * `f` - Computes the value to store on a miss
+    ///
+    /// # Returns
+    ///
+    /// Returns the existing value, or the newly stored one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * `f` returns an empty value without [`Options::allow_empty_values`]
+    ///   ([`Error::InvalidEmptyValue`])
+    /// * `f` returns a value longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let value = db.get_or_insert_with(b"my_key", || b"default".to_vec())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn get_or_insert_with<F: FnOnce() -> Vec<u8>>(
+        &mut self,
+        key: &[u8],
+        f: F,
+    ) -> Result<Vec<u8>, Error> {
+        match self.ask(key) {
+            Err(Error::KeyNotFound) => {
+                let value = f();
+                self.put(key.to_vec(), value.clone())?;
+                Ok(value)
+            }
+            result => result,
+        }
+    }
+
+    /// Gets the [`Entry`] of `key` for in-place reads and updates.
+    ///
+    /// Only the key directory is consulted here; values are read when the entry is used.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let value = db.entry(b"my_key".to_vec()).or_insert(b"default".to_vec())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn entry(&mut self, key: Vec<u8>) -> Entry<'_> {
+        Entry::new(self, key)
+    }
+
+    /// Appends a set command to the active file and flushes it, rotating first if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The set command to append
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`KeyDirEntry`] describing where the value was written. The caller
+    /// decides whether to insert it into the key directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * The value is empty without [`Options::allow_empty_values`]
+    ///   ([`Error::InvalidEmptyValue`])
+    /// * The value is longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    fn write_set(&mut self, command: &CommandSet) -> Result<KeyDirEntry, Error> {
+        let entry = self.append_set(command)?;
+        let position = entry.value_position - command.key.len() as u64 - RecordHeader::SIZE as u64;
+        self.flush_from(position)?;
+        Ok(entry)
+    }
+
+    /// Appends a set command to the active file without flushing, rotating it first
+    /// if needed.
+    ///
+    /// Same as [`Bitask::write_set`], but the record may stay in the writer's buffer
+    /// until the next flush.
+    fn append_set(&mut self, command: &CommandSet) -> Result<KeyDirEntry, Error> {
+        let key = &command.key;
+        let value = &command.value;
+        check_set(key, value, self.options.allow_empty_values)?;
+
+        let total_size = RecordHeader::SIZE + key.len() + value.len();
+        self.reserve_space(total_size as u64)?;
+        self.rotate_before(total_size as u64)?;
+        self.append_to_active(command)
+    }
+
+    /// Appends a set command to the active file as it is, without rotating it or
+    /// making room first like [`Bitask::append_set`], and without flushing.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if writing to the active file fails.
+    fn append_to_active(&mut self, command: &CommandSet) -> Result<KeyDirEntry, Error> {
+        let key = &command.key;
+        let value = &command.value;
+        let total_size = RecordHeader::SIZE + key.len() + value.len();
+
+        // Serialize into the scratch buffer, which only allocates when it's too small
+        let mut buffer = std::mem::take(&mut self.scratch);
+        buffer.clear();
+        buffer.resize(RecordHeader::SIZE, 0);
+        buffer.extend_from_slice(key);
+        buffer.extend_from_slice(value);
+        command.serialize(&mut buffer)?;
+
+        let position = self.write_position;
+        let written = self.write_active(&buffer);
+        if buffer.capacity() <= MAX_SCRATCH_CAPACITY {
+            self.scratch = buffer;
+        }
+        written?;
+        self.write_position += total_size as u64;
+        self.metrics.puts.add(1);
+        self.access.touch(key);
+        self.metrics.bytes_written.add(total_size as u64);
+
+        let value_position = position + RecordHeader::SIZE as u64 + key.len() as u64;
+        Ok(KeyDirEntry {
+            file_id: self.writer_id,
+            value_size: value.len() as u32,
+            value_position,
+            timestamp: command.timestamp,
+            tombstone: false,
+        })
+    }
+
+    /// Rotates the active file if appending `record_len` bytes would take it past
+    /// [`Options::max_file_size`], then compacts if [`Options::auto_compact`] is set
+    /// and [`Bitask::should_compact`] says so.
+    ///
+    /// An empty active file is never rotated, so a record larger than the limit gets a
+    /// file of its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if rotating or compacting fails ([`Error::Io`]).
+    fn rotate_before(&mut self, record_len: u64) -> Result<(), Error> {
+        let file_size = self.write_position;
+        if file_size == 0 || file_size + record_len <= self.options.max_file_size {
+            return Ok(());
+        }
+
+        log::debug!(
+            "File size {} would exceed limit with {} more bytes, rotating",
+            file_size,
+            record_len
+        );
+        self.rotate_active_file()?;
+
+        if self.options.auto_compact {
+            log::debug!("Auto-compaction is enabled, checking dead bytes");
+            if self.should_compact() {
+                log::debug!("Auto-triggering compaction");
+                self.compact()?;
+            }
+        } else {
+            log::debug!("Auto-compaction is disabled");
+        }
+        Ok(())
+    }
+
+    /// Makes room for a record of `record_len` bytes under [`Options::max_total_size`],
+    /// applying the configured [`SizeLimitPolicy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The record doesn't fit once the policy was applied ([`Error::StorageFull`])
+    /// * Evicting keys or merging fails ([`Error::Io`])
+    fn reserve_space(&mut self, record_len: u64) -> Result<(), Error> {
+        let Some(limit) = self.options.max_total_size else {
+            return Ok(());
+        };
+        if self.data_size() + record_len <= limit {
+            return Ok(());
+        }
+
+        match self.options.size_limit_policy {
+            SizeLimitPolicy::Reject => (),
+            SizeLimitPolicy::Compact => {
+                log::debug!("Size limit of {} bytes reached, merging", limit);
+                self.merge_unless_compacting()?;
+            }
+            SizeLimitPolicy::EvictOldest => {
+                let evicted = self.evict(limit.saturating_sub(record_len))?;
+                log::debug!(
+                    "Size limit of {} bytes reached, evicted {} keys",
+                    limit,
+                    evicted
+                );
+                self.merge_unless_compacting()?;
+            }
+        }
+
+        let size = self.data_size() + record_len;
+        if size > limit {
+            return Err(Error::StorageFull { size, limit });
+        }
+        Ok(())
+    }
+
+    /// Removes keys in the order of [`Options::eviction`] until the records of the
+    /// remaining keys take at most `live_bytes`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of keys removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if writing the tombstones fails.
+    fn evict(&mut self, live_bytes: u64) -> Result<usize, Error> {
+        let hashed = self.options.hash_keys;
+        let record_len = |key: &[u8], entry: &KeyDirEntry| {
+            (RecordHeader::SIZE + key_hash::key_len(key, hashed)) as u64 + entry.value_size as u64
+        };
+        let mut live: u64 = self
+            .keydir
+            .iter()
+            .map(|(key, entry)| record_len(key, entry))
+            .sum();
+        let mut by_age: Vec<_> = self.keydir.iter().collect();
+        by_age.sort_by_key(|(key, entry)| (self.access.last_access(key), entry.timestamp));
+
+        let mut evicted = Vec::new();
+        for (key, entry) in by_age {
+            if live <= live_bytes {
+                break;
+            }
+            live -= record_len(key, entry);
+            evicted.push((key.clone(), *entry));
+        }
+
+        let count = evicted.len();
+        if count > 0 {
+            let keys = evicted
+                .into_iter()
+                .map(|(key, entry)| self.stored_key(key, &entry))
+                .collect::<Result<_, _>>()?;
+            self.write_tombstones(keys)?;
+        }
+        Ok(count)
+    }
+
+    /// Returns the total size of the data files, counting writes still buffered.
+    fn data_size(&self) -> u64 {
+        self.sealed_size + self.write_position
+    }
+
+    /// Picks the identifier of a new data file.
+    ///
+    /// Normally the current timestamp, but always greater than every existing file ID
+    /// so that files created within the same millisecond never collide. IDs already
+    /// taken by a file in the directory, for instance one this handle doesn't track
+    /// yet, are skipped too, so creating or later sealing the new file can't replace it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if reading the system time fails ([`Error::TimestampError`]).
+    fn next_file_id(&self) -> Result<u64, Error> {
+        let last_id = self
+            .sealed_files
+            .last()
+            .map_or(self.writer_id, |&last| last.max(self.writer_id));
+        let mut file_id = self.options.clock.now()?.max(last_id + 1);
+
+        let storage = &*self.options.storage;
+        while storage.exists(&self.layout.log_path(file_id))
+            || storage.exists(&self.layout.active_log_path(file_id))
+            || storage.exists(&self.layout.compacting_path(file_id))
+        {
+            log::debug!("File ID {} is taken, trying the next one", file_id);
+            file_id += 1;
+        }
+        Ok(file_id)
+    }
+
+    /// Records the current active and sealed files in the manifest.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the manifest can't be written.
+    fn write_manifest(&self) -> Result<(), Error> {
+        // Until a pending compaction finishes, a crash makes the next open scan the
+        // directory, which also removes its unfinished file
+        if self.compacting.load(Ordering::SeqCst) {
+            return Manifest::remove(&*self.options.storage, &self.layout);
+        }
+        Manifest {
+            active: self.writer_id,
+            sealed: self.sealed_files.clone(),
+        }
+        .write(&*self.options.storage, &self.layout)?;
+        self.sync_dir(self.layout.dir())?;
+        Ok(())
+    }
+
+    /// Syncs directory `dir` if [`Options::durability`] syncs files, so the files
+    /// created, renamed or removed in it survive a power loss along with the writes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if syncing fails.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        match self.options.durability {
+            Durability::Sync | Durability::SyncInterval(_) => self.options.storage.sync_dir(dir),
+            Durability::Flush | Durability::Buffered => Ok(()),
+        }
+    }
+
+    /// Writes a serialized record to the active file.
+    ///
+    /// If writing fails, whatever part of the record reached the file is dropped again,
+    /// so the log only holds complete records.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if writing fails.
+    fn write_active(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        #[cfg(test)]
+        let result = match &mut self.fault {
+            Some(fault) => fault.write_all(&mut self.writer, buffer),
+            None => self.writer.write_all(buffer),
+        };
+        #[cfg(not(test))]
+        let result = self.writer.write_all(buffer);
+
+        if let Err(e) = result {
+            self.truncate_active(self.write_position);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Flushes the active file writer like [`Bitask::flush_writer`], dropping the
+    /// records written from `position` on if that fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if flushing or syncing the file fails.
+    fn flush_from(&mut self, position: u64) -> Result<(), Error> {
+        let result = self.flush_writer();
+        if result.is_err() {
+            self.truncate_active(position);
+        }
+        result
+    }
+
+    /// Flushes the writes still buffered with [`Durability::Buffered`], so readers of
+    /// the active file see them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the flush fails.
+    fn flush_buffered(&mut self) -> Result<(), Error> {
+        if !self.writer.buffer().is_empty() {
+            self.writer.flush()?;
+            self.metrics.flushes.add(1);
+        }
+        Ok(())
+    }
+
+    /// Cuts the active file back to `position` after a failed write or flush.
+    ///
+    /// Records before `position` still waiting in the writer's buffer are kept, while
+    /// the bytes of the failed records are dropped from both the buffer and the file.
+    /// The failed operation's error is what callers report, so a failure here is only
+    /// logged; reopening the database still skips a torn record at the end of the file.
+    fn truncate_active(&mut self, position: u64) {
+        if let Err(e) = self.try_truncate_active(position) {
+            log::warn!(
+                "Failed to truncate active file {} to {}: {}",
+                self.writer_id,
+                position,
+                e
+            );
+        }
+    }
+
+    /// Same as [`Bitask::truncate_active`], but reports failures.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the active file can't be reopened, resized or written.
+    fn try_truncate_active(&mut self, position: u64) -> Result<(), Error> {
+        let segment = self
+            .options
+            .storage
+            .create(&self.layout.active_log_path(self.writer_id))?;
+        let (mut failed, buffered) =
+            std::mem::replace(&mut self.writer, BufWriter::new(segment)).into_parts();
+        let buffered = buffered.unwrap_or_else(|e| e.into_inner());
+
+        // Bytes past the end of the file are still buffered, those before `position`
+        // belong to complete records and are buffered again
+        let flushed = failed.stream_position()?;
+        let end = flushed.min(position);
+        let keep = (position - end) as usize;
+        let segment = self.writer.get_mut();
+        segment.set_len(end)?;
+        if self.options.preallocate && end < self.options.max_file_size {
+            segment.set_len(self.options.max_file_size)?;
+        }
+        segment.seek(SeekFrom::Start(end))?;
+        self.writer
+            .write_all(&buffered[..keep.min(buffered.len())])?;
+        self.write_position = position;
+        Ok(())
+    }
+
+    /// Flushes the active file writer according to the configured [`Durability`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if flushing or syncing the file fails.
+    fn flush_writer(&mut self) -> Result<(), Error> {
+        if self.options.durability == Durability::Buffered {
+            return Ok(());
+        }
+
+        #[cfg(test)]
+        if let Some(fault) = &mut self.fault {
+            fault.flush_all(&mut self.writer)?;
+        }
+        self.writer.flush()?;
+        self.metrics.flushes.add(1);
+        match self.options.durability {
+            Durability::Flush | Durability::Buffered => Ok(()),
+            Durability::Sync => self.sync_writer(),
+            Durability::SyncInterval(_) => self.sync_if_due(),
+        }
+    }
+
+    /// Flushes and syncs the active file if [`Durability::SyncInterval`] is set and
+    /// the interval elapsed since the last sync.
+    ///
+    /// Called by writes and at the start of reads, so the interval is honored as long
+    /// as the database is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if reading the clock, flushing or syncing fails.
+    fn sync_if_due(&mut self) -> Result<(), Error> {
+        let Durability::SyncInterval(interval) = self.options.durability else {
+            return Ok(());
+        };
+        let elapsed = self.options.clock.now()?.saturating_sub(self.last_sync);
+        if u128::from(elapsed) < interval.as_millis() {
+            return Ok(());
+        }
+
+        self.writer.flush()?;
+        self.sync_writer()
+    }
+
+    /// Syncs the already flushed active file to stable storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if syncing or reading the clock fails.
+    fn sync_writer(&mut self) -> Result<(), Error> {
+        self.writer.get_ref().sync()?;
+        self.metrics.syncs.add(1);
+        self.last_sync = self.options.clock.now()?;
+        Ok(())
+    }
+
+    /// Flushes and syncs the active file to stable storage, whatever the configured
+    /// [`Durability`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * Flushing or syncing the file fails ([`Error::Io`])
+    /// * A [`Bitask::extend`] call failed since the last sync, with its error
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put(b"key".to_vec(), b"value".to_vec())?;
+    /// db.sync()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        self.sync_writer()?;
+        self.extend_error.take().map_or(Ok(()), Err)
+    }
+
+    /// Closes the database, reporting the errors dropping it would ignore.
+    ///
+    /// Flushes buffered writes, syncs the active file unless [`Options::durability`]
+    /// is [`Durability::Flush`] or [`Durability::Buffered`], trims preallocated space
+    /// and releases the lock, so another handle can open the database right away.
+    /// Dropping the database does the same on a best-effort basis, and still does if
+    /// closing fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * Flushing, syncing or trimming the active file fails ([`Error::Io`])
+    /// * Removing the lock file fails ([`Error::Io`])
+    /// * Reading the clock fails ([`Error::TimestampError`])
+    /// * A [`Bitask::extend`] call failed since the last sync, with its error, once the
+    ///   database is closed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put(b"key".to_vec(), b"value".to_vec())?;
+    /// db.close()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn close(mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        match self.options.durability {
+            Durability::Sync | Durability::SyncInterval(_) => self.sync_writer()?,
+            Durability::Flush | Durability::Buffered => (),
+        }
+        if self.options.preallocate {
+            self.writer.get_ref().set_len(self.write_position)?;
+        }
+
+        match self.options.storage.remove(&self.layout.lock_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        self.closed = true;
+        log::info!("Closed database at {}", self.layout.dir().display());
+        self.extend_error.take().map_or(Ok(()), Err)
+    }
+
+    /// Seals the active file now, instead of waiting for it to reach
+    /// [`Options::max_file_size`].
+    ///
+    /// The active file is renamed to a sealed `.log` file and writes continue in a new,
+    /// empty active file, as on a regular rotation. Useful before backing up or
+    /// shipping the sealed files, which are immutable. An empty active file isn't
+    /// sealed, so calling this twice in a row seals a single file.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the sealed file, or of the active file if it was empty and
+    /// left as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if flushing, renaming or creating a file, or writing the
+    /// manifest, fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.put(b"key".to_vec(), b"value".to_vec())?;
+    /// let sealed = db.seal_active()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn seal_active(&mut self) -> Result<u64, Error> {
+        let file_id = self.writer_id;
+        if self.write_position == 0 {
+            return Ok(file_id);
+        }
+        self.rotate_active_file()?;
+        Ok(file_id)
+    }
+
+    /// Removes a key-value pair from the database.
+    ///
+    /// The operation is atomic and durable. Even if the key doesn't exist,
+    /// a tombstone entry is written to ensure the removal is persisted.
+    ///
+    /// Performance: Requires one disk write (append-only) and one in-memory index update.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to remove
+    ///
+    /// # Returns
+    ///
+    /// Returns `()` if the operation was successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.remove(b"my_key".to_vec())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn remove(&mut self, key: Vec<u8>) -> Result<(), Error> {
+        self.remove_many(vec![key])
+    }
+
+    /// Removes several keys at once.
+    ///
+    /// Every key is validated before anything is written, so an invalid key rejects
+    /// the whole batch. The tombstones are then serialized into one buffer, appended
+    /// and flushed once, instead of once per key as with [`Bitask::remove`]. As with
+    /// [`Bitask::remove`], a tombstone is written even for keys that don't exist.
+    ///
+    /// Tombstones rotate the active file and count towards [`Options::max_total_size`]
+    /// like values do. The [`SizeLimitPolicy`] gets to make room for them, but they're
+    /// never rejected, since removing keys is how space is freed.
+    ///
+    /// # Parameters
+    ///
+    /// * `keys` - The keys to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * A key is empty ([`Error::InvalidEmptyKey`])
+    /// * A key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+    /// * IO operations fail ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.remove_many(vec![b"key1".to_vec(), b"key2".to_vec()])?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn remove_many(&mut self, keys: Vec<Vec<u8>>) -> Result<(), Error> {
+        for key in &keys {
+            if key.is_empty() {
+                return Err(Error::InvalidEmptyKey);
+            }
+            check_key_size(key.len())?;
+        }
+
+        // Tombstones take room like any record and the size limit policy applies to
+        // them, but they're written even past the limit, as removing keys is how the
+        // space is freed once merged. All of them go to the same file.
+        let total_size = keys
+            .iter()
+            .map(|key| (RecordHeader::SIZE + key.len()) as u64)
+            .sum();
+        match self.reserve_space(total_size) {
+            Err(Error::StorageFull { size, limit }) => {
+                log::debug!(
+                    "Writing tombstones past the size limit of {} bytes, to {} bytes",
+                    limit,
+                    size
+                );
+            }
+            result => result?,
+        }
+        self.rotate_before(total_size)?;
+        self.write_tombstones(keys)
+    }
+
+    /// Appends a tombstone for each of `keys` to the active file as it is, flushed
+    /// once, and drops the keys from the key directory.
+    ///
+    /// Doesn't make room or rotate first like [`Bitask::remove_many`], so eviction can
+    /// use it while making room.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if writing or flushing the active file fails.
+    fn write_tombstones(&mut self, keys: Vec<Vec<u8>>) -> Result<(), Error> {
+        let slots = self.slots_of(keys.iter().map(Vec::as_slice))?;
+
+        let total_size = keys.iter().map(|key| RecordHeader::SIZE + key.len()).sum();
+        let mut buffer = Vec::with_capacity(total_size);
+        for key in &keys {
+            let start = buffer.len();
+            buffer.resize(start + RecordHeader::SIZE + key.len(), 0);
+            let command =
+                CommandRemove::new(key.clone(), &*self.options.clock, self.options.checksum)?;
+            command.serialize(&mut buffer[start..])?;
+        }
+
+        let position = self.write_position;
+        self.write_active(&buffer)?;
+        self.write_position += buffer.len() as u64;
+        self.flush_from(position)?;
+        self.metrics.removes.add(keys.len() as u64);
+        self.metrics.bytes_written.add(buffer.len() as u64);
+
+        for (key, slot) in keys.iter().zip(&slots) {
+            self.keydir.remove(slot.as_ref().unwrap_or(key));
+            self.access.forget(key);
+        }
+        Ok(())
+    }
+
+    /// Removes every key within `range`.
+    ///
+    /// Writes a tombstone for each live key in the interval, as [`Bitask::remove`]
+    /// would. Keys are ordered lexicographically by their bytes, so a range such as
+    /// `b"user:".to_vec()..b"user;".to_vec()` clears a key prefix.
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - Bounds on the keys to remove
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of keys removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * [`Options::hash_keys`] is set ([`Error::KeysHashed`])
+    /// * IO operations fail ([`Error::Io`]), in which case the keys visited before
+    ///   the failure have been removed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let removed = db.delete_range(b"b".to_vec()..b"d".to_vec())?;
+    /// println!("Removed {} keys", removed);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn delete_range<R: RangeBounds<Vec<u8>>>(&mut self, range: R) -> Result<usize, Error> {
+        if self.options.hash_keys {
+            return Err(Error::KeysHashed);
+        }
+        let keys: Vec<Vec<u8>> = self
+            .keydir
+            .range(range)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            self.remove(key.clone())?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Returns the fraction of bytes in sealed files that no longer hold live data.
+    ///
+    /// Dead bytes are overwritten values, tombstones and the records of removed keys.
+    /// Only sealed files are considered since the active file is never compacted.
+    ///
+    /// # Returns
+    ///
+    /// Returns a ratio between `0.0` and `1.0`, or `0.0` if there are no sealed files.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the data directory can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// println!("{:.0}% reclaimable", db.dead_bytes_ratio()? * 100.0);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn dead_bytes_ratio(&self) -> Result<f64, Error> {
+        let stats = self.segment_stats()?;
+        let sealed_bytes: u64 = stats.iter().map(|stat| stat.total_bytes).sum();
+        if sealed_bytes == 0 {
+            return Ok(0.0);
+        }
+
+        let live_bytes: u64 = stats.iter().map(|stat| stat.live_bytes).sum();
+        Ok(sealed_bytes.saturating_sub(live_bytes) as f64 / sealed_bytes as f64)
+    }
+
+    /// Returns the size and live data of every sealed file, ordered by file ID.
+    ///
+    /// Useful to plan compactions, e.g. by picking the files with the most dead bytes.
+    /// Live bytes count whole records (header, key and value) the key directory still
+    /// points at, so `total_bytes - live_bytes` is what compaction would reclaim.
+    ///
+    /// # Returns
+    ///
+    /// Returns one [`SegmentStat`] per sealed file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the size of a sealed file can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// for stat in db.segment_stats()? {
+    ///     println!("{}: {} of {} bytes live", stat.file_id, stat.live_bytes, stat.total_bytes);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn segment_stats(&self) -> Result<Vec<SegmentStat>, Error> {
+        let mut stats = BTreeMap::new();
+        for &file_id in &self.sealed_files {
+            let total_bytes = self.options.storage.size(&self.layout.log_path(file_id))?;
+            stats.insert(
+                file_id,
+                SegmentStat {
+                    file_id,
+                    total_bytes,
+                    live_bytes: 0,
+                    live_entries: 0,
+                },
+            );
+        }
+
+        for (key, entry) in &self.keydir {
+            if let Some(stat) = stats.get_mut(&entry.file_id) {
+                let key_len = key_hash::key_len(key, self.options.hash_keys);
+                stat.live_bytes +=
+                    RecordHeader::SIZE as u64 + key_len as u64 + entry.value_size as u64;
+                stat.live_entries += 1;
+            }
+        }
+
+        Ok(stats.into_values().collect())
+    }
+
+    /// Scans one data file and counts its records and how many are still live.
+    ///
+    /// Finer grained than [`Bitask::segment_stats`], which only looks at the key
+    /// directory: every record of the file is read, so overwritten values and removals
+    /// are counted too, which helps inspecting a suspicious file. Works on the active
+    /// file as well as sealed ones. Byte counts cover whole records (header, key and
+    /// value), so space reserved past the last record isn't counted.
+    ///
+    /// # Parameters
+    ///
+    /// * `file_id` - Identifier (timestamp) of the file to scan
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`FileSummary`] of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * `file_id` isn't a data file of the database ([`Error::FileNotFound`])
+    /// * IO operations fail while scanning the file ([`Error::Io`])
+    /// * The file contains invalid or corrupted data
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// for stat in db.segment_stats()? {
+    ///     let summary = db.file_summary(stat.file_id)?;
+    ///     println!("{}: {} of {} records live", summary.file_id, summary.live_records, summary.total_records);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn file_summary(&mut self, file_id: u64) -> Result<FileSummary, Error> {
+        let active = file_id == self.writer_id;
+        if active {
+            self.writer.flush()?;
+        } else if !self.sealed_files.contains(&file_id) {
+            return Err(Error::FileNotFound(format!("{}", file_id)));
+        }
+        let path = self.layout.path_for(file_id, active);
+
+        let mut summary = FileSummary {
+            file_id,
+            total_records: 0,
+            live_records: 0,
+            total_bytes: 0,
+            live_bytes: 0,
+        };
+        let mut reader = BufReader::new(self.options.storage.open(&path)?);
+        let keydir = &self.keydir;
+        let hashed = self.options.hash_keys;
+        summary.total_bytes = Self::scan_log(&mut reader, file_id, |key, entry| {
+            summary.total_records += 1;
+            let is_live = key_hash::candidates(keydir, &key, hashed).any(|(_, live)| {
+                live.file_id == file_id && live.value_position == entry.value_position
+            });
+            if is_live {
+                summary.live_records += 1;
+                summary.live_bytes +=
+                    RecordHeader::SIZE as u64 + key.len() as u64 + entry.value_size as u64;
+            }
+        })?;
+        Ok(summary)
+    }
+
+    /// Returns `true` if enough of the sealed data is dead to make compaction worthwhile.
+    ///
+    /// Compares [`Bitask::dead_bytes_ratio`] against [`Options::compaction_dead_ratio`].
+    /// Automatic compaction uses the same check, and callers driving compaction
+    /// manually can use it to decide when to call [`Bitask::compact`]. Failing to
+    /// compute the ratio is logged and reported as `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// if db.should_compact() {
+    ///     db.compact()?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn should_compact(&self) -> bool {
+        match self.dead_bytes_ratio() {
+            Ok(ratio) => ratio > self.options.compaction_dead_ratio,
+            Err(e) => {
+                log::warn!("Failed to compute dead bytes ratio: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns a one-line, human-readable description of the database.
+    ///
+    /// Formatted as `N keys, M sealed files, X MB on disk, active file <id> (Y KB)`,
+    /// handy in logs and REPLs where the derived [`Debug`] output is too noisy.
+    /// Failing to read the size of the sealed files is logged and they count as empty.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let db = bitask::db::Bitask::open("my_db")?;
+    /// println!("{}", db.summary());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn summary(&self) -> String {
+        let sealed_bytes: u64 = match self.segment_stats() {
+            Ok(stats) => stats.iter().map(|stat| stat.total_bytes).sum(),
+            Err(e) => {
+                log::warn!("Failed to read sealed file sizes: {}", e);
+                0
+            }
+        };
+        let disk_bytes = sealed_bytes + self.write_position;
+
+        format!(
+            "{} keys, {} sealed files, {:.2} MB on disk, active file {} ({:.1} KB)",
+            self.keydir.len(),
+            self.sealed_files.len(),
+            disk_bytes as f64 / (1024.0 * 1024.0),
+            self.writer_id,
+            self.write_position as f64 / 1024.0,
+        )
+    }
+
+    /// Compacts the database by removing obsolete entries and merging files.
+    ///
+    /// This process:
+    /// 1. Identifies immutable files (not including active file)
+    /// 2. Writes the latest entries to a temporary `.compacting` file, under an ID
+    ///    newer than every file of the database and not used by any file on disk
+    /// 3. Renames it to a regular log file once complete, and writes its hint file
+    /// 4. Removes old files after successful compaction, except those pinned by a
+    ///    live [`Snapshot`], which are removed when the snapshot is dropped
+    ///
+    /// Performance: Requires reading all immutable files and writing live entries
+    /// to a new file. Memory usage remains constant as entries are processed
+    /// sequentially.
+    ///
+    /// Live records are copied byte for byte, headers and timestamps included, so
+    /// reopening the database rebuilds the same key directory as before compaction.
+    ///
+    /// Compaction is idempotent: once the sealed files are merged into one, calling
+    /// it again reads and writes nothing until another file is sealed. A compaction
+    /// failing part way leaves every data file on disk either in the file set, to be
+    /// merged by the next compaction, or removed.
+    ///
+    /// Compaction is skipped while a [`Compaction`] is pending: its new file may hold
+    /// values removed since it began, which only the tombstones of the removals keep
+    /// from coming back.
+    ///
+    /// # Returns
+    ///
+    /// Returns `()` if compaction was successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail ([`Error::Io`])
+    /// * File operations fail ([`Error::FileNotFound`])
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// // After many operations, compact to reclaim space
+    /// db.compact()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let immutable_files = self.sealed_files.len();
+        if immutable_files < 2 {
+            log::debug!(
+                "Skipping compaction with {} immutable files",
+                immutable_files
+            );
+            return Ok(());
         }
-        Ok(keydir)
+        if self.compacting.load(Ordering::SeqCst) {
+            log::debug!("Skipping compaction while another one is pending");
+            return Ok(());
+        }
+        log::debug!("Starting compaction of {} immutable files", immutable_files);
+
+        let files = self.sealed_files.clone();
+        self.merge_files(&files, |_| ())?;
+        Ok(())
     }
 
-    /// Rotates the active log file when it reaches the size limit.
+    /// Same as [`Bitask::compact`], reporting progress to `progress` as live entries
+    /// are copied.
     ///
-    /// This process:
-    /// 1. Renames the current active file to a sealed log file
-    /// 2. Creates a new active file with current timestamp
-    /// 3. Updates internal writer and reader references
+    /// `progress` is called every 1024 entries and once more when every entry
+    /// was copied, so a long merge can drive a progress bar or emit metrics. It isn't
+    /// called when compaction is skipped.
+    ///
+    /// # Parameters
+    ///
+    /// * `progress` - Called with the [`CompactionProgress`] so far
     ///
     /// # Returns
     ///
-    /// Returns `()` if rotation was successful.
+    /// Returns the [`CompactionStats`] of the merge, all zero if it was skipped.
     ///
     /// # Errors
     ///
-    /// Returns an error if:
-    /// * File operations fail (`Error::Io`)
-    /// * System time operations fail (`Error::TimestampError`)
-    /// * IO operations fail (`Error::Io`)
-    fn rotate_active_file(&mut self) -> Result<(), Error> {
-        let timestamp = timestamp_as_u64()?;
-
-        // Rename current active file to regular log file
-        let old_path = file_active_log_path(&self.path, self.writer_id);
-        let new_path = file_log_path(&self.path, self.writer_id);
-        fs::rename(old_path, new_path)?;
+    /// Returns the same errors as [`Bitask::compact`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.compact_with_progress(|progress| {
+    ///     println!(
+    ///         "{} entries copied, {} files to go",
+    ///         progress.entries_processed, progress.files_remaining
+    ///     );
+    /// })?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn compact_with_progress(
+        &mut self,
+        progress: impl FnMut(CompactionProgress),
+    ) -> Result<CompactionStats, Error> {
+        if self.sealed_files.len() < 2 || self.compacting.load(Ordering::SeqCst) {
+            return Ok(CompactionStats::default());
+        }
 
-        // Create new active file
-        let writer_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .append(true)
-            .open(file_active_log_path(&self.path, timestamp))?;
-
-        let reader_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .append(true)
-            .open(file_active_log_path(&self.path, timestamp))?;
+        let files = self.sealed_files.clone();
+        self.merge_files(&files, progress)
+    }
 
-        // Update writer and readers
-        self.writer = BufWriter::new(writer_file);
-        self.readers.insert(timestamp, BufReader::new(reader_file));
-        self.writer_id = timestamp;
+    /// Reports what [`Bitask::compact`] would do, without writing anything.
+    ///
+    /// Built from [`Bitask::segment_stats`], so it costs one size lookup per sealed
+    /// file. Files pinned by a live [`Snapshot`] are merged too, but only freed once the
+    /// snapshot is dropped, so the reclaimable bytes are an upper bound.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`CompactionPlan`], empty when compaction would be skipped because
+    /// there are fewer than two sealed files.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the size of a sealed file can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let plan = db.compact_dry_run()?;
+    /// if plan.reclaimable_bytes > 1024 * 1024 {
+    ///     db.compact()?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn compact_dry_run(&self) -> Result<CompactionPlan, Error> {
+        if self.sealed_files.len() < 2 {
+            return Ok(CompactionPlan::default());
+        }
 
-        Ok(())
+        let stats = self.segment_stats()?;
+        Ok(CompactionPlan {
+            file_ids: stats.iter().map(|stat| stat.file_id).collect(),
+            estimated_output_bytes: stats.iter().map(|stat| stat.live_bytes).sum(),
+            reclaimable_bytes: stats
+                .iter()
+                .map(|stat| stat.total_bytes.saturating_sub(stat.live_bytes))
+                .sum(),
+        })
     }
 
-    /// Retrieves the value associated with the given key.
+    /// Compacts a single sealed file by rewriting its live entries into a new file.
     ///
-    /// Performs an O(1) lookup in the in-memory index followed by a single disk read.
+    /// Other files are left untouched, so together with [`Bitask::segment_stats`] this
+    /// allows merging only the files with the most dead bytes. The original file is
+    /// removed afterwards, unless a live [`Snapshot`] still references it. Its
+    /// tombstones are copied too, as another file may still hold a value they remove.
     ///
     /// # Parameters
     ///
-    /// * `key` - The key to look up
+    /// * `file_id` - Identifier (timestamp) of the sealed file to compact
     ///
     /// # Returns
     ///
-    /// Returns the value as a [`Vec<u8>`] if the key exists.
+    /// Returns the [`CompactionStats`] of the rewrite.
     ///
     /// # Errors
     ///
     /// Returns an [`Error`] if:
-    /// * The key is empty ([`Error::InvalidEmptyKey`])
-    /// * The key doesn't exist ([`Error::KeyNotFound`])
-    /// * The data file is missing ([`Error::FileNotFound`])
+    /// * `file_id` isn't a sealed file of the database ([`Error::FileNotFound`])
+    /// * A [`Compaction`] is pending ([`Error::CompactionPending`])
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
     /// * IO operations fail ([`Error::Io`])
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # let mut db = bitask::db::Bitask::open("my_db")?;
-    /// if let Ok(value) = db.ask(b"my_key") {
-    ///     println!("Found value: {:?}", value);
+    /// let stats = db.segment_stats()?;
+    /// if let Some(worst) = stats.iter().max_by_key(|stat| stat.total_bytes - stat.live_bytes) {
+    ///     db.compact_file(worst.file_id)?;
     /// }
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn ask(&mut self, key: &[u8]) -> Result<Vec<u8>, Error> {
-        if key.is_empty() {
-            return Err(Error::InvalidEmptyKey);
+    pub fn compact_file(&mut self, file_id: u64) -> Result<CompactionStats, Error> {
+        if !self.sealed_files.contains(&file_id) {
+            return Err(Error::FileNotFound(format!("{}", file_id)));
         }
-
-        if let Some(entry) = self.keydir.get(key) {
-            if let std::collections::hash_map::Entry::Vacant(e) = self.readers.entry(entry.file_id)
-            {
-                let file = OpenOptions::new()
-                    .read(true)
-                    .open(file_log_path(&self.path, entry.file_id))?;
-                e.insert(BufReader::new(file));
-            }
-
-            let reader = self
-                .readers
-                .get_mut(&entry.file_id)
-                .ok_or(Error::FileNotFound(format!("{}", entry.file_id)))?;
-
-            reader.seek(SeekFrom::Start(entry.value_position))?;
-            let mut value = vec![0; entry.value_size as usize]; // Initialize with zeros
-            reader.read_exact(&mut value)?;
-            return Ok(value);
+        if self.compacting.load(Ordering::SeqCst) {
+            return Err(Error::CompactionPending);
         }
 
-        Err(Error::KeyNotFound)
+        log::debug!("Starting compaction of file {}", file_id);
+        self.merge_files(&BTreeSet::from([file_id]), |_| ())
     }
 
-    /// Stores a key-value pair in the database.
-    ///
-    /// If the key already exists, it will be updated with the new value.
-    /// The operation is atomic and durable (synced to disk).
+    /// Merges exactly the given sealed files into a single new file.
     ///
-    /// Performance: Requires one disk write (append-only) and one in-memory index update.
-    /// May trigger file rotation if the active file exceeds size limit (4MB).
+    /// Same as [`Bitask::compact_file`] for several files at once, leaving the others
+    /// untouched, so a scheduler can pick which files to merge together, such as
+    /// several small ones. IDs given twice are merged once, and an empty list does
+    /// nothing. Tombstones are copied unless every sealed file is merged, as a file
+    /// left out may still hold a value they remove.
     ///
     /// # Parameters
     ///
-    /// * `key` - The key to store
-    /// * `value` - The value to associate with the key
+    /// * `file_ids` - Identifiers (timestamps) of the sealed files to merge
     ///
     /// # Returns
     ///
-    /// Returns `()` if the operation was successful.
+    /// Returns the [`CompactionStats`] of the merge.
     ///
     /// # Errors
     ///
     /// Returns an [`Error`] if:
-    /// * The key is empty ([`Error::InvalidEmptyKey`])
-    /// * The value is empty ([`Error::InvalidEmptyValue`])
+    /// * An ID is the active file ([`Error::ActiveFile`])
+    /// * An ID isn't a file of the database ([`Error::FileNotFound`])
+    /// * A [`Compaction`] is pending ([`Error::CompactionPending`])
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
     /// * IO operations fail ([`Error::Io`])
     ///
+    /// Nothing is merged if an ID is invalid.
+    ///
     /// # Examples
     ///
     /// ```no_run
     /// # let mut db = bitask::db::Bitask::open("my_db")?;
-    /// db.put(b"my_key".to_vec(), b"my_value".to_vec())?;
+    /// let small: Vec<u64> = db
+    ///     .segment_stats()?
+    ///     .iter()
+    ///     .filter(|stat| stat.total_bytes < 64 * 1024)
+    ///     .map(|stat| stat.file_id)
+    ///     .collect();
+    /// db.compact_files(&small)?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
-        if key.is_empty() {
-            return Err(Error::InvalidEmptyKey);
+    pub fn compact_files(&mut self, file_ids: &[u64]) -> Result<CompactionStats, Error> {
+        for &file_id in file_ids {
+            if file_id == self.writer_id {
+                return Err(Error::ActiveFile { file_id });
+            }
+            if !self.sealed_files.contains(&file_id) {
+                return Err(Error::FileNotFound(format!("{}", file_id)));
+            }
         }
-
-        if value.is_empty() {
-            return Err(Error::InvalidEmptyValue);
+        if file_ids.is_empty() {
+            return Ok(CompactionStats::default());
         }
-
-        let file_size = self.writer.get_ref().metadata()?.len();
-        if file_size > MAX_ACTIVE_FILE_SIZE {
-            log::debug!("File size {} exceeded limit, rotating", file_size);
-            self.rotate_active_file()?;
-
-            if false {
-                log::debug!("Auto-compaction is enabled, checking file count");
-                // Count immutable files and trigger compaction if too many
-                let immutable_files = std::fs::read_dir(&self.path)?
-                    .filter_map(Result::ok)
-                    .filter(|entry| {
-                        let name = entry.file_name().to_string_lossy().to_string();
-                        name.ends_with(".log") && !name.ends_with(".active.log")
-                    })
-                    .count();
-
-                log::debug!("Found {} immutable files", immutable_files);
-                if immutable_files >= 2 {
-                    log::debug!(
-                        "Auto-triggering compaction with {} immutable files",
-                        immutable_files
-                    );
-                    self.compact()?;
-                }
-            } else {
-                log::debug!("Auto-compaction is disabled");
-            }
+        if self.compacting.load(Ordering::SeqCst) {
+            return Err(Error::CompactionPending);
         }
 
-        // Pre-allocate a single buffer for the entire command
-        let total_size = CommandHeader::SIZE + key.len() + value.len();
-        let mut buffer = Vec::with_capacity(total_size);
-        buffer.extend_from_slice(&[0; CommandHeader::SIZE]);
-        buffer.extend_from_slice(&key);
-        buffer.extend_from_slice(&value);
-
-        let command = CommandSet::new(key.clone(), value.clone())?;
-        command.serialize(&mut buffer)?;
-
-        let position = self.writer.seek(SeekFrom::End(0))?;
-        self.writer.write_all(&buffer)?;
-        self.writer.flush()?;
-
-        let value_position = position + CommandHeader::SIZE as u64 + key.len() as u64;
-        self.keydir.insert(
-            key,
-            KeyDirEntry {
-                file_id: self.writer_id,
-                value_size: value.len() as u32,
-                value_position,
-                timestamp: command.timestamp,
-            },
-        );
-        Ok(())
+        let file_ids: BTreeSet<u64> = file_ids.iter().copied().collect();
+        log::debug!("Starting compaction of files {:?}", file_ids);
+        self.merge_files(&file_ids, |_| ())
     }
 
-    /// Removes a key-value pair from the database.
+    /// Merges every data file, including the active one, into a single sealed file.
     ///
-    /// The operation is atomic and durable. Even if the key doesn't exist,
-    /// a tombstone entry is written to ensure the removal is persisted.
-    ///
-    /// Performance: Requires one disk write (append-only) and one in-memory index update.
-    ///
-    /// # Parameters
-    ///
-    /// * `key` - The key to remove
+    /// This is the full Bitcask merge: the active file is sealed first, then all sealed
+    /// files are rewritten with only the latest value of each live key, so every key has
+    /// exactly one copy on disk. The merged file gets a hint file so reopening doesn't
+    /// read its values. Unlike [`Bitask::compact`], which leaves the active file alone
+    /// and skips databases with fewer than two sealed files, this always runs.
     ///
     /// # Returns
     ///
-    /// Returns `()` if the operation was successful.
+    /// Returns the [`CompactionStats`] of the merge, all zero if the database holds no data.
     ///
     /// # Errors
     ///
     /// Returns an [`Error`] if:
-    /// * The key is empty ([`Error::InvalidEmptyKey`])
-    /// * IO operations fail ([`Error::Io`])
+    /// * A [`Compaction`] is pending ([`Error::CompactionPending`])
+    /// * Sealing the active file fails ([`Error::Io`], [`Error::TimestampError`])
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
+    /// * IO operations fail while merging ([`Error::Io`])
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # let mut db = bitask::db::Bitask::open("my_db")?;
-    /// db.remove(b"my_key".to_vec())?;
+    /// let stats = db.merge()?;
+    /// println!("{} live entries kept", stats.entries_written);
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn remove(&mut self, key: Vec<u8>) -> Result<(), Error> {
-        if key.is_empty() {
-            return Err(Error::InvalidEmptyKey);
+    pub fn merge(&mut self) -> Result<CompactionStats, Error> {
+        if self.compacting.load(Ordering::SeqCst) {
+            return Err(Error::CompactionPending);
+        }
+        if self.write_position > 0 {
+            self.rotate_active_file()?;
+        }
+        if self.sealed_files.is_empty() {
+            return Ok(CompactionStats::default());
         }
 
-        // Pre-allocate buffer for remove command
-        let total_size = CommandHeader::SIZE + key.len();
-        let mut buffer = Vec::with_capacity(total_size);
-        buffer.extend_from_slice(&[0; CommandHeader::SIZE]);
-        buffer.extend_from_slice(&key);
-
-        let command = CommandRemove::new(key.clone())?;
-        command.serialize(&mut buffer)?;
-
-        self.writer.write_all(&buffer)?;
-        self.writer.flush()?;
+        log::debug!("Starting full merge of {} files", self.sealed_files.len());
+        let files = self.sealed_files.clone();
+        self.merge_files(&files, |_| ())
+    }
 
-        self.keydir.remove(&key);
+    /// Runs [`Bitask::merge`], unless a [`Compaction`] is pending, whose finish
+    /// reclaims the space instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::merge`].
+    fn merge_unless_compacting(&mut self) -> Result<(), Error> {
+        if self.compacting.load(Ordering::SeqCst) {
+            log::debug!("Skipping merge while a compaction is pending");
+            return Ok(());
+        }
+        self.merge()?;
         Ok(())
     }
 
-    /// Compacts the database by removing obsolete entries and merging files.
-    ///
-    /// This process:
-    /// 1. Identifies immutable files (not including active file)
-    /// 2. Creates a new compacted file with only latest entries
-    /// 3. Removes old files after successful compaction
+    /// Writes every live entry into a fresh database at `dest`, leaving this one untouched.
     ///
-    /// Performance: Requires reading all immutable files and writing live entries
-    /// to a new file. Memory usage remains constant as entries are processed
-    /// sequentially.
+    /// The copy holds a single sealed file with its hint file and an empty active file,
+    /// so it is as compact as after [`Bitask::merge`] and can be opened on its own. This
+    /// works as an online backup, or as the first half of a backup-and-swap compaction
+    /// where the directories are swapped once the database is closed. Buffered writes
+    /// are flushed first so they are included. A named database is copied under the
+    /// same name.
     ///
-    /// # Returns
+    /// # Parameters
     ///
-    /// Returns `()` if compaction was successful.
+    /// * `dest` - Directory to write the copy to, created if it doesn't exist
     ///
     /// # Errors
     ///
     /// Returns an [`Error`] if:
+    /// * `dest` is locked by an open database ([`Error::WriterLock`])
+    /// * `dest` or one of its parents is a file ([`Error::NotADirectory`])
+    /// * `dest` already holds a database ([`Error::Io`])
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
     /// * IO operations fail ([`Error::Io`])
-    /// * File operations fail ([`Error::FileNotFound`])
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # let mut db = bitask::db::Bitask::open("my_db")?;
-    /// // After many operations, compact to reclaim space
-    /// db.compact()?;
+    /// db.compact_to("my_db.compacted")?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn compact(&mut self) -> Result<(), Error> {
-        let immutable_files = std::fs::read_dir(&self.path)?
-            .filter_map(Result::ok)
-            .filter(|entry| {
-                let name = entry.file_name().to_string_lossy().to_string();
-                name.ends_with(".log") && !name.ends_with(".active.log")
-            })
-            .count();
-        if immutable_files < 2 {
-            return Ok(());
+    pub fn compact_to(&mut self, dest: impl AsRef<Path>) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        let storage = self.options.storage.clone();
+        let layout = self.layout.with_dir(dest);
+        create_dirs(&*storage, &layout)?;
+        // Keeps the copy from being opened while it's written
+        let _lock = lock_database(&*storage, &layout, None)?;
+        if storage.exists(&layout.manifest_path()) || Self::has_data_files(&*storage, &layout)? {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already holds a database", layout.dir().display()),
+            )));
+        }
+
+        log::debug!(
+            "Starting compaction of {} keys into {}",
+            self.keydir.len(),
+            layout.dir().display()
+        );
+
+        // Same crash safety as compaction: the copy is only a database once its
+        // manifest is written, and a leftover temporary file is removed on open
+        let file_id = self.next_file_id()?;
+        let compacting_path = layout.compacting_path(file_id);
+        let mut writer = BufWriter::new(storage.create(&compacting_path)?);
+        let mut file_ids = self.sealed_files.clone();
+        file_ids.insert(self.writer_id);
+        if let Err(e) = self.copy_live_entries(&file_ids, &mut writer, |_| ()) {
+            drop(writer);
+            storage.remove(&compacting_path)?;
+            return Err(e);
+        }
+        writer.flush()?;
+        writer.get_ref().sync()?;
+        storage.rename(&compacting_path, &layout.log_path(file_id))?;
+
+        // Locate the copied entries, in the same order they were copied
+        let mut keydir = BTreeMap::new();
+        let mut position = 0;
+        for (key, entry) in &self.keydir {
+            let key_len = key_hash::key_len(key, self.options.hash_keys);
+            let value_position = position + RecordHeader::SIZE as u64 + key_len as u64;
+            keydir.insert(
+                key.clone(),
+                KeyDirEntry {
+                    file_id,
+                    value_position,
+                    ..*entry
+                },
+            );
+            position = value_position + entry.value_size as u64;
         }
+        // A hint file lists keys, which hashed keys don't give back
+        if !self.options.hash_keys {
+            hint::write(&*storage, &layout, file_id, position, &keydir)?;
+        }
+
+        let active = file_id + 1;
+        storage.create(&layout.active_log_path(active))?.sync()?;
+        self.sync_dir(layout.data_dir())?;
+        Manifest {
+            active,
+            sealed: BTreeSet::from([file_id]),
+        }
+        .write(&*storage, &layout)?;
+        self.sync_dir(layout.dir())?;
 
-        // Create new file for compaction
-        let timestamp = timestamp_as_u64()?;
-        let mut compaction_writer = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(file_log_path(&self.path, timestamp))?,
+        log::debug!(
+            "Finished compaction into {}: {} bytes written",
+            layout.dir().display(),
+            position
         );
+        Ok(())
+    }
+
+    /// Rewrites the live entries of the given sealed files into a single new file and
+    /// removes the originals.
+    ///
+    /// Tombstones are dropped when every sealed file is merged. Otherwise those
+    /// [`Bitask::kept_tombstones`] returns are copied after the live entries, and the
+    /// new file gets no hint file, as hint files don't list removals.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_ids` - Sealed files to merge
+    /// * `progress` - Called as live entries are copied, see
+    ///   [`Bitask::compact_with_progress`]
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`CompactionStats`] of the merge.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * A live record doesn't match its key directory entry ([`Error::CorruptedEntry`])
+    /// * Reading, writing or removing files fails ([`Error::Io`])
+    fn merge_files(
+        &mut self,
+        file_ids: &BTreeSet<u64>,
+        progress: impl FnMut(CompactionProgress),
+    ) -> Result<CompactionStats, Error> {
+        // Until the new file set is recorded, a crash makes the next open scan the
+        // directory, which also cleans up the temporary file
+        let storage = self.options.storage.clone();
+        Manifest::remove(&*storage, &self.layout)?;
+
+        let mut merged_bytes = 0;
+        for &file_id in file_ids {
+            merged_bytes += storage.size(&self.layout.log_path(file_id))?;
+        }
+        let tombstones = self.kept_tombstones(file_ids)?;
+
+        // Create new file for compaction. Entries are written to a temporary file that
+        // only becomes a log file once complete, so a crash never leaves a partial merge.
+        let timestamp = self.next_file_id()?;
+        let compacting_path = self.layout.compacting_path(timestamp);
+        let compaction_file = storage.create(&compacting_path)?;
+        compaction_file.set_len(0)?;
+        let mut compaction_writer = BufWriter::new(compaction_file);
 
+        // Copy live entries, then the tombstones to keep. On failure the partial merge
+        // is removed and the file set, which didn't change, recorded again.
+        let copied = self
+            .copy_live_entries(file_ids, &mut compaction_writer, progress)
+            .and_then(|entries_written| {
+                let path = |file_id| self.layout.log_path(file_id);
+                let removals = tombstones.iter().map(|(key, entry)| (key, entry));
+                copy_records(
+                    &*storage,
+                    path,
+                    false,
+                    removals,
+                    &mut compaction_writer,
+                    |_| (),
+                )?;
+                Ok(entries_written)
+            });
+        let entries_written = match copied {
+            Ok(entries_written) => entries_written,
+            Err(e) => {
+                drop(compaction_writer);
+                storage.remove(&compacting_path)?;
+                self.write_manifest()?;
+                return Err(e);
+            }
+        };
+
+        // Nothing points at the merged file until it's renamed, so a failure drops it
+        // like a failed copy, leaving no file a later compaction wouldn't know about
+        let merged_path = self.layout.log_path(timestamp);
+        let sealed = compaction_writer
+            .flush()
+            .and_then(|()| compaction_writer.get_ref().sync())
+            .and_then(|()| storage.rename(&compacting_path, &merged_path))
+            // The merged file must be on disk before the files it replaces are removed
+            .and_then(|()| self.sync_dir(self.layout.data_dir()));
+        if let Err(e) = sealed {
+            drop(compaction_writer);
+            for path in [&compacting_path, &merged_path] {
+                match storage.remove(path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => (),
+                }
+            }
+            self.write_manifest()?;
+            return Err(e.into());
+        }
+        self.sealed_files.insert(timestamp);
+
+        // Point the keydir at the compacted file, visiting entries in the same order
+        // they were copied so positions line up
         let mut new_pos = 0;
-        // Copy live entries
         for (key, entry) in self.keydir.iter_mut() {
-            // Skip entries in active file
-            if entry.file_id == self.writer_id {
+            if !file_ids.contains(&entry.file_id) {
                 continue;
             }
 
-            // Open reader at the start of the entry (header position)
-            let mut reader = BufReader::new(File::open(file_log_path(&self.path, entry.file_id))?);
-            let header_pos = entry.value_position - key.len() as u64 - CommandHeader::SIZE as u64;
-            reader.seek(SeekFrom::Start(header_pos))?;
-
-            // Copy the entire entry (header + key + value)
-            let entry_size =
-                CommandHeader::SIZE as u64 + key.len() as u64 + entry.value_size as u64;
-            io::copy(&mut reader.take(entry_size), &mut compaction_writer)?;
-
-            // Update position
+            let key_len = key_hash::key_len(key, self.options.hash_keys) as u64;
+            let entry_size = RecordHeader::SIZE as u64 + key_len + entry.value_size as u64;
             entry.file_id = timestamp;
-            entry.value_position = new_pos + CommandHeader::SIZE as u64 + key.len() as u64;
+            entry.value_position = new_pos + RecordHeader::SIZE as u64 + key_len;
             new_pos += entry_size;
         }
+        for (key, _) in &tombstones {
+            new_pos += RecordHeader::SIZE as u64 + key.len() as u64;
+        }
+        self.sealed_size += new_pos;
 
-        compaction_writer.flush()?;
-
-        // Remove old files
-        for file in std::fs::read_dir(&self.path)? {
-            let file = file?;
-            let name = file.file_name().to_string_lossy().to_string();
-            if name.ends_with(".log")
-                && !name.ends_with(".active.log")
-                && !name.starts_with(&timestamp.to_string())
-            {
-                std::fs::remove_file(file.path())?;
-            }
+        // Opening the database can load the new entries from the hint file instead
+        // of replaying the compacted file, unless keys are hashed as hint files list
+        // keys, or the file holds tombstones. From here on a failure records the file
+        // set as it is: the old files are dead, and merged again by the next compaction.
+        let hinted = match self.options.hash_keys || !tombstones.is_empty() {
+            true => Ok(()),
+            false => hint::write(&*storage, &self.layout, timestamp, new_pos, &self.keydir),
+        };
+        if let Err(e) = hinted {
+            self.write_manifest()?;
+            return Err(e);
         }
 
-        Ok(())
+        let removed_files = self.retire_files(file_ids)?;
+        self.metrics.compactions.add(1);
+
+        log::debug!(
+            "Finished compaction into file {}: {} bytes written, {} files removed",
+            timestamp,
+            new_pos,
+            removed_files
+        );
+        Ok(CompactionStats {
+            files_compacted: file_ids.len(),
+            entries_written,
+            bytes_written: new_pos,
+            bytes_reclaimed: merged_bytes.saturating_sub(new_pos),
+        })
     }
-}
 
-/// Header structure for commands stored in the log files.
-/// Contains metadata about the stored key-value pairs.
-#[derive(Debug)]
-struct CommandHeader {
-    /// CRC32 checksum of the key and value
-    crc: u32,
-    /// Timestamp when the command was written
-    timestamp: u64,
-    /// Length of the key in bytes
-    key_len: u32,
-    /// Size of the value in bytes (0 for remove commands)
-    value_size: u32,
-}
+    /// Returns the tombstones of `file_ids` a merge of those files must copy.
+    ///
+    /// A sealed file left out of the merge may hold an older value of a key removed
+    /// in `file_ids`, which would come back on the next open without the tombstone.
+    /// So when some sealed file isn't merged, the tombstones that are still the latest
+    /// record of their key are kept, unless a newer value of the key is live.
+    ///
+    /// # Returns
+    ///
+    /// Returns the keys and entries of the tombstones, in key order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * IO operations fail while scanning the files ([`Error::Io`])
+    /// * Log files contain invalid or corrupted data
+    fn kept_tombstones(
+        &mut self,
+        file_ids: &BTreeSet<u64>,
+    ) -> Result<Vec<(Vec<u8>, KeyDirEntry)>, Error> {
+        if self.sealed_files.is_subset(file_ids) {
+            return Ok(Vec::new());
+        }
+
+        // Hint files don't list tombstones, so the files are scanned
+        let mut replayed = BTreeMap::new();
+        for &file_id in file_ids {
+            let path = self.layout.log_path(file_id);
+            let mut reader = BufReader::new(self.options.storage.open(&path)?);
+            Self::rebuild_keydir(&mut reader, file_id, &mut replayed)?;
+        }
 
-impl CommandHeader {
-    /// Size of the header in bytes, computed from its field types.
-    const SIZE: usize = std::mem::size_of::<u32>()
-        + std::mem::size_of::<u64>()
-        + std::mem::size_of::<u32>()
-        + std::mem::size_of::<u32>();
+        let mut tombstones = Vec::new();
+        for (key, entry) in replayed {
+            if !entry.tombstone {
+                continue;
+            }
+            match self.lookup(&key)? {
+                Some(live) if live.timestamp >= entry.timestamp => (),
+                _ => tombstones.push((key, entry)),
+            }
+        }
+        Ok(tombstones)
+    }
 
-    /// Creates a new command header with the specified metadata.
+    /// Removes sealed files merged into another one, keeping those still referenced
+    /// by a snapshot, and records the new file set.
     ///
-    /// # Arguments
+    /// # Returns
     ///
-    /// * `crc` - CRC32 checksum of the key and value data
-    /// * `timestamp` - Timestamp when the command was created (milliseconds since UNIX epoch)
-    /// * `key_len` - Length of the key in bytes
-    /// * `value_len` - Length of the value in bytes (0 for remove commands)
+    /// Returns the number of files removed.
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// Returns a new [`CommandHeader`] initialized with the provided values
-    fn new(crc: u32, timestamp: u64, key_len: u32, value_len: u32) -> Self {
-        Self {
-            crc,
-            timestamp,
-            key_len,
-            value_size: value_len,
+    /// Returns [`Error::Io`] if removing a file or writing the manifest fails. The
+    /// files not removed yet stay sealed.
+    fn retire_files(&mut self, file_ids: &BTreeSet<u64>) -> Result<usize, Error> {
+        let storage = self.options.storage.clone();
+        let mut removed_files = 0;
+        for &file_id in file_ids {
+            self.readers.remove(&file_id);
+            #[cfg(feature = "mmap")]
+            self.mmaps
+                .get_mut()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .remove(&file_id);
+
+            let file_path = self.layout.log_path(file_id);
+            let pins = &mut self.pins;
+            let mut file_size = 0;
+            let retired = storage
+                .size(&file_path)
+                .map_err(Error::from)
+                .and_then(|size| {
+                    file_size = size;
+                    hint::remove(&*storage, &self.layout, file_id)
+                })
+                .and_then(|()| {
+                    let retired_path = self.layout.retired_path(file_id);
+                    if pins.defer_removal(file_id, &file_path, retired_path)? {
+                        log::debug!("Keeping file {} pinned by a snapshot", file_id);
+                        return Ok(false);
+                    }
+                    storage.remove(&file_path)?;
+                    Ok(true)
+                });
+            match retired {
+                Ok(removed) => {
+                    self.sealed_files.remove(&file_id);
+                    self.sealed_size -= file_size;
+                    removed_files += usize::from(removed);
+                }
+                // The file stays sealed so it isn't left behind on disk
+                Err(e) => {
+                    self.write_manifest()?;
+                    return Err(e);
+                }
+            }
         }
+        self.sync_dir(self.layout.data_dir())?;
+        self.write_manifest()?;
+        Ok(removed_files)
     }
 
-    /// Serializes the header into a byte buffer.
+    /// Copies the records the key directory points at in `file_ids` to `writer`, in
+    /// key order.
     ///
-    /// The header is written in little-endian byte order with the following layout:
-    /// - CRC32 (4 bytes)
-    /// - Timestamp (8 bytes)
-    /// - Key length (4 bytes)
-    /// - Value size (4 bytes)
+    /// `progress` is called every [`COMPACTION_PROGRESS_INTERVAL`] records and once
+    /// after the last one.
     ///
-    /// # Arguments
+    /// # Returns
     ///
-    /// * `buffer` - The vector to write the serialized header to
+    /// Returns the number of records copied.
     ///
     /// # Errors
     ///
-    /// Returns [`Error::Io`] if writing to the buffer fails
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), Error> {
-        // Verify buffer has enough space
-        if buffer.len() < Self::SIZE {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::WriteZero,
-                "buffer too small for header",
-            )));
-        }
+    /// Returns the same errors as [`copy_records`].
+    fn copy_live_entries(
+        &self,
+        file_ids: &BTreeSet<u64>,
+        writer: &mut impl Write,
+        progress: impl FnMut(CompactionProgress),
+    ) -> Result<usize, Error> {
+        let entries = self
+            .keydir
+            .iter()
+            .filter(|(_, entry)| file_ids.contains(&entry.file_id));
+        let path = |file_id| self.layout.path_for(file_id, file_id == self.writer_id);
+        copy_records(
+            &*self.options.storage,
+            path,
+            self.options.hash_keys,
+            entries,
+            writer,
+            progress,
+        )
+    }
+}
 
-        buffer[0..4].copy_from_slice(&self.crc.to_le_bytes());
-        buffer[4..12].copy_from_slice(&self.timestamp.to_le_bytes());
-        buffer[12..16].copy_from_slice(&self.key_len.to_le_bytes());
-        buffer[16..20].copy_from_slice(&self.value_size.to_le_bytes());
-        Ok(())
+/// Copies the records `entries` point at to `writer`, in order.
+///
+/// The data file of each entry is found at `path(file_id)`. `hashed` says whether
+/// the keys of `entries` are slots, see [`Options::hash_keys`]. `progress` is called
+/// every [`COMPACTION_PROGRESS_INTERVAL`] records and once after the last one.
+///
+/// # Returns
+///
+/// Returns the number of records copied.
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * A record doesn't match its key directory entry ([`Error::CorruptedEntry`])
+/// * IO operations fail ([`Error::Io`])
+fn copy_records<'a>(
+    storage: &dyn Storage,
+    path: impl Fn(u64) -> PathBuf,
+    hashed: bool,
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a KeyDirEntry)> + Clone,
+    writer: &mut impl Write,
+    mut progress: impl FnMut(CompactionProgress),
+) -> Result<usize, Error> {
+    // Live entries left to copy from each file, to report the files remaining
+    let mut remaining: HashMap<u64, usize> = HashMap::new();
+    for (_, entry) in entries.clone() {
+        *remaining.entry(entry.file_id).or_default() += 1;
     }
 
-    /// Deserializes a header from a byte buffer.
-    ///
-    /// # Arguments
-    ///
-    /// * `buf` - Buffer containing the serialized header data (must be at least [`Self::SIZE`] bytes)
-    ///
-    /// # Errors
-    ///
-    /// Returns [`Error::Io`] if:
-    /// * The buffer is smaller than [`Self::SIZE`] bytes
-    /// * The buffer contains invalid data that can't be converted to header fields
-    ///
-    /// # Panics
-    ///
-    /// Will not panic as buffer size is checked before conversion
-    fn deserialize(buf: &[u8]) -> Result<Self, Error> {
-        if buf.len() < Self::SIZE {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "buffer too small for header",
-            )));
+    let mut sources: HashMap<u64, LogReader> = HashMap::new();
+    let mut done = CompactionProgress {
+        files_remaining: remaining.len(),
+        ..CompactionProgress::default()
+    };
+    for (key, entry) in entries {
+        let reader = match sources.entry(entry.file_id) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(BufReader::new(storage.open(&path(entry.file_id))?))
+            }
+        };
+
+        // Seek to the start of the entry (header position)
+        let key_len = key_hash::key_len(key, hashed);
+        let header_pos = entry.value_position - key_len as u64 - RecordHeader::SIZE as u64;
+        reader.seek(SeekFrom::Start(header_pos))?;
+
+        // Check the header and key really belong to the entry before relabeling
+        // the record, a stale entry would otherwise copy another key's bytes
+        let mut header_and_key = vec![0; RecordHeader::SIZE + key_len];
+        reader.read_exact(&mut header_and_key)?;
+        let header = RecordHeader::deserialize(&header_and_key)?;
+        let stored_key = &header_and_key[RecordHeader::SIZE..];
+        let key_matches = match hashed {
+            true => key_hash::digest(stored_key)[..] == key[..key_hash::DIGEST_SIZE],
+            false => stored_key == key.as_slice(),
+        };
+        if header.key_len as usize != key_len
+            || header.value_size != entry.value_size
+            || header.timestamp != entry.timestamp
+            || !key_matches
+        {
+            return Err(Error::CorruptedEntry {
+                file_id: entry.file_id,
+                position: header_pos,
+            });
         }
 
-        let crc = u32::from_le_bytes(buf[0..4].try_into()?);
-        let timestamp = u64::from_le_bytes(buf[4..12].try_into()?);
-        let key_len = u32::from_le_bytes(buf[12..16].try_into()?);
-        let value_size = u32::from_le_bytes(buf[16..20].try_into()?);
+        // Copy the entire entry (header + key + value)
+        writer.write_all(&header_and_key)?;
+        io::copy(&mut reader.take(entry.value_size as u64), writer)?;
 
-        Ok(Self {
-            crc,
-            timestamp,
-            key_len,
-            value_size,
-        })
+        done.entries_processed += 1;
+        done.bytes_written += header_and_key.len() as u64 + entry.value_size as u64;
+        if let Some(left) = remaining.get_mut(&entry.file_id) {
+            *left -= 1;
+            if *left == 0 {
+                done.files_remaining -= 1;
+            }
+        }
+        if done
+            .entries_processed
+            .is_multiple_of(COMPACTION_PROGRESS_INTERVAL)
+        {
+            progress(done);
+        }
+    }
+    progress(done);
+    Ok(done.entries_processed)
+}
+
+/// Bulk loads key-value pairs through [`Bitask::put_many`].
+///
+/// `Extend` can't report errors, so a failure, such as an empty key or value or an
+/// IO error, is logged and kept until the next [`Bitask::sync`] or [`Bitask::close`]
+/// returns it. As with [`Bitask::put_many`], none of the pairs of a failed call are
+/// stored. Call [`Bitask::put_many`] directly to handle errors right away.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// db.extend(vec![(b"key".to_vec(), b"value".to_vec())]);
+/// db.sync()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+impl Extend<(Vec<u8>, Vec<u8>)> for Bitask {
+    fn extend<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(&mut self, iter: I) {
+        if let Err(e) = self.put_many(iter) {
+            log::error!("Failed to extend database: {}", e);
+            // Later failures are only logged, the first one is usually the cause
+            self.extend_error.get_or_insert(e);
+        }
     }
 }
 
 /// A command to append a key-value pair to the log.
 #[derive(Debug)]
 struct CommandSet {
-    /// CRC32 checksum of key and value
+    /// Checksum of key and value
     crc: u32,
+    /// Record flags holding the checksum algorithm
+    flags: u8,
     /// Timestamp when command was created (milliseconds since UNIX epoch)
     timestamp: u64,
     /// Key to be stored as [`Vec<u8>`]
@@ -923,8 +4925,10 @@ struct CommandSet {
 /// A command to remove a key from the database.
 #[derive(Debug)]
 struct CommandRemove {
-    /// CRC32 checksum of key
+    /// Checksum of key
     crc: u32,
+    /// Record flags holding the checksum algorithm
+    flags: u8,
     /// Timestamp when command was created (milliseconds since UNIX epoch)
     timestamp: u64,
     /// Key to be removed as [`Vec<u8>`]
@@ -934,12 +4938,15 @@ struct CommandRemove {
 impl CommandSet {
     /// Creates a new set command.
     ///
-    /// Generates a CRC32 checksum of the key-value pair and includes current timestamp.
+    /// Generates a checksum of the key-value pair and includes current timestamp.
     ///
     /// # Arguments
     ///
     /// * `key` - The key to store as [`Vec<u8>`]
     /// * `value` - The value to associate with the key as [`Vec<u8>`]
+    /// * `clock` - Source of the current timestamp
+    /// * `checksum` - Algorithm used to checksum key and value
+    /// * `compression` - Codec applied to the value, if any
     ///
     /// # Returns
     ///
@@ -950,16 +4957,58 @@ impl CommandSet {
     /// Returns an [`Error`] if:
     /// * System time operations fail ([`Error::TimestampError`])
     /// * Timestamp conversion fails ([`Error::TimestampOverflow`])
-    pub fn new(key: Vec<u8>, value: Vec<u8>) -> Result<Self, Error> {
-        let timestamp = timestamp_as_u64()?;
+    /// * Compressing the value fails ([`Error::UnsupportedCompression`], [`Error::Io`])
+    pub fn new(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        clock: &dyn Clock,
+        checksum: ChecksumKind,
+        compression: Option<CompressionKind>,
+    ) -> Result<Self, Error> {
+        Self::with_timestamp(key, value, clock.now()?, checksum, compression)
+    }
 
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(key.as_slice());
-        hasher.update(value.as_slice());
-        let crc = hasher.finalize();
+    /// Creates a new set command with an explicit timestamp.
+    ///
+    /// When `compression` is set the value is compressed here, so the stored value,
+    /// its size and its checksum all refer to the compressed bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to store as [`Vec<u8>`]
+    /// * `value` - The value to associate with the key as [`Vec<u8>`]
+    /// * `timestamp` - Write time in milliseconds since UNIX epoch
+    /// * `checksum` - Algorithm used to checksum key and value
+    /// * `compression` - Codec applied to the value, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`CommandSet`] with its checksum computed over key and stored value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if compressing the value fails
+    /// ([`Error::UnsupportedCompression`], [`Error::Io`]).
+    pub fn with_timestamp(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: u64,
+        checksum: ChecksumKind,
+        compression: Option<CompressionKind>,
+    ) -> Result<Self, Error> {
+        let (value, compression_flags) = match compression {
+            Some(kind) => (kind.compress(&value)?, kind.to_flags()),
+            None => (value, 0),
+        };
+        let crc = checksum.checksum(&key, &value);
+        let empty_flag = match value.is_empty() {
+            true => RecordHeader::EMPTY_VALUE_FLAG,
+            false => 0,
+        };
 
         Ok(Self {
             crc,
+            flags: checksum.to_flags() | compression_flags | empty_flag,
             timestamp,
             key,
             value,
@@ -981,7 +5030,7 @@ impl CommandSet {
     ///
     /// Returns an [`Error::Io`] if IO operations fail
     fn serialize(&self, buffer: &mut [u8]) -> Result<(), Error> {
-        let total_size = CommandHeader::SIZE + self.key.len() + self.value.len();
+        let total_size = RecordHeader::SIZE + self.key.len() + self.value.len();
         if buffer.len() < total_size {
             return Err(Error::Io(std::io::Error::new(
                 std::io::ErrorKind::WriteZero,
@@ -990,18 +5039,18 @@ impl CommandSet {
         }
 
         // Write header
-        CommandHeader::new(
+        RecordHeader::new(
             self.crc,
             self.timestamp,
             self.key.len() as u32,
+            self.flags,
             self.value.len() as u32,
         )
-        .serialize(&mut buffer[..CommandHeader::SIZE])?;
+        .serialize(&mut buffer[..RecordHeader::SIZE])?;
 
         // Write key and value
-        buffer[CommandHeader::SIZE..CommandHeader::SIZE + self.key.len()]
-            .copy_from_slice(&self.key);
-        buffer[CommandHeader::SIZE + self.key.len()..total_size].copy_from_slice(&self.value);
+        buffer[RecordHeader::SIZE..RecordHeader::SIZE + self.key.len()].copy_from_slice(&self.key);
+        buffer[RecordHeader::SIZE + self.key.len()..total_size].copy_from_slice(&self.value);
 
         Ok(())
     }
@@ -1010,11 +5059,13 @@ impl CommandSet {
 impl CommandRemove {
     /// Creates a new remove command.
     ///
-    /// Generates a CRC32 checksum of the key and includes current timestamp.
+    /// Generates a checksum of the key and includes current timestamp.
     ///
     /// # Arguments
     ///
     /// * `key` - The key to remove as [`Vec<u8>`]
+    /// * `clock` - Source of the current timestamp
+    /// * `checksum` - Algorithm used to checksum the key
     ///
     /// # Returns
     ///
@@ -1025,15 +5076,13 @@ impl CommandRemove {
     /// Returns an [`Error`] if:
     /// * System time operations fail ([`Error::TimestampError`])
     /// * Timestamp conversion fails ([`Error::TimestampOverflow`])
-    pub fn new(key: Vec<u8>) -> Result<Self, Error> {
-        let timestamp = timestamp_as_u64()?;
-
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(key.as_slice());
-        let crc = hasher.finalize();
+    pub fn new(key: Vec<u8>, clock: &dyn Clock, checksum: ChecksumKind) -> Result<Self, Error> {
+        let timestamp = clock.now()?;
+        let crc = checksum.checksum(&key, &[]);
 
         Ok(Self {
             crc,
+            flags: checksum.to_flags(),
             timestamp,
             key,
         })
@@ -1053,7 +5102,7 @@ impl CommandRemove {
     ///
     /// Returns an [`Error::Io`] if IO operations fail
     fn serialize(&self, buffer: &mut [u8]) -> Result<(), Error> {
-        let total_size = CommandHeader::SIZE + self.key.len();
+        let total_size = RecordHeader::SIZE + self.key.len();
         if buffer.len() < total_size {
             return Err(Error::Io(std::io::Error::new(
                 std::io::ErrorKind::WriteZero,
@@ -1062,76 +5111,376 @@ impl CommandRemove {
         }
 
         // Write header
-        CommandHeader::new(self.crc, self.timestamp, self.key.len() as u32, 0)
-            .serialize(&mut buffer[..CommandHeader::SIZE])?;
+        RecordHeader::new(
+            self.crc,
+            self.timestamp,
+            self.key.len() as u32,
+            self.flags,
+            0,
+        )
+        .serialize(&mut buffer[..RecordHeader::SIZE])?;
 
         // Write key
-        buffer[CommandHeader::SIZE..total_size].copy_from_slice(&self.key);
+        buffer[RecordHeader::SIZE..total_size].copy_from_slice(&self.key);
 
         Ok(())
     }
 }
 
-/// Constructs the path for an active log file.
-///
-/// # Arguments
+/// Indexes a replayed record unless the key directory already holds a newer one.
 ///
-/// * `path` - Base directory path
-/// * `timestamp` - Timestamp used as file identifier
+/// A later record with the same timestamp wins, since records are replayed in the
+/// order they were written.
 ///
 /// # Returns
 ///
-/// Returns a [`PathBuf`] containing the full path to the active log file in format:
-/// `<path>/<timestamp>.active.log`
-fn file_active_log_path(path: impl AsRef<Path>, timestamp: u64) -> PathBuf {
-    path.as_ref().join(format!("{}.active.log", timestamp))
+/// Returns `false` if the record was skipped as older than the indexed one.
+fn index_entry(
+    keydir: &mut BTreeMap<Vec<u8>, KeyDirEntry>,
+    key: Vec<u8>,
+    entry: KeyDirEntry,
+) -> bool {
+    match keydir.get(&key) {
+        Some(existing) if existing.timestamp > entry.timestamp => false,
+        _ => {
+            keydir.insert(key, entry);
+            true
+        }
+    }
+}
+
+/// Checks that a key-value pair can be stored.
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * The key is empty ([`Error::InvalidEmptyKey`])
+/// * The key is longer than [`MAX_KEY_SIZE`] ([`Error::KeyTooLarge`])
+/// * The value is empty and `allow_empty_value` is `false`
+///   ([`Error::InvalidEmptyValue`])
+/// * The value is longer than [`MAX_VALUE_SIZE`] ([`Error::ValueTooLarge`])
+fn check_set(key: &[u8], value: &[u8], allow_empty_value: bool) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::InvalidEmptyKey);
+    }
+
+    if value.is_empty() && !allow_empty_value {
+        return Err(Error::InvalidEmptyValue);
+    }
+
+    check_key_size(key.len())?;
+    check_value_size(value.len())
+}
+
+/// Checks that a key length fits in the record header's key length field.
+///
+/// Takes the length rather than the key so the limit can be tested without
+/// allocating an oversized key.
+///
+/// # Errors
+///
+/// Returns [`Error::KeyTooLarge`] if the length is over [`MAX_KEY_SIZE`].
+fn check_key_size(len: usize) -> Result<(), Error> {
+    if len > MAX_KEY_SIZE {
+        return Err(Error::KeyTooLarge {
+            size: len,
+            max: MAX_KEY_SIZE,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a value length fits in the record header's value size field.
+///
+/// # Errors
+///
+/// Returns [`Error::ValueTooLarge`] if the length is over [`MAX_VALUE_SIZE`].
+fn check_value_size(len: usize) -> Result<(), Error> {
+    if len > MAX_VALUE_SIZE {
+        return Err(Error::ValueTooLarge {
+            size: len,
+            max: MAX_VALUE_SIZE,
+        });
+    }
+    Ok(())
 }
 
-/// Constructs the path for a regular (sealed) log file.
+/// Reads the record a key directory entry points at and verifies its checksum.
+///
+/// With `paranoid` set, the header and key stored on disk are checked against the
+/// entry and the requested key before the value is read, so a stale entry can't make
+/// the read return the wrong bytes.
 ///
 /// # Arguments
 ///
-/// * `path` - Base directory path
-/// * `timestamp` - Timestamp used as file identifier
+/// * `reader` - Reader over the file holding the record
+/// * `key` - The key the entry belongs to
+/// * `entry` - Location of the value within the file
+/// * `paranoid` - Whether to verify the stored header and key first
 ///
 /// # Returns
 ///
-/// Returns a [`PathBuf`] containing the full path to the log file in format:
-/// `<path>/<timestamp>.log`
-fn file_log_path(path: impl AsRef<Path>, timestamp: u64) -> PathBuf {
-    path.as_ref().join(format!("{}.log", timestamp))
+/// Returns the value bytes as a [`Vec<u8>`].
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * The file ends before the value does ([`Error::TruncatedValue`])
+/// * The stored header or key doesn't match the entry ([`Error::RecordMismatch`])
+/// * The stored checksum doesn't match ([`Error::ChecksumMismatch`])
+/// * The value can't be decompressed ([`Error::UnsupportedCompression`], [`Error::Io`])
+/// * IO operations fail ([`Error::Io`])
+fn read_record(
+    reader: &mut LogReader,
+    key: &[u8],
+    entry: &KeyDirEntry,
+    paranoid: bool,
+) -> Result<Vec<u8>, Error> {
+    let mut value = Vec::new();
+    read_record_into(reader, key, entry, paranoid, &mut value)?;
+    Ok(value)
+}
+
+/// Reader returned by [`Bitask::ask_reader`].
+enum ValueReader<'a> {
+    /// The value as stored in its data file
+    Stored(io::Take<&'a mut LogReader>),
+    /// A compressed value, decompressed into memory
+    Decompressed(io::Cursor<Vec<u8>>),
+}
+
+impl Read for ValueReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Stored(reader) => reader.read(buf),
+            Self::Decompressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Wraps a data file in a reader for values, buffered as [`Options::read_buffer_size`]
+/// says.
+fn value_reader(segment: Box<dyn Segment>, options: &Options) -> LogReader {
+    BufReader::with_capacity(options.read_buffer_size, segment)
+}
+
+/// Same as [`read_record`], but reads the value into `value`, reusing its allocation.
+///
+/// `value` is cleared first and holds the value on success. Its contents are
+/// unspecified after an error.
+fn read_record_into(
+    reader: &mut LogReader,
+    key: &[u8],
+    entry: &KeyDirEntry,
+    paranoid: bool,
+    value: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let header = read_record_header(reader, key, entry, paranoid)?;
+    let header_position = entry.value_position - key.len() as u64 - RecordHeader::SIZE as u64;
+
+    value.clear();
+    value.resize(entry.value_size as usize, 0);
+    reader
+        .read_exact(value)
+        .map_err(|e| read_error(reader, key, entry, e))?;
+
+    let checksum = ChecksumKind::from_flags(header.flags).ok_or(Error::UnknownChecksum {
+        flags: header.flags,
+    })?;
+    if checksum.checksum(key, value) != header.crc {
+        return Err(Error::ChecksumMismatch {
+            file_id: entry.file_id,
+            position: header_position,
+        });
+    }
+
+    if let Some(compression) = CompressionKind::from_flags(header.flags)? {
+        *value = compression.decompress(value)?;
+    }
+    Ok(())
+}
+
+/// Reads the header of the record `entry` points at, leaving `reader` at the start
+/// of the value.
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * The file ends before the header does ([`Error::TruncatedValue`])
+/// * The stored header or key doesn't match the entry ([`Error::RecordMismatch`])
+/// * IO operations fail ([`Error::Io`])
+fn read_record_header(
+    reader: &mut LogReader,
+    key: &[u8],
+    entry: &KeyDirEntry,
+    paranoid: bool,
+) -> Result<RecordHeader, Error> {
+    let header_position = entry.value_position - key.len() as u64 - RecordHeader::SIZE as u64;
+    reader.seek(SeekFrom::Start(header_position))?;
+
+    let mut header_buf = [0u8; RecordHeader::SIZE];
+    reader
+        .read_exact(&mut header_buf)
+        .map_err(|e| read_error(reader, key, entry, e))?;
+    let header = RecordHeader::deserialize(&header_buf)?;
+
+    if paranoid {
+        if header.key_len as usize != key.len() || header.value_size != entry.value_size {
+            return Err(Error::RecordMismatch {
+                file_id: entry.file_id,
+                position: header_position,
+            });
+        }
+        let mut stored_key = vec![0; key.len()];
+        reader
+            .read_exact(&mut stored_key)
+            .map_err(|e| read_error(reader, key, entry, e))?;
+        if stored_key != key {
+            return Err(Error::RecordMismatch {
+                file_id: entry.file_id,
+                position: header_position,
+            });
+        }
+    } else {
+        reader.seek_relative(key.len() as i64)?;
+    }
+    Ok(header)
+}
+
+/// Converts an error reading the record of `key` into an [`Error`], reporting an
+/// unexpected end of file as [`Error::TruncatedValue`].
+fn read_error(reader: &LogReader, key: &[u8], entry: &KeyDirEntry, e: io::Error) -> Error {
+    if e.kind() != io::ErrorKind::UnexpectedEof {
+        return e.into();
+    }
+
+    match reader.get_ref().size() {
+        Ok(size) => Error::TruncatedValue {
+            key: key.to_vec(),
+            file_id: entry.file_id,
+            expected: entry.value_size,
+            available: size.saturating_sub(entry.value_position),
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// Creates the directories of a database laid out as `layout` if missing.
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * The database directory, its data subdirectory or one of their parents is a
+///   file ([`Error::NotADirectory`])
+/// * The directories can't be created ([`Error::Io`])
+fn create_dirs(storage: &dyn Storage, layout: &Layout) -> Result<(), Error> {
+    // Creating a directory under a file fails with an obscure OS error
+    if let Some(file) = layout
+        .data_dir()
+        .ancestors()
+        .find(|path| storage.exists(path))
+    {
+        return Err(Error::NotADirectory {
+            path: file.to_path_buf(),
+        });
+    }
+    storage.create_dir_all(layout.data_dir())?;
+    Ok(())
 }
 
-/// Gets current timestamp as milliseconds since UNIX epoch.
+/// Longest pause between two attempts to take the lock of a busy database
+const MAX_LOCK_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Takes the exclusive lock of the database laid out as `layout`.
+///
+/// Without a timeout a busy lock fails right away. With one, the lock is retried with
+/// an exponential backoff until the timeout elapses.
+///
+/// # Arguments
+///
+/// * `storage` - Storage holding the database files
+/// * `layout` - Paths of the database files
+/// * `timeout` - How long to wait for a busy lock
 ///
 /// # Returns
 ///
-/// Returns the current time in milliseconds since UNIX epoch as [`u64`]
+/// Returns the lock handle, which must be kept to hold the lock.
 ///
 /// # Errors
 ///
 /// Returns an [`Error`] if:
-/// * System time operations fail ([`Error::TimestampError`])
-/// * Milliseconds value doesn't fit in [`u64`] ([`Error::TimestampOverflow`])
-fn timestamp_as_u64() -> Result<u64, Error> {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(Error::TimestampError)?
-        .as_millis()
-        .try_into()
-        .map_err(Error::TimestampOverflow)
+/// * The lock is still held when the timeout elapses ([`Error::WriterLock`])
+/// * The lock file can't be opened ([`Error::Io`])
+fn lock_database(
+    storage: &dyn Storage,
+    layout: &Layout,
+    timeout: Option<Duration>,
+) -> Result<StorageLock, Error> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut backoff = Duration::from_millis(1);
+
+    loop {
+        if let Some(lock) = storage.try_lock(&layout.lock_path())? {
+            return Ok(lock);
+        }
+
+        let now = Instant::now();
+        match deadline {
+            Some(deadline) if now < deadline => {
+                thread::sleep(backoff.min(deadline - now));
+                backoff = (backoff * 2).min(MAX_LOCK_BACKOFF);
+            }
+            _ => return Err(Error::WriterLock),
+        }
+    }
+}
+
+/// Creates a new active log file and returns a writer positioned at its start.
+///
+/// Reserves [`Options::max_file_size`] bytes up front when [`Options::preallocate`] is set.
+///
+/// # Arguments
+///
+/// * `layout` - Paths of the database files
+/// * `timestamp` - Timestamp used as file identifier
+/// * `options` - Options of the database
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if the file can't be created or resized.
+fn create_active_writer(
+    layout: &Layout,
+    timestamp: u64,
+    options: &Options,
+) -> Result<BufWriter<Box<dyn Segment>>, Error> {
+    let writer_file = options.storage.create(&layout.active_log_path(timestamp))?;
+
+    if options.preallocate {
+        writer_file.set_len(options.max_file_size)?;
+    }
+
+    Ok(BufWriter::new(writer_file))
 }
 
 impl Drop for Bitask {
     /// Cleans up resources when the database is dropped.
     ///
-    /// Removes the physical lock file from the filesystem to allow
-    /// future database instances to acquire the write lock.
+    /// Trims any preallocated space from the active file and removes the physical
+    /// lock file from the filesystem to allow future database instances to acquire
+    /// the write lock.
     fn drop(&mut self) {
-        // Remove the physical lock file from the filesystem
-        if let Ok(path) = self.path.join("db.lock").canonicalize() {
-            let _ = std::fs::remove_file(path);
+        if self.closed {
+            return;
+        }
+
+        // Leave the active file at its real size
+        if self.options.preallocate && self.writer.flush().is_ok() {
+            let _ = self.writer.get_ref().set_len(self.write_position);
         }
+
+        // Remove the physical lock file from the storage
+        let _ = self.options.storage.remove(&self.layout.lock_path());
+        log::info!("Closed database at {}", self.layout.dir().display());
     }
 }
 
@@ -1143,22 +5492,29 @@ mod tests {
     fn test_set_command_serialization() {
         let key = b"key".to_vec();
         let value = b"value".to_vec();
-        let command = CommandSet::new(key.clone(), value.clone()).unwrap();
+        let command = CommandSet::new(
+            key.clone(),
+            value.clone(),
+            &SystemClock,
+            ChecksumKind::Crc32,
+            None,
+        )
+        .unwrap();
 
-        let mut buffer = vec![0; CommandHeader::SIZE + key.len() + value.len()];
+        let mut buffer = vec![0; RecordHeader::SIZE + key.len() + value.len()];
         command.serialize(&mut buffer).unwrap();
 
         // Check header structure
-        let header = CommandHeader::deserialize(&buffer[..CommandHeader::SIZE]).unwrap();
+        let header = RecordHeader::deserialize(&buffer[..RecordHeader::SIZE]).unwrap();
         assert_eq!(header.key_len, key.len() as u32);
         assert_eq!(header.value_size, value.len() as u32);
 
         // Check key and value bytes
         assert_eq!(
-            &buffer[CommandHeader::SIZE..CommandHeader::SIZE + key.len()],
+            &buffer[RecordHeader::SIZE..RecordHeader::SIZE + key.len()],
             key
         );
-        assert_eq!(&buffer[CommandHeader::SIZE + key.len()..], value);
+        assert_eq!(&buffer[RecordHeader::SIZE + key.len()..], value);
 
         // Verify CRC
         let mut hasher = crc32fast::Hasher::new();
@@ -1170,18 +5526,18 @@ mod tests {
     #[test]
     fn test_remove_command_serialization() {
         let key = b"key".to_vec();
-        let command = CommandRemove::new(key.clone()).unwrap();
+        let command = CommandRemove::new(key.clone(), &SystemClock, ChecksumKind::Crc32).unwrap();
 
-        let mut buffer = vec![0; CommandHeader::SIZE + key.len()];
+        let mut buffer = vec![0; RecordHeader::SIZE + key.len()];
         command.serialize(&mut buffer).unwrap();
 
         // Check header structure
-        let header = CommandHeader::deserialize(&buffer[..CommandHeader::SIZE]).unwrap();
+        let header = RecordHeader::deserialize(&buffer[..RecordHeader::SIZE]).unwrap();
         assert_eq!(header.key_len, key.len() as u32);
         assert_eq!(header.value_size, 0);
 
         // Check key bytes
-        assert_eq!(&buffer[CommandHeader::SIZE..], key);
+        assert_eq!(&buffer[RecordHeader::SIZE..], key);
 
         // Verify CRC
         let mut hasher = crc32fast::Hasher::new();
@@ -1189,6 +5545,385 @@ mod tests {
         assert_eq!(header.crc, hasher.finalize());
     }
 
+    #[test]
+    fn test_oversized_lengths_rejected() {
+        assert!(check_key_size(MAX_KEY_SIZE).is_ok());
+        assert!(matches!(
+            check_key_size(MAX_KEY_SIZE + 1),
+            Err(Error::KeyTooLarge { .. })
+        ));
+        assert!(matches!(
+            check_key_size(u32::MAX as usize),
+            Err(Error::KeyTooLarge { .. })
+        ));
+
+        assert!(check_value_size(MAX_VALUE_SIZE).is_ok());
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            check_value_size(MAX_VALUE_SIZE + 1),
+            Err(Error::ValueTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_torn_write_recovered_on_reopen() -> Result<(), Error> {
+        let key_len = b"key3".len();
+        // Cut inside the header, the key and the value of the third record
+        for cut in [5, RecordHeader::SIZE + 2, RecordHeader::SIZE + key_len + 3] {
+            let temp = tempfile::tempdir()?;
+            {
+                let mut db = Bitask::open(temp.path())?;
+                db.put(b"key1".to_vec(), b"value1".to_vec())?;
+                db.put(b"key2".to_vec(), b"value2".to_vec())?;
+
+                db.fault = Some(fault::FailAfter::new(cut));
+                assert!(matches!(
+                    db.put(b"key3".to_vec(), b"value3".to_vec()),
+                    Err(Error::Io(_))
+                ));
+            }
+
+            let mut db = Bitask::open(temp.path())?;
+            assert_eq!(db.ask(b"key1")?, b"value1");
+            assert_eq!(db.ask(b"key2")?, b"value2");
+            assert!(matches!(db.ask(b"key3"), Err(Error::KeyNotFound)));
+
+            // The torn bytes are dropped, so new records are readable after them
+            db.put(b"key4".to_vec(), b"value4".to_vec())?;
+            drop(db);
+            let mut db = Bitask::open(temp.path())?;
+            assert_eq!(db.ask(b"key4")?, b"value4");
+            assert_eq!(db.keydir.len(), 3);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_verifies_records() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let mut db = Bitask::open(dir.path())?;
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+        db.put(b"key2".to_vec(), b"value2".to_vec())?;
+
+        // Point key1 at the record of key2, which has the same sizes
+        let key1 = db.keydir[&b"key1"[..]];
+        let key2 = db.keydir[&b"key2"[..]];
+        db.keydir.insert(b"key1".to_vec(), key2);
+        assert!(matches!(
+            db.merge(),
+            Err(Error::CorruptedEntry { position, .. })
+                if position == key2.value_position - 4 - RecordHeader::SIZE as u64
+        ));
+        let leftovers = std::fs::read_dir(dir.path())?
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(".compacting")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+
+        db.keydir.insert(b"key1".to_vec(), key1);
+        db.merge()?;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert_eq!(db.ask(b"key2")?, b"value2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_write_truncates_active_file() -> Result<(), Error> {
+        let temp = tempfile::tempdir()?;
+        let mut db = Bitask::open(temp.path())?;
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+        let active = db.layout.active_log_path(db.writer_id);
+        let len = std::fs::metadata(&active)?.len();
+
+        // A flush failing once the record reached the file, and a torn write
+        db.fault = Some(fault::FailAfter::flush());
+        assert!(matches!(
+            db.put(b"key2".to_vec(), b"value2".to_vec()),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(db.remove(b"key1".to_vec()), Err(Error::Io(_))));
+        db.fault = Some(fault::FailAfter::new(RecordHeader::SIZE + 2));
+        assert!(matches!(
+            db.put(b"key3".to_vec(), b"value3".to_vec()),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(std::fs::metadata(&active)?.len(), len);
+        assert_eq!(db.write_position, len);
+
+        db.fault = None;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert!(matches!(db.ask(b"key2"), Err(Error::KeyNotFound)));
+        db.put(b"key4".to_vec(), b"value4".to_vec())?;
+        assert_eq!(db.ask(b"key4")?, b"value4");
+
+        drop(db);
+        let mut db = Bitask::open(temp.path())?;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert_eq!(db.ask(b"key4")?, b"value4");
+        assert_eq!(db.keydir.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_put_many_indexes_nothing_when_flush_fails() -> Result<(), Error> {
+        let temp = tempfile::tempdir()?;
+        let mut db = Bitask::open(temp.path())?;
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+        let len = db.write_position;
+
+        db.fault = Some(fault::FailAfter::flush());
+        let pairs = vec![
+            (b"key1".to_vec(), b"updated".to_vec()),
+            (b"key2".to_vec(), b"value2".to_vec()),
+        ];
+        assert!(matches!(db.put_many(pairs), Err(Error::Io(_))));
+        db.fault = None;
+        assert_eq!(db.write_position, len);
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert!(matches!(db.ask(b"key2"), Err(Error::KeyNotFound)));
+
+        drop(db);
+        let mut db = Bitask::open(temp.path())?;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert!(matches!(db.ask(b"key2"), Err(Error::KeyNotFound)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_size_tracks_files() -> Result<(), Error> {
+        let temp = tempfile::tempdir()?;
+        let mut db = Bitask::builder()
+            .path(temp.path())
+            .max_file_size(1)
+            .open()?;
+        let on_disk = |db: &Bitask| -> Result<u64, Error> {
+            let mut size = db
+                .options
+                .storage
+                .size(&db.layout.active_log_path(db.writer_id))?;
+            for &file_id in &db.sealed_files {
+                size += db.options.storage.size(&db.layout.log_path(file_id))?;
+            }
+            Ok(size)
+        };
+
+        for i in 0..4u8 {
+            db.put(vec![b'k', i], vec![i; 10])?;
+            db.put(b"shared".to_vec(), vec![i; 10])?;
+        }
+        db.sync()?;
+        assert_eq!(db.data_size(), on_disk(&db)?);
+
+        db.compact()?;
+        assert_eq!(db.data_size(), on_disk(&db)?);
+
+        db.reopen()?;
+        assert_eq!(db.data_size(), on_disk(&db)?);
+
+        drop(db);
+        let db = Bitask::open(temp.path())?;
+        assert_eq!(db.data_size(), on_disk(&db)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_flush_keeps_earlier_buffered_records() -> Result<(), Error> {
+        let temp = tempfile::tempdir()?;
+        let mut db = Bitask::builder()
+            .path(temp.path())
+            .durability(Durability::Buffered)
+            .open()?;
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+
+        // The torn record is dropped, the buffered one before it is kept
+        db.fault = Some(fault::FailAfter::new(5));
+        assert!(db.put(b"key2".to_vec(), vec![b'x'; 16 * 1024]).is_err());
+        db.fault = None;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+
+        drop(db);
+        let mut db = Bitask::open(temp.path())?;
+        assert_eq!(db.ask(b"key1")?, b"value1");
+        assert!(matches!(db.ask(b"key2"), Err(Error::KeyNotFound)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_keydir_starts_at_file_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Bitask::open(dir.path()).unwrap();
+        db.put(b"first".to_vec(), b"value1".to_vec()).unwrap();
+        db.put(b"second".to_vec(), b"value2".to_vec()).unwrap();
+        let path = db.layout.active_log_path(db.writer_id);
+        let file_id = db.writer_id;
+        drop(db);
+
+        // A reader left past the first record still replays it
+        let mut reader = BufReader::new(FsStorage.open(&path).unwrap());
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        let mut keydir = BTreeMap::new();
+        let end = Bitask::rebuild_keydir(&mut reader, file_id, &mut keydir).unwrap();
+        assert_eq!(end, FsStorage.size(&path).unwrap());
+        let first = keydir.get(&b"first"[..]).unwrap();
+        assert_eq!(first.value_position, (RecordHeader::SIZE + 5) as u64);
+        assert!(keydir.contains_key(&b"second"[..]));
+
+        let mut db = Bitask::open(dir.path()).unwrap();
+        assert_eq!(db.ask(b"first").unwrap(), b"value1");
+    }
+
+    #[test]
+    fn test_compaction_preserves_record_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new(1_000);
+        let open = || {
+            Bitask::builder()
+                .path(dir.path())
+                .clock(clock.clone())
+                .max_file_size(512)
+                .open()
+                .unwrap()
+        };
+        let mut db = open();
+        for round in 0..3u64 {
+            for i in 0..20 {
+                clock.advance(1);
+                let key = format!("key{:02}", i).into_bytes();
+                db.put(key, format!("value{}-{}", i, round).into_bytes())
+                    .unwrap();
+            }
+        }
+        for i in (0..20).step_by(4) {
+            clock.advance(1);
+            db.remove(format!("key{:02}", i).into_bytes()).unwrap();
+        }
+        // Imported records keep their older timestamps, whether they win or not
+        db.put_with_timestamp(b"key01".to_vec(), b"imported".to_vec(), 5)
+            .unwrap();
+        db.put_with_timestamp(b"imported".to_vec(), b"value".to_vec(), 5)
+            .unwrap();
+        clock.advance(1);
+        db.put(b"last".to_vec(), b"value".to_vec()).unwrap();
+        assert!(db.sealed_files.len() > 2);
+
+        let timestamps = |db: &Bitask| -> BTreeMap<Vec<u8>, u64> {
+            db.keydir
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.timestamp))
+                .collect()
+        };
+        let values = |db: &mut Bitask| -> Vec<Vec<u8>> {
+            let keys: Vec<_> = db.keydir.keys().cloned().collect();
+            keys.iter().map(|key| db.ask(key).unwrap()).collect()
+        };
+        let before = timestamps(&db);
+        let before_values = values(&mut db);
+        assert_eq!(before[&b"key01"[..]], 1_042);
+        assert_eq!(before[&b"imported"[..]], 5);
+
+        db.compact().unwrap();
+        assert_eq!(db.sealed_files.len(), 1);
+        assert_eq!(timestamps(&db), before);
+        let compacted = *db.sealed_files.first().unwrap();
+        let layout = db.layout.clone();
+
+        // Loaded from the hint file, then replayed from the compacted file itself
+        drop(db);
+        let mut db = open();
+        assert_eq!(timestamps(&db), before);
+        assert_eq!(values(&mut db), before_values);
+        drop(db);
+        std::fs::remove_file(layout.hint_path(compacted)).unwrap();
+        let mut db = open();
+        assert_eq!(timestamps(&db), before);
+        assert_eq!(values(&mut db), before_values);
+    }
+
+    #[test]
+    fn test_hashed_keys_tell_colliding_keys_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Bitask::builder()
+            .path(dir.path())
+            .hash_keys(true)
+            .open()
+            .unwrap();
+        db.put(b"key_a".to_vec(), b"a".to_vec()).unwrap();
+
+        // Move key_a to the first slot of key_b, as if both had the same hash
+        let slot = |key: &[u8], n: u8| [&key_hash::digest(key)[..], &[n]].concat();
+        let entry = db.keydir.remove(&slot(b"key_a", 0)).unwrap();
+        db.keydir.insert(slot(b"key_b", 0), entry);
+        assert!(matches!(db.ask(b"key_b"), Err(Error::KeyNotFound)));
+
+        db.put(b"key_b".to_vec(), b"b".to_vec()).unwrap();
+        assert_eq!(db.keydir.len(), 2);
+        assert_eq!(
+            db.keydir[&slot(b"key_b", 0)].value_position,
+            entry.value_position
+        );
+        assert_eq!(db.ask(b"key_b").unwrap(), b"b");
+        db.put(b"key_b".to_vec(), b"b2".to_vec()).unwrap();
+        assert_eq!(db.keydir.len(), 2);
+        assert_eq!(db.ask(b"key_b").unwrap(), b"b2");
+
+        db.remove(b"key_b".to_vec()).unwrap();
+        assert_eq!(db.keydir.len(), 1);
+        assert_eq!(
+            db.keydir[&slot(b"key_b", 0)].value_position,
+            entry.value_position
+        );
+        assert!(matches!(db.ask(b"key_b"), Err(Error::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_put_reuses_scratch_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Bitask::open(dir.path()).unwrap();
+
+        db.put(b"key".to_vec(), vec![1; 100]).unwrap();
+        let capacity = db.scratch.capacity();
+        assert!(capacity >= RecordHeader::SIZE + 103);
+        for i in 0..100 {
+            db.put(format!("key{}", i).into_bytes(), vec![2; 50])
+                .unwrap();
+        }
+        assert_eq!(db.scratch.capacity(), capacity);
+
+        // A large value's buffer isn't kept
+        db.put(b"large".to_vec(), vec![3; 2 * MAX_SCRATCH_CAPACITY])
+            .unwrap();
+        assert!(db.scratch.capacity() <= MAX_SCRATCH_CAPACITY);
+        assert_eq!(db.ask(b"large").unwrap(), vec![3; 2 * MAX_SCRATCH_CAPACITY]);
+        assert_eq!(db.ask(b"key99").unwrap(), vec![2; 50]);
+    }
+
+    #[test]
+    fn test_paranoid_read_catches_stale_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Bitask::builder()
+            .path(dir.path())
+            .paranoid_reads(true)
+            .open()
+            .unwrap();
+        db.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        db.put(b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        assert_eq!(db.ask(b"key1").unwrap(), b"value1");
+
+        db.keydir.get_mut(&b"key1"[..]).unwrap().value_size += 4;
+        assert!(matches!(
+            db.ask(b"key1"),
+            Err(Error::RecordMismatch { position: 0, .. })
+        ));
+
+        db.options.paranoid_reads = false;
+        assert!(matches!(
+            db.ask(b"key1"),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
     #[test]
     fn test_automatic_compaction_disabled() {
         // Create test directory
@@ -0,0 +1,147 @@
+//! Asynchronous handle around a [`Bitask`] database, enabled by the `async` feature.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use tokio::sync::oneshot;
+
+use super::{Bitask, Error, Options};
+
+/// An operation sent to the worker thread, run against the database it owns.
+type Job = Box<dyn FnOnce(&mut Bitask) + Send>;
+
+/// A cloneable handle to a [`Bitask`] database for use from async code.
+///
+/// The database is owned by a dedicated worker thread, so blocking file IO never runs
+/// on the async runtime. Operations are sent to the worker over a channel and run one
+/// at a time in the order they were sent, which keeps the single writer invariant no
+/// matter how many clones of the handle exist. The worker exits, closing the database,
+/// once every clone has been dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # async fn example() -> Result<(), bitask::db::Error> {
+/// use bitask::db::AsyncBitask;
+///
+/// let db = AsyncBitask::open("my_db").await?;
+/// db.put(b"key".to_vec(), b"value".to_vec()).await?;
+/// assert_eq!(db.ask(b"key".to_vec()).await?, b"value");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncBitask {
+    /// Sender feeding the worker thread, shared by every clone
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncBitask {
+    /// Moves an already opened database to a new worker thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the worker thread can't be spawned.
+    pub fn new(db: Bitask) -> Result<Self, Error> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("bitask-worker".to_string())
+            .spawn(move || {
+                let mut db = db;
+                for job in receiver {
+                    job(&mut db);
+                }
+            })?;
+        Ok(Self { jobs })
+    }
+
+    /// Opens a database at `path` on a new worker thread, see [`Bitask::open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`], converted into an [`Error`].
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_with_options(path, Options::default()).await
+    }
+
+    /// Opens a database at `path` with `options` on a new worker thread, see
+    /// [`Bitask::open_with_options`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open_with_options`], converted into an
+    /// [`Error`].
+    pub async fn open_with_options(
+        path: impl AsRef<Path>,
+        options: Options,
+    ) -> Result<Self, Error> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let (reply, opened) = oneshot::channel();
+        thread::Builder::new()
+            .name("bitask-open".to_string())
+            .spawn(move || {
+                let _ = reply.send(Bitask::open_with_options(path, options));
+            })?;
+        let db = opened.await.map_err(|_| Error::WorkerStopped)??;
+        Self::new(db)
+    }
+
+    /// Retrieves the value associated with `key`, see [`Bitask::ask`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], or [`Error::WorkerStopped`] if the
+    /// worker thread panicked.
+    pub async fn ask(&self, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.call(move |db| db.ask(&key)).await
+    }
+
+    /// Stores a key-value pair, see [`Bitask::put`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put`], or [`Error::WorkerStopped`] if the
+    /// worker thread panicked.
+    pub async fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.call(move |db| db.put(key, value)).await
+    }
+
+    /// Removes a key-value pair, see [`Bitask::remove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::remove`], or [`Error::WorkerStopped`] if
+    /// the worker thread panicked.
+    pub async fn remove(&self, key: Vec<u8>) -> Result<(), Error> {
+        self.call(move |db| db.remove(key)).await
+    }
+
+    /// Compacts the database, see [`Bitask::compact`].
+    ///
+    /// Other operations sent through any clone wait until compaction finishes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::compact`], or [`Error::WorkerStopped`] if
+    /// the worker thread panicked.
+    pub async fn compact(&self) -> Result<(), Error> {
+        self.call(Bitask::compact).await
+    }
+
+    /// Runs `operation` on the worker thread and waits for its result.
+    async fn call<T, F>(&self, operation: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Bitask) -> Result<T, Error> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |db| {
+            let _ = reply.send(operation(db));
+        });
+        self.jobs.send(job).map_err(|_| Error::WorkerStopped)?;
+        result.await.map_err(|_| Error::WorkerStopped)?
+    }
+}
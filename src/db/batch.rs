@@ -0,0 +1,319 @@
+//! Groups of writes applied atomically, surviving a crash all or not at all.
+//!
+//! [`Bitask::write_batch`] frames the records of a batch between two marker records,
+//! flagged with [`RecordHeader::BATCH_MARKER_FLAG`]. The begin marker's value holds
+//! the number of records in the batch, the commit marker's value the same count
+//! followed by the group checksum, a CRC32 of the header and key of every record in
+//! the batch. Both are little-endian `u32`s. Replay only applies the records of a
+//! batch once it reads a commit marker matching them.
+
+use super::{
+    check_key_size, check_set, Bitask, ChecksumKind, CommandRemove, CommandSet, Error, KeyDirEntry,
+    RecordHeader,
+};
+
+/// Key of the marker written before the records of a batch.
+const BEGIN_KEY: &[u8] = b"batch.begin";
+
+/// Key of the marker written after the records of a batch.
+const COMMIT_KEY: &[u8] = b"batch.commit";
+
+/// Bytes taken by the begin and commit markers of a batch.
+const MARKERS_SIZE: usize = 2 * RecordHeader::SIZE + BEGIN_KEY.len() + 4 + COMMIT_KEY.len() + 8;
+
+/// A write in a batch passed to [`Bitask::write_batch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Stores a value under a key, as [`Bitask::put`] would
+    Put(Vec<u8>, Vec<u8>),
+    /// Removes a key, as [`Bitask::remove`] would
+    Remove(Vec<u8>),
+}
+
+impl Op {
+    /// Returns the key the operation writes.
+    fn key(&self) -> &[u8] {
+        match self {
+            Op::Put(key, _) | Op::Remove(key) => key,
+        }
+    }
+}
+
+/// A marker record framing a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Marker {
+    /// Opens a batch of `count` records
+    Begin { count: u32 },
+    /// Closes a batch of `count` records whose headers and keys checksum to `crc`
+    Commit { count: u32, crc: u32 },
+}
+
+impl Marker {
+    /// Decodes the marker record with `key` and `value`, or returns `None` if this
+    /// version doesn't know it.
+    pub(super) fn decode(key: &[u8], value: &[u8]) -> Option<Self> {
+        let word = |at: usize| Some(u32::from_le_bytes(value.get(at..at + 4)?.try_into().ok()?));
+        match key {
+            BEGIN_KEY => Some(Self::Begin { count: word(0)? }),
+            COMMIT_KEY => Some(Self::Commit {
+                count: word(0)?,
+                crc: word(4)?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Appends the marker to `buffer` as a record written at `timestamp`.
+    fn encode(
+        &self,
+        timestamp: u64,
+        checksum: ChecksumKind,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let (key, value) = match *self {
+            Self::Begin { count } => (BEGIN_KEY, count.to_le_bytes().to_vec()),
+            Self::Commit { count, crc } => (
+                COMMIT_KEY,
+                [count.to_le_bytes(), crc.to_le_bytes()].concat(),
+            ),
+        };
+        let start = buffer.len();
+        buffer.resize(start + RecordHeader::SIZE, 0);
+        RecordHeader::new(
+            checksum.checksum(key, &value),
+            timestamp,
+            key.len() as u32,
+            checksum.to_flags() | RecordHeader::BATCH_MARKER_FLAG,
+            value.len() as u32,
+        )
+        .serialize(&mut buffer[start..])?;
+        buffer.extend_from_slice(key);
+        buffer.extend_from_slice(&value);
+        Ok(())
+    }
+}
+
+/// The records of a batch read during replay or repair, held back until its commit
+/// marker.
+///
+/// Each record is kept as its key and whatever locates it for the reader, the key
+/// directory entry pointing at it by default.
+#[derive(Debug)]
+pub(super) struct PendingBatch<T = KeyDirEntry> {
+    /// Offset of the begin marker
+    pub(super) start: u64,
+    /// Number of records announced by the begin marker
+    count: u32,
+    /// Group checksum of the records read so far
+    hasher: crc32fast::Hasher,
+    /// Records read so far, with what locates each of them
+    pub(super) records: Vec<(Vec<u8>, T)>,
+}
+
+impl<T> PendingBatch<T> {
+    /// Starts a batch of `count` records whose begin marker is at `start`.
+    pub(super) fn new(start: u64, count: u32) -> Self {
+        Self {
+            start,
+            count,
+            hasher: crc32fast::Hasher::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Returns `true` once every record announced by the begin marker was read.
+    pub(super) fn is_full(&self) -> bool {
+        self.records.len() as u64 >= self.count as u64
+    }
+
+    /// Adds a record of the batch, given its serialized header.
+    pub(super) fn push(&mut self, header: &[u8], key: Vec<u8>, location: T) {
+        self.hasher.update(header);
+        self.hasher.update(&key);
+        self.records.push((key, location));
+    }
+
+    /// Returns `true` if the commit marker `count` and `crc` match the records read.
+    pub(super) fn matches(&self, count: u32, crc: u32) -> bool {
+        self.records.len() as u64 == count as u64 && self.hasher.clone().finalize() == crc
+    }
+}
+
+impl Bitask {
+    /// Applies several writes atomically.
+    ///
+    /// Every operation is validated before anything is written, so an invalid one
+    /// rejects the whole batch. The records are then appended to the active file
+    /// between a begin and a commit marker, as one write flushed once. After a crash,
+    /// opening the database applies the batch only if its commit marker made it to
+    /// disk intact, so either every write of the batch is visible or none is. Within
+    /// a batch, later operations on a key win over earlier ones.
+    ///
+    /// Batches are atomic, not isolated: there's a single writer, so no other write
+    /// can interleave with them anyway. The markers take 75 bytes per batch. Older
+    /// versions of bitask don't know them and read them as records of a regular key.
+    ///
+    /// # Parameters
+    ///
+    /// * `ops` - The writes to apply, in order
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * A key is empty ([`Error::InvalidEmptyKey`])
+    /// * A key is longer than [`MAX_KEY_SIZE`](super::MAX_KEY_SIZE) ([`Error::KeyTooLarge`])
+    /// * A value is empty while [`Options::allow_empty_values`](super::Options::allow_empty_values)
+    ///   is off ([`Error::InvalidEmptyValue`])
+    /// * The batch doesn't fit under [`Options::max_total_size`](super::Options::max_total_size)
+    ///   ([`Error::StorageFull`])
+    /// * IO operations fail ([`Error::Io`]), in which case none of the writes are applied
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::Op;
+    ///
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.write_batch(vec![
+    ///     Op::Put(b"from".to_vec(), b"90".to_vec()),
+    ///     Op::Put(b"to".to_vec(), b"110".to_vec()),
+    ///     Op::Remove(b"pending".to_vec()),
+    /// ])?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn write_batch(&mut self, ops: Vec<Op>) -> Result<(), Error> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        for op in &ops {
+            match op {
+                Op::Put(key, value) => check_set(key, value, self.options.allow_empty_values)?,
+                Op::Remove(key) if key.is_empty() => return Err(Error::InvalidEmptyKey),
+                Op::Remove(key) => check_key_size(key.len())?,
+            }
+        }
+        let slots = self.slots_of(ops.iter().map(Op::key))?;
+
+        // Serialize the records first, to size the batch before rotating
+        let timestamp = self.options.clock.now()?;
+        let checksum = self.options.checksum;
+        let mut records = Vec::new();
+        let mut group = crc32fast::Hasher::new();
+        // Key, offset of the value in `records` and stored value size of each record
+        let mut written = Vec::with_capacity(ops.len());
+        for op in ops {
+            let start = records.len();
+            let (key, value_size) = match op {
+                Op::Put(key, value) => {
+                    let command = CommandSet::with_timestamp(
+                        key,
+                        value,
+                        timestamp,
+                        checksum,
+                        self.options.compression,
+                    )?;
+                    records.resize(
+                        start + RecordHeader::SIZE + command.key.len() + command.value.len(),
+                        0,
+                    );
+                    command.serialize(&mut records[start..])?;
+                    (command.key, Some(command.value.len() as u32))
+                }
+                Op::Remove(key) => {
+                    let command = CommandRemove {
+                        crc: checksum.checksum(&key, &[]),
+                        flags: checksum.to_flags(),
+                        timestamp,
+                        key,
+                    };
+                    records.resize(start + RecordHeader::SIZE + command.key.len(), 0);
+                    command.serialize(&mut records[start..])?;
+                    (command.key, None)
+                }
+            };
+            let value_offset = start + RecordHeader::SIZE + key.len();
+            group.update(&records[start..value_offset]);
+            written.push((key, value_offset as u64, value_size));
+        }
+
+        let count = written.len() as u32;
+        let mut buffer = Vec::with_capacity(records.len() + MARKERS_SIZE);
+        Marker::Begin { count }.encode(timestamp, checksum, &mut buffer)?;
+        let records_start = buffer.len() as u64;
+        buffer.extend_from_slice(&records);
+        Marker::Commit {
+            count,
+            crc: group.finalize(),
+        }
+        .encode(timestamp, checksum, &mut buffer)?;
+
+        // The whole batch goes to the same file, rotating beforehand if needed
+        self.reserve_space(buffer.len() as u64)?;
+        self.rotate_before(buffer.len() as u64)?;
+
+        let position = self.write_position;
+        self.write_active(&buffer)?;
+        self.write_position += buffer.len() as u64;
+        self.flush_from(position)?;
+        self.metrics.bytes_written.add(buffer.len() as u64);
+
+        for ((key, value_offset, value_size), slot) in written.into_iter().zip(slots) {
+            match value_size {
+                Some(value_size) => {
+                    self.metrics.puts.add(1);
+                    self.access.touch(&key);
+                    self.keydir.insert(
+                        slot.unwrap_or(key),
+                        KeyDirEntry {
+                            file_id: self.writer_id,
+                            value_size,
+                            value_position: position + records_start + value_offset,
+                            timestamp,
+                            tombstone: false,
+                        },
+                    );
+                }
+                None => {
+                    self.metrics.removes.add(1);
+                    self.access.forget(&key);
+                    self.keydir.remove(slot.as_ref().unwrap_or(&key));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the value of `from` to `to`, atomically.
+    ///
+    /// The value is written under `to` and `from` removed in one
+    /// [`Bitask::write_batch`], so after a crash the value is under exactly one of the
+    /// two keys. An existing value under `to` is overwritten. Renaming a key to itself
+    /// only checks that it exists.
+    ///
+    /// # Parameters
+    ///
+    /// * `from` - The key to move the value from
+    /// * `to` - The key to move the value to
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * `from` doesn't exist ([`Error::KeyNotFound`])
+    /// * Reading the value fails, see [`Bitask::ask`]
+    /// * Writing the batch fails, see [`Bitask::write_batch`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// db.rename(b"draft:42", b"published:42".to_vec())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn rename(&mut self, from: &[u8], to: Vec<u8>) -> Result<(), Error> {
+        let value = self.ask(from)?;
+        if from == to.as_slice() {
+            return Ok(());
+        }
+        self.write_batch(vec![Op::Put(to, value), Op::Remove(from.to_vec())])
+    }
+}
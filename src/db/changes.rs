@@ -0,0 +1,182 @@
+//! Streams of the records written to a database, for change data capture and
+//! auditing.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::{read_record, Bitask, Error, KeyDirEntry, LogReader, RecordHeader};
+
+/// Iterator over the records written after a timestamp, file by file.
+///
+/// Created by [`Bitask::records_since`](super::Bitask::records_since). Yields every
+/// set as `(key, Some(value))` and every removal as `(key, None)`. Each file is
+/// scanned once the records of the previous one are yielded, and values are read
+/// lazily, so each item fails for the same reasons as
+/// [`Bitask::ask`](super::Bitask::ask) or a scan of the file.
+#[derive(Debug)]
+pub struct RecordsSince {
+    /// Only records with a greater timestamp are yielded
+    after_timestamp: u64,
+    /// Files left to scan, in the order they're yielded
+    files: VecDeque<(u64, LogReader)>,
+    /// Reader over the file being yielded
+    reader: Option<LogReader>,
+    /// Records of that file left to yield, in reverse order so the next one is
+    /// popped from the end
+    records: Vec<(Vec<u8>, KeyDirEntry)>,
+    /// Whether reads verify the stored header and key
+    paranoid: bool,
+}
+
+impl RecordsSince {
+    /// Creates an iterator over the records of `files`, readers at their start.
+    pub(super) fn new(after_timestamp: u64, files: Vec<(u64, LogReader)>, paranoid: bool) -> Self {
+        Self {
+            after_timestamp,
+            files: files.into(),
+            reader: None,
+            records: Vec::new(),
+            paranoid,
+        }
+    }
+
+    /// Scans the next file into `records`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` once every file was scanned.
+    fn scan_next_file(&mut self) -> Result<bool, Error> {
+        let Some((file_id, mut reader)) = self.files.pop_front() else {
+            return Ok(false);
+        };
+        let after_timestamp = self.after_timestamp;
+        let mut records = Vec::new();
+        Bitask::scan_log(&mut reader, file_id, |key, entry| {
+            if entry.timestamp > after_timestamp {
+                records.push((key, entry));
+            }
+        })?;
+
+        // Sorting is stable, so equal timestamps keep the order they were scanned in
+        records.sort_by_key(|(_, entry)| entry.timestamp);
+        records.reverse();
+        self.records = records;
+        self.reader = Some(reader);
+        Ok(true)
+    }
+}
+
+impl Iterator for RecordsSince {
+    type Item = Result<(Vec<u8>, Option<Vec<u8>>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.records.is_empty() {
+            match self.scan_next_file() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let (key, entry) = self.records.pop()?;
+        if entry.tombstone {
+            return Some(Ok((key, None)));
+        }
+
+        let value = match &mut self.reader {
+            Some(reader) => read_record(reader, &key, &entry, self.paranoid),
+            None => Err(Error::FileNotFound(format!("{}", entry.file_id))),
+        };
+        Some(value.map(|value| (key, Some(value))))
+    }
+}
+
+/// A record of the raw command stream, yielded by [`EventLog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    /// Identifier (timestamp) of the data file holding the record
+    pub file_id: u64,
+    /// Offset of the record header within the file
+    pub offset: u64,
+    /// Timestamp stored in the record (milliseconds since UNIX epoch)
+    pub timestamp: u64,
+    /// The key written or removed
+    pub key: Vec<u8>,
+    /// The value set, `None` for a removal
+    pub value: Option<Vec<u8>>,
+    /// Timestamp of the previous record of the same key in the stream, the one this
+    /// record overwrites or removes. `None` for the first record of the key still on
+    /// disk.
+    pub previous: Option<u64>,
+}
+
+/// Iterator over every record on disk in physical order, file by file and offset by
+/// offset.
+///
+/// Created by [`Bitask::event_log`](super::Bitask::event_log). Values are read lazily,
+/// so each item fails for the same reasons as [`Bitask::ask`](super::Bitask::ask).
+#[derive(Debug)]
+pub struct EventLog {
+    /// Records left to yield with the timestamp of the record each one follows, in
+    /// reverse order so the next one is popped from the end
+    records: Vec<(Vec<u8>, KeyDirEntry, Option<u64>)>,
+    /// Readers over every file holding a remaining record
+    readers: HashMap<u64, LogReader>,
+    /// Whether reads verify the stored header and key
+    paranoid: bool,
+}
+
+impl EventLog {
+    /// Creates an iterator over `records`, already in physical order.
+    pub(super) fn new(
+        records: Vec<(Vec<u8>, KeyDirEntry)>,
+        readers: HashMap<u64, LogReader>,
+        paranoid: bool,
+    ) -> Self {
+        let mut last_seen: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut records: Vec<_> = records
+            .into_iter()
+            .map(|(key, entry)| {
+                let previous = last_seen.insert(key.clone(), entry.timestamp);
+                (key, entry, previous)
+            })
+            .collect();
+        records.reverse();
+        Self {
+            records,
+            readers,
+            paranoid,
+        }
+    }
+}
+
+impl Iterator for EventLog {
+    type Item = Result<LogEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry, previous) = self.records.pop()?;
+        let value = if entry.tombstone {
+            None
+        } else {
+            match self.readers.get_mut(&entry.file_id) {
+                Some(reader) => match read_record(reader, &key, &entry, self.paranoid) {
+                    Ok(value) => Some(value),
+                    Err(e) => return Some(Err(e)),
+                },
+                None => return Some(Err(Error::FileNotFound(format!("{}", entry.file_id)))),
+            }
+        };
+
+        Some(Ok(LogEvent {
+            file_id: entry.file_id,
+            offset: entry.value_position - key.len() as u64 - RecordHeader::SIZE as u64,
+            timestamp: entry.timestamp,
+            key,
+            value,
+            previous,
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.records.len(), Some(self.records.len()))
+    }
+}
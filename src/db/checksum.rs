@@ -0,0 +1,66 @@
+//! Checksum algorithms used to verify records.
+
+/// Algorithm used to checksum the key and value of each record.
+///
+/// The algorithm is recorded in the flags of every record header, so files written
+/// with different algorithms can be read by any instance regardless of its options.
+/// Data files have no header of their own to record it once per file: records copied
+/// by compaction keep their checksum, so a single file can mix algorithms, and files
+/// written before the choice existed read as CRC32 since their flags are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumKind {
+    /// CRC32 (IEEE), the original format
+    #[default]
+    Crc32,
+    /// CRC32C (Castagnoli), hardware accelerated on modern x86 and ARM CPUs
+    Crc32c,
+    /// 32-bit xxHash, fast in software for large values
+    XxHash32,
+}
+
+impl ChecksumKind {
+    /// Bits of the record flags holding the checksum kind.
+    pub(crate) const FLAGS_MASK: u8 = 0b0000_0011;
+
+    /// Decodes the checksum kind from record header flags.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the flags hold an unknown algorithm.
+    pub(crate) fn from_flags(flags: u8) -> Option<Self> {
+        match flags & Self::FLAGS_MASK {
+            0 => Some(Self::Crc32),
+            1 => Some(Self::Crc32c),
+            2 => Some(Self::XxHash32),
+            _ => None,
+        }
+    }
+
+    /// Encodes the checksum kind into record header flags.
+    pub(crate) fn to_flags(self) -> u8 {
+        match self {
+            Self::Crc32 => 0,
+            Self::Crc32c => 1,
+            Self::XxHash32 => 2,
+        }
+    }
+
+    /// Computes the checksum of a record's key followed by its value.
+    pub(crate) fn checksum(self, key: &[u8], value: &[u8]) -> u32 {
+        match self {
+            Self::Crc32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(key);
+                hasher.update(value);
+                hasher.finalize()
+            }
+            Self::Crc32c => crc32c::crc32c_append(crc32c::crc32c(key), value),
+            Self::XxHash32 => {
+                let mut hasher = xxhash_rust::xxh32::Xxh32::new(0);
+                hasher.update(key);
+                hasher.update(value);
+                hasher.digest()
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Time sources for record timestamps and file IDs.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::Error;
+
+/// Source of the current time used by a database.
+///
+/// Every record timestamp and new file ID is read from the clock configured in
+/// [`Options::clock`](super::Options::clock), so tests can control them.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time in milliseconds since UNIX epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if the time can't be read or doesn't fit in a [`u64`].
+    fn now(&self) -> Result<u64, Error>;
+}
+
+/// The system wall clock, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    /// Returns the system time in milliseconds since UNIX epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * System time operations fail ([`Error::TimestampError`])
+    /// * Milliseconds value doesn't fit in [`u64`] ([`Error::TimestampOverflow`])
+    fn now(&self) -> Result<u64, Error> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(Error::TimestampError)?
+            .as_millis()
+            .try_into()
+            .map_err(Error::TimestampOverflow)
+    }
+}
+
+/// A manually driven clock for deterministic tests.
+///
+/// Clones share the same time, so a test can keep one to move the time of the
+/// database it was passed to.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bitask::db::{Bitask, MockClock};
+///
+/// let clock = MockClock::new(1_000);
+/// let mut db = Bitask::builder().path("my_db").clock(clock.clone()).open()?;
+/// db.put(b"key".to_vec(), b"old".to_vec())?;
+/// clock.advance(10);
+/// db.put(b"key".to_vec(), b"new".to_vec())?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    /// Current time in milliseconds since UNIX epoch, shared by every clone
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at `now` milliseconds since UNIX epoch.
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Sets the current time to `now` milliseconds since UNIX epoch.
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Moves the current time forward by `millis` milliseconds.
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Result<u64, Error> {
+        Ok(self.now.load(Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,287 @@
+//! Compaction running next to writes, see [`Bitask::begin_compaction`].
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{
+    copy_records, hint, key_hash, layout::Layout, manifest::Manifest, snapshot::FilePins, Bitask,
+    CompactionStats, Error, KeyDirEntry, RecordHeader, Storage,
+};
+
+/// A merge of the sealed files that lets writes go on while it copies their entries.
+///
+/// [`Bitask::begin_compaction`] freezes the sealed files and the key directory entries
+/// pointing into them. [`Compaction::run`] copies those entries into a new file
+/// without the database, so it can keep serving reads and writes meanwhile, from
+/// another thread for instance. [`Bitask::finish_compaction`] then points the
+/// entries that weren't overwritten or removed in the meantime at the new file and
+/// removes the merged ones, the only part holding up the database.
+///
+/// The frozen entries are a copy of part of the key directory, which takes memory
+/// until the compaction is finished or dropped. Dropping it unfinished removes the new
+/// file and leaves the database as it was.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// if let Some(mut compaction) = db.begin_compaction()? {
+///     db.put(b"key".to_vec(), b"written meanwhile".to_vec())?;
+///     compaction.run()?;
+///     db.finish_compaction(compaction)?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Compaction {
+    /// Sealed files being merged
+    file_ids: BTreeSet<u64>,
+    /// ID of the new file
+    file_id: u64,
+    /// Key directory entries pointing into `file_ids` when the compaction began, in
+    /// key order
+    entries: Vec<(Vec<u8>, KeyDirEntry)>,
+    /// Whether the keys of `entries` are slots, see [`Options::hash_keys`](super::Options::hash_keys)
+    hashed: bool,
+    /// Total size of the files being merged
+    merged_bytes: u64,
+    /// Whether [`Compaction::run`] wrote the new file
+    copied: bool,
+    /// Whether the new file became a sealed file of the database
+    finished: bool,
+    /// Storage holding the database files
+    storage: Arc<dyn Storage>,
+    /// Paths of the database files
+    layout: Layout,
+    /// Pins of the database, keeping the merged files on disk while they're read
+    pins: FilePins,
+    /// Flag of the database telling it a compaction is pending
+    compacting: Arc<AtomicBool>,
+}
+
+impl Compaction {
+    /// Copies the frozen entries into the new file.
+    ///
+    /// Doesn't touch the database, so it can run while the database serves other
+    /// operations. Calling it again once it succeeded does nothing, and
+    /// [`Bitask::finish_compaction`] calls it if it wasn't.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * A frozen record doesn't match its entry ([`Error::CorruptedEntry`])
+    /// * Reading or writing files fails ([`Error::Io`])
+    pub fn run(&mut self) -> Result<(), Error> {
+        if self.copied {
+            return Ok(());
+        }
+
+        let file = self
+            .storage
+            .create(&self.layout.compacting_path(self.file_id))?;
+        file.set_len(0)?;
+        let mut writer = BufWriter::new(file);
+        let entries = self.entries.iter().map(|(key, entry)| (key, entry));
+        let path = |file_id| self.layout.log_path(file_id);
+        copy_records(
+            &*self.storage,
+            path,
+            self.hashed,
+            entries,
+            &mut writer,
+            |_| (),
+        )?;
+        writer.flush()?;
+        writer.get_ref().sync()?;
+        self.copied = true;
+        Ok(())
+    }
+}
+
+impl Drop for Compaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            match self
+                .storage
+                .remove(&self.layout.compacting_path(self.file_id))
+            {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    log::warn!("Failed to remove compaction file {}: {}", self.file_id, e)
+                }
+                _ => (),
+            }
+        }
+        self.pins.unpin(self.file_ids.iter().copied());
+        self.compacting.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Bitask {
+    /// Begins merging the sealed files while writes go on, see [`Compaction`].
+    ///
+    /// Freezes the sealed files and the entries pointing into them, and seals the
+    /// active file, even an empty one, so every write made from now on lands in files
+    /// newer than the merged one. Until the compaction is finished or dropped, [`Bitask::compact`]
+    /// and auto-compaction are skipped, and the other merges fail with
+    /// [`Error::CompactionPending`], as dropping the tombstones of keys removed
+    /// meanwhile would bring back their frozen values.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`Compaction`] to run and finish, or `None` if there are fewer than
+    /// two sealed files, like [`Bitask::compact`], or another compaction is pending.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * Reading the system time fails ([`Error::TimestampError`])
+    /// * Creating the new file or rotating the active file fails ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let compaction = db.begin_compaction()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn begin_compaction(&mut self) -> Result<Option<Compaction>, Error> {
+        if self.sealed_files.len() < 2 || self.compacting.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        let storage = self.options.storage.clone();
+        let file_ids = self.sealed_files.clone();
+        let mut merged_bytes = 0;
+        for &file_id in &file_ids {
+            merged_bytes += storage.size(&self.layout.log_path(file_id))?;
+        }
+        let entries = self
+            .keydir
+            .iter()
+            .filter(|(_, entry)| file_ids.contains(&entry.file_id))
+            .map(|(key, entry)| (key.clone(), *entry))
+            .collect();
+
+        // Creating the new file reserves its ID, and the manifest stays away until the
+        // compaction is finished
+        let file_id = self.next_file_id()?;
+        storage.create(&self.layout.compacting_path(file_id))?;
+        self.compacting.store(true, Ordering::SeqCst);
+        self.pins.pin(file_ids.iter().copied());
+        let compaction = Compaction {
+            file_ids,
+            file_id,
+            entries,
+            hashed: self.options.hash_keys,
+            merged_bytes,
+            copied: false,
+            finished: false,
+            storage: storage.clone(),
+            layout: self.layout.clone(),
+            pins: self.pins.clone(),
+            compacting: self.compacting.clone(),
+        };
+        Manifest::remove(&*storage, &self.layout)?;
+
+        // Replay reads the new file before the ones written from now on, so a value
+        // written meanwhile wins over the copy of the value it replaces, even with the
+        // same timestamp. An empty active file is sealed too, as its ID is older.
+        self.rotate_active_file()?;
+        log::debug!(
+            "Began compaction of {} files into file {}",
+            compaction.file_ids.len(),
+            file_id
+        );
+        Ok(Some(compaction))
+    }
+
+    /// Finishes `compaction`, running it first if it wasn't.
+    ///
+    /// The frozen entries still pointing where they did when the compaction began are
+    /// pointed at their copies in the new file. Those overwritten or removed in the
+    /// meantime keep their newer location, their copies are dead and dropped by the
+    /// next compaction. The merged files are then removed, keeping those a snapshot
+    /// still references, like [`Bitask::compact`] does.
+    ///
+    /// # Parameters
+    ///
+    /// * `compaction` - A compaction begun by [`Bitask::begin_compaction`] on this
+    ///   database
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`CompactionStats`] of the merge, counting every frozen entry as
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Compaction::run`], or [`Error::Io`] if sealing the
+    /// new file or removing the merged ones fails.
+    pub fn finish_compaction(
+        &mut self,
+        mut compaction: Compaction,
+    ) -> Result<CompactionStats, Error> {
+        compaction.run()?;
+        let storage = self.options.storage.clone();
+        let file_id = compaction.file_id;
+        storage.rename(
+            &self.layout.compacting_path(file_id),
+            &self.layout.log_path(file_id),
+        )?;
+        self.sync_dir(self.layout.data_dir())?;
+        compaction.finished = true;
+        self.sealed_files.insert(file_id);
+
+        // Entries were copied in order, so their positions follow from their sizes
+        let mut position = 0;
+        for (key, frozen) in &compaction.entries {
+            let key_len = key_hash::key_len(key, compaction.hashed) as u64;
+            let value_position = position + RecordHeader::SIZE as u64 + key_len;
+            position = value_position + frozen.value_size as u64;
+            match self.keydir.get_mut(key) {
+                Some(entry)
+                    if entry.file_id == frozen.file_id
+                        && entry.value_position == frozen.value_position =>
+                {
+                    entry.file_id = file_id;
+                    entry.value_position = value_position;
+                }
+                _ => (),
+            }
+        }
+        self.sealed_size += position;
+        if !self.options.hash_keys {
+            hint::write(&*storage, &self.layout, file_id, position, &self.keydir)?;
+        }
+
+        // Only files still sealed are retired, in case one was dropped meanwhile
+        let file_ids: BTreeSet<u64> = compaction
+            .file_ids
+            .intersection(&self.sealed_files)
+            .copied()
+            .collect();
+        let stats = CompactionStats {
+            files_compacted: compaction.file_ids.len(),
+            entries_written: compaction.entries.len(),
+            bytes_written: position,
+            bytes_reclaimed: compaction.merged_bytes.saturating_sub(position),
+        };
+        drop(compaction);
+        let removed_files = self.retire_files(&file_ids)?;
+        self.metrics.compactions.add(1);
+
+        log::debug!(
+            "Finished compaction into file {}: {} bytes written, {} files removed",
+            file_id,
+            position,
+            removed_files
+        );
+        Ok(stats)
+    }
+}
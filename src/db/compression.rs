@@ -0,0 +1,91 @@
+//! Compression codecs applied to stored values.
+
+use super::Error;
+
+/// Codec used to compress values before they are written.
+///
+/// Like the checksum algorithm, the codec is recorded in the flags of every record
+/// header, so records written with different settings can be mixed in one database.
+/// Each codec needs its cargo feature, `lz4` or `zstd`: writing or reading a value
+/// with a codec that wasn't compiled in fails with [`Error::UnsupportedCompression`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionKind {
+    /// LZ4, very fast with a moderate ratio. Requires the `lz4` feature.
+    Lz4,
+    /// Zstandard, slower with a better ratio. Requires the `zstd` feature.
+    Zstd,
+}
+
+impl CompressionKind {
+    /// Bits of the record flags holding the compression codec.
+    pub(crate) const FLAGS_MASK: u8 = 0b0000_1100;
+
+    /// Decodes the compression codec from record header flags.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the value is stored uncompressed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCompression`] if the flags hold an unknown codec.
+    pub(crate) fn from_flags(flags: u8) -> Result<Option<Self>, Error> {
+        match (flags & Self::FLAGS_MASK) >> 2 {
+            0 => Ok(None),
+            1 => Ok(Some(Self::Lz4)),
+            2 => Ok(Some(Self::Zstd)),
+            _ => Err(Error::UnsupportedCompression { flags }),
+        }
+    }
+
+    /// Encodes the compression codec into record header flags.
+    pub(crate) fn to_flags(self) -> u8 {
+        match self {
+            Self::Lz4 => 1 << 2,
+            Self::Zstd => 2 << 2,
+        }
+    }
+
+    /// Compresses a value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The codec's feature isn't enabled ([`Error::UnsupportedCompression`])
+    /// * The codec fails ([`Error::Io`])
+    #[cfg_attr(not(all(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn compress(self, value: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(value)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::encode_all(value, 0)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::UnsupportedCompression {
+                flags: self.to_flags(),
+            }),
+        }
+    }
+
+    /// Decompresses a stored value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The codec's feature isn't enabled ([`Error::UnsupportedCompression`])
+    /// * The stored bytes aren't valid for the codec ([`Error::Io`])
+    #[cfg_attr(not(all(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, stored: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4_flex::decompress_size_prepended(stored)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::decode_all(stored)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::UnsupportedCompression {
+                flags: self.to_flags(),
+            }),
+        }
+    }
+}
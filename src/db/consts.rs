@@ -0,0 +1,29 @@
+//! Limits and names of the on-disk format, for tools reading databases without going
+//! through [`Bitask`](super::Bitask).
+
+use super::RecordHeader;
+
+/// Maximum size of active log file before rotation, the default of
+/// [`Options::max_file_size`](super::Options::max_file_size).
+///
+/// 4MB, unless the `BITASK_MAX_ACTIVE_FILE_SIZE` environment variable held another
+/// number of bytes when the crate was built.
+pub const MAX_ACTIVE_FILE_SIZE: u64 =
+    include!(concat!(env!("OUT_DIR"), "/max_active_file_size.rs"));
+
+/// Maximum key size in bytes. The top byte of the key length field holds record flags.
+pub const MAX_KEY_SIZE: usize = 0x00FF_FFFF;
+
+/// Maximum value size in bytes, the largest length the value size field can hold.
+pub const MAX_VALUE_SIZE: usize = u32::MAX as usize;
+
+/// Size in bytes of the header written before every record, see [`RecordHeader`].
+pub const RECORD_HEADER_SIZE: usize = RecordHeader::SIZE;
+
+/// Name of the lock file, held by the writer to ensure only one writer at a time.
+/// Databases opened with a name prefix it with `<name>.`.
+pub const LOCK_FILE_NAME: &str = "db.lock";
+
+/// Name of the manifest file listing the data files. Databases opened with a name
+/// prefix it with `<name>.`.
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
@@ -0,0 +1,182 @@
+//! Read-modify-write access to a single key, in the style of [`HashMap::entry`].
+//!
+//! [`HashMap::entry`]: std::collections::HashMap::entry
+
+use super::{Bitask, Error};
+
+/// A view into a single key of a database, either present or missing.
+///
+/// Created by [`Bitask::entry`]. A [`Bitask`] instance is the single writer for its
+/// directory, so nothing can change the key between reading it through the entry and
+/// writing it back.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// // Count visits, stored as a little-endian u64
+/// db.entry(b"visits".to_vec())
+///     .and_modify(|value| {
+///         let count = u64::from_le_bytes(value.as_slice().try_into().unwrap());
+///         *value = (count + 1).to_le_bytes().to_vec();
+///     })?
+///     .or_insert(1u64.to_le_bytes().to_vec())?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub enum Entry<'a> {
+    /// The key is present
+    Occupied(OccupiedEntry<'a>),
+    /// The key is missing
+    Vacant(VacantEntry<'a>),
+}
+
+impl<'a> Entry<'a> {
+    /// Creates the entry of `key` in `db`.
+    pub(super) fn new(db: &'a mut Bitask, key: Vec<u8>) -> Self {
+        // A failed lookup counts as present, so reading the value reports the error
+        if !matches!(db.lookup(&key), Ok(None)) {
+            Entry::Occupied(OccupiedEntry {
+                db,
+                key,
+                value: None,
+            })
+        } else {
+            Entry::Vacant(VacantEntry { db, key })
+        }
+    }
+
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Reads the current value, lets `f` change it and writes it back, if the key is
+    /// present. Does nothing for a missing key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if reading the value fails, see [`Bitask::ask`], or writing
+    /// the modified value fails, see [`Bitask::put`].
+    pub fn and_modify<F: FnOnce(&mut Vec<u8>)>(self, f: F) -> Result<Self, Error> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                let mut value = entry.get()?;
+                f(&mut value);
+                entry.insert(value)?;
+                Ok(Entry::Occupied(entry))
+            }
+            Entry::Vacant(entry) => Ok(Entry::Vacant(entry)),
+        }
+    }
+
+    /// Returns the current value, storing `default` first if the key is missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if reading the value fails, see [`Bitask::ask`], or storing
+    /// `default` fails, see [`Bitask::put`].
+    pub fn or_insert(self, default: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the current value, storing the result of `f` first if the key is missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Entry::or_insert`].
+    pub fn or_insert_with<F: FnOnce() -> Vec<u8>>(self, f: F) -> Result<Vec<u8>, Error> {
+        match self {
+            Entry::Occupied(mut entry) => entry.get(),
+            Entry::Vacant(entry) => {
+                let value = f();
+                entry.insert(value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// A key present in the database, part of an [`Entry`].
+#[derive(Debug)]
+pub struct OccupiedEntry<'a> {
+    /// Database holding the key
+    db: &'a mut Bitask,
+    /// The key
+    key: Vec<u8>,
+    /// Value last read or written through this entry, to avoid reading it again
+    value: Option<Vec<u8>>,
+}
+
+impl OccupiedEntry<'_> {
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Returns the current value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`].
+    pub fn get(&mut self) -> Result<Vec<u8>, Error> {
+        if let Some(value) = &self.value {
+            return Ok(value.clone());
+        }
+
+        let value = self.db.ask(&self.key)?;
+        self.value = Some(value.clone());
+        Ok(value)
+    }
+
+    /// Replaces the value, returning the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`] and [`Bitask::put`].
+    pub fn insert(&mut self, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let previous = self.get()?;
+        self.db.put(self.key.clone(), value.clone())?;
+        self.value = Some(value);
+        Ok(previous)
+    }
+
+    /// Removes the key, returning its value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`] and [`Bitask::remove`].
+    pub fn remove(mut self) -> Result<Vec<u8>, Error> {
+        let value = self.get()?;
+        self.db.remove(self.key)?;
+        Ok(value)
+    }
+}
+
+/// A key missing from the database, part of an [`Entry`].
+#[derive(Debug)]
+pub struct VacantEntry<'a> {
+    /// Database the key is missing from
+    db: &'a mut Bitask,
+    /// The key
+    key: Vec<u8>,
+}
+
+impl VacantEntry<'_> {
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Stores `value` under the key.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put`].
+    pub fn insert(self, value: Vec<u8>) -> Result<(), Error> {
+        self.db.put(self.key, value)
+    }
+}
@@ -0,0 +1,75 @@
+//! Access tracking used to pick the keys evicted from a database at its size limit.
+
+use std::collections::HashMap;
+
+use super::{key_hash, EvictionPolicy};
+
+/// Order in which keys were last read or written, kept only for
+/// [`EvictionPolicy::LeastRecentlyUsed`].
+#[derive(Debug, Default)]
+pub(super) struct AccessOrder {
+    /// Tick of the last access of every key accessed since the database was opened
+    ticks: HashMap<Vec<u8>, u64>,
+    /// Tick given to the next access, starting at 1 so untouched keys sort first
+    next: u64,
+    /// Whether accesses are recorded
+    enabled: bool,
+    /// Whether keys are hashed, see [`Options::hash_keys`](super::Options::hash_keys).
+    /// Accesses are then tracked by key hash, and shared by keys with the same hash.
+    hashed: bool,
+}
+
+impl AccessOrder {
+    /// Creates an empty order, recording accesses only if `policy` needs them.
+    pub(super) fn new(policy: EvictionPolicy, hashed: bool) -> Self {
+        Self {
+            ticks: HashMap::new(),
+            next: 1,
+            enabled: policy == EvictionPolicy::LeastRecentlyUsed,
+            hashed,
+        }
+    }
+
+    /// Records an access to `key`, making it the most recently used.
+    pub(super) fn touch(&mut self, key: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+
+        let digest;
+        let key = if self.hashed {
+            digest = key_hash::digest(key);
+            &digest[..]
+        } else {
+            key
+        };
+        let tick = self.next;
+        self.next += 1;
+        match self.ticks.get_mut(key) {
+            Some(last) => *last = tick,
+            None => {
+                self.ticks.insert(key.to_vec(), tick);
+            }
+        }
+    }
+
+    /// Forgets the accesses to a removed key.
+    pub(super) fn forget(&mut self, key: &[u8]) {
+        if self.hashed {
+            self.ticks.remove(&key_hash::digest(key)[..]);
+        } else {
+            self.ticks.remove(key);
+        }
+    }
+
+    /// Returns the tick of the last access to the key indexed under `keydir_key`, `0`
+    /// if it wasn't accessed since the database was opened or accesses aren't
+    /// recorded.
+    pub(super) fn last_access(&self, keydir_key: &[u8]) -> u64 {
+        let key = match self.hashed {
+            true => &keydir_key[..key_hash::DIGEST_SIZE],
+            false => keydir_key,
+        };
+        self.ticks.get(key).copied().unwrap_or(0)
+    }
+}
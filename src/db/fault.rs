@@ -0,0 +1,65 @@
+//! Fault injection for the active file writer, so tests can simulate torn writes and
+//! crashes deterministically.
+
+use std::io::{self, Write};
+
+/// Lets a fixed number of bytes through, then fails every write.
+///
+/// The write crossing the limit is torn: the bytes before the limit are flushed to
+/// the file, as if the process had crashed in the middle of writing the record.
+#[derive(Debug)]
+pub(super) struct FailAfter {
+    /// Bytes that can still be written before failing
+    remaining: usize,
+    /// Whether flushes fail once the buffered bytes reached the file
+    fail_flush: bool,
+}
+
+impl FailAfter {
+    /// Creates a fault letting `bytes` bytes through.
+    pub(super) fn new(bytes: usize) -> Self {
+        Self {
+            remaining: bytes,
+            fail_flush: false,
+        }
+    }
+
+    /// Creates a fault letting every write through but failing every flush, after
+    /// the buffered bytes reached the file, as when syncing to the disk fails.
+    pub(super) fn flush() -> Self {
+        Self {
+            remaining: usize::MAX,
+            fail_flush: true,
+        }
+    }
+
+    /// Writes `buf` to `writer`, or the part of it that fits before the limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the limit is reached or writing fails.
+    pub(super) fn write_all<W: Write>(&mut self, writer: &mut W, buf: &[u8]) -> io::Result<()> {
+        if buf.len() <= self.remaining {
+            self.remaining -= buf.len();
+            return writer.write_all(buf);
+        }
+
+        writer.write_all(&buf[..self.remaining])?;
+        writer.flush()?;
+        self.remaining = 0;
+        Err(io::Error::other("injected fault: torn write"))
+    }
+
+    /// Flushes `writer`, then fails if the fault fails flushes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the fault fails flushes or flushing fails.
+    pub(super) fn flush_all<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.flush()?;
+        if self.fail_flush {
+            return Err(io::Error::other("injected fault: failed flush"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Hint files listing the records of a compacted file, so opening the database can
+//! rebuild the key directory without reading every value.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufWriter, Write},
+};
+
+use super::{layout::Layout, Error, KeyDirEntry, Storage};
+
+/// Size of the hint file header: length of the data file and number of entries
+const HEADER_SIZE: usize = 16;
+
+/// Size of the fixed part of an entry: timestamp, key length, value size and value
+/// position, followed by the key bytes
+const ENTRY_SIZE: usize = 24;
+
+/// Key directory entries read from a hint file, in key order
+type HintEntries = Vec<(Vec<u8>, KeyDirEntry)>;
+
+/// Writes the hint file of data file `file_id`, listing every key directory entry
+/// pointing into it.
+///
+/// The header records the data file length and the number of entries, so a hint that
+/// doesn't belong to the data file or was cut short is detected when loading it.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if the hint file can't be written.
+pub(super) fn write(
+    storage: &dyn Storage,
+    layout: &Layout,
+    file_id: u64,
+    data_len: u64,
+    keydir: &BTreeMap<Vec<u8>, KeyDirEntry>,
+) -> Result<(), Error> {
+    let entries: Vec<_> = keydir
+        .iter()
+        .filter(|(_, entry)| entry.file_id == file_id)
+        .collect();
+
+    let file = storage.create(&layout.hint_path(file_id))?;
+    file.set_len(0)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&data_len.to_le_bytes())?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (key, entry) in entries {
+        writer.write_all(&entry.timestamp.to_le_bytes())?;
+        writer.write_all(&(key.len() as u32).to_le_bytes())?;
+        writer.write_all(&entry.value_size.to_le_bytes())?;
+        writer.write_all(&entry.value_position.to_le_bytes())?;
+        writer.write_all(key)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync()?;
+    Ok(())
+}
+
+/// Reads the hint file of data file `file_id`.
+///
+/// # Returns
+///
+/// Returns the key directory entries listed in the hint, or `None` if there is no
+/// hint or it doesn't match the data file, in which case the data file must be
+/// replayed instead.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if the hint file exists but can't be read.
+pub(super) fn read(
+    storage: &dyn Storage,
+    layout: &Layout,
+    file_id: u64,
+    data_len: u64,
+) -> Result<Option<HintEntries>, Error> {
+    let contents = match storage.read(&layout.hint_path(file_id)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let entries = parse(&contents, file_id, data_len);
+    if entries.is_none() {
+        log::warn!("Ignoring invalid hint file for file {}", file_id);
+    }
+    Ok(entries)
+}
+
+/// Parses the contents of a hint file, returning `None` if they are malformed or
+/// describe a data file of a different length.
+fn parse(contents: &[u8], file_id: u64, data_len: u64) -> Option<HintEntries> {
+    let u32_at = |at: usize| {
+        Some(u32::from_le_bytes(
+            contents.get(at..at + 4)?.try_into().ok()?,
+        ))
+    };
+    let u64_at = |at: usize| {
+        Some(u64::from_le_bytes(
+            contents.get(at..at + 8)?.try_into().ok()?,
+        ))
+    };
+
+    if u64_at(0)? != data_len {
+        return None;
+    }
+    let count = u64_at(8)?;
+
+    let mut entries = Vec::new();
+    let mut at = HEADER_SIZE;
+    while at < contents.len() {
+        let key_len = u32_at(at + 8)? as usize;
+        let entry = KeyDirEntry {
+            file_id,
+            timestamp: u64_at(at)?,
+            value_size: u32_at(at + 12)?,
+            value_position: u64_at(at + 16)?,
+            tombstone: false,
+        };
+        let key = contents.get(at + ENTRY_SIZE..at + ENTRY_SIZE + key_len)?;
+        entries.push((key.to_vec(), entry));
+        at += ENTRY_SIZE + key_len;
+    }
+
+    (entries.len() as u64 == count).then_some(entries)
+}
+
+/// Removes the hint file of data file `file_id`, if any.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if the hint file exists but can't be removed.
+pub(super) fn remove(storage: &dyn Storage, layout: &Layout, file_id: u64) -> Result<(), Error> {
+    match storage.remove(&layout.hint_path(file_id)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
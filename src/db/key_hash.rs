@@ -0,0 +1,191 @@
+//! Key directories holding key hashes instead of keys, with [`Options::hash_keys`].
+//!
+//! A hashed key is indexed under its slot: the 64-bit xxHash3 of the key, the key
+//! length on 3 bytes, since keys are at most [`MAX_KEY_SIZE`] long, and a
+//! disambiguator byte. Keys of the same length and hash get different
+//! disambiguators, and a lookup tells them apart by reading the key of every slot
+//! sharing its hash back from disk. The length lets the record header be found from
+//! the value position, as with the key itself.
+//!
+//! [`Options::hash_keys`]: super::Options::hash_keys
+//! [`MAX_KEY_SIZE`]: super::MAX_KEY_SIZE
+
+use std::{
+    collections::{btree_map, BTreeMap},
+    io::{self, Read, Seek, SeekFrom},
+    ops::Bound,
+};
+
+use super::{Bitask, Error, KeyDirEntry};
+
+/// Bytes of the hash and key length shared by every slot of a key
+pub(super) const DIGEST_SIZE: usize = 11;
+
+/// Bytes of a slot, the digest followed by the disambiguator
+const SLOT_SIZE: usize = DIGEST_SIZE + 1;
+
+/// Returns the hash and length of `key`, the part its slots start with.
+pub(super) fn digest(key: &[u8]) -> [u8; DIGEST_SIZE] {
+    let mut digest = [0; DIGEST_SIZE];
+    digest[..8].copy_from_slice(&xxhash_rust::xxh3::xxh3_64(key).to_be_bytes());
+    digest[8..].copy_from_slice(&(key.len() as u32).to_be_bytes()[1..]);
+    digest
+}
+
+/// Returns the slot of a key with `digest` and the disambiguator `n`.
+fn slot(digest: &[u8; DIGEST_SIZE], n: u8) -> Vec<u8> {
+    let mut slot = Vec::with_capacity(SLOT_SIZE);
+    slot.extend_from_slice(digest);
+    slot.push(n);
+    slot
+}
+
+/// Returns the length of the key indexed under `keydir_key`, a slot if `hashed`.
+pub(super) fn key_len(keydir_key: &[u8], hashed: bool) -> usize {
+    if !hashed {
+        return keydir_key.len();
+    }
+    let mut len = [0; 4];
+    len[1..].copy_from_slice(&keydir_key[8..DIGEST_SIZE]);
+    u32::from_be_bytes(len) as usize
+}
+
+/// Returns the entries `key` may be indexed under: its own, or those of every slot
+/// sharing its hash if `hashed`.
+pub(super) fn candidates<'a>(
+    keydir: &'a BTreeMap<Vec<u8>, KeyDirEntry>,
+    key: &[u8],
+    hashed: bool,
+) -> btree_map::Range<'a, Vec<u8>, KeyDirEntry> {
+    if !hashed {
+        return keydir.range::<[u8], _>((Bound::Included(key), Bound::Included(key)));
+    }
+    let digest = digest(key);
+    keydir.range(slot(&digest, 0)..=slot(&digest, u8::MAX))
+}
+
+/// Indexes every key of a replayed key directory under its slot.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if more keys share a hash and length than there are
+/// disambiguators.
+pub(super) fn hash_keydir(
+    keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+) -> Result<BTreeMap<Vec<u8>, KeyDirEntry>, Error> {
+    let mut hashed = BTreeMap::new();
+    for (key, entry) in keydir {
+        let slot = free_slot(&hashed, &digest(&key), &[])?;
+        hashed.insert(slot, entry);
+    }
+    Ok(hashed)
+}
+
+/// Returns the first slot with `digest` neither in `keydir` nor in `taken`.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if every disambiguator is used.
+fn free_slot(
+    keydir: &BTreeMap<Vec<u8>, KeyDirEntry>,
+    digest: &[u8; DIGEST_SIZE],
+    taken: &[Vec<u8>],
+) -> Result<Vec<u8>, Error> {
+    (0..=u8::MAX)
+        .map(|n| slot(digest, n))
+        .find(|slot| !keydir.contains_key(slot) && !taken.contains(slot))
+        .ok_or_else(|| io::Error::other("too many keys with the same hash").into())
+}
+
+impl Bitask {
+    /// Returns the key directory entry of `key`, if it's present.
+    ///
+    /// With hashed keys, the key of every slot sharing its hash is read back from
+    /// disk until one matches.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if reading a key back fails.
+    pub(super) fn lookup(&mut self, key: &[u8]) -> Result<Option<KeyDirEntry>, Error> {
+        if !self.options.hash_keys {
+            return Ok(self.keydir.get(key).copied());
+        }
+        Ok(self.find_slot(key)?.map(|(_, entry)| entry))
+    }
+
+    /// Returns the slot to index `key` under with hashed keys: the one it's already
+    /// indexed under, or else a free one. Returns `None` when keys aren't hashed, as
+    /// they're indexed as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if reading a key back fails, or every slot with the hash
+    /// of `key` is taken.
+    pub(super) fn slot_of(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.slots_of([key])?.pop().flatten())
+    }
+
+    /// Same as [`Bitask::slot_of`] for several keys about to be indexed together, so
+    /// a key given twice gets the same slot and two new keys never get the same one.
+    pub(super) fn slots_of<'k>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        let keys = keys.into_iter();
+        if !self.options.hash_keys {
+            return Ok(keys.map(|_| None).collect());
+        }
+
+        let mut slots = Vec::new();
+        // Keys not indexed yet and the free slots handed to them
+        let mut new_keys: Vec<&[u8]> = Vec::new();
+        let mut taken: Vec<Vec<u8>> = Vec::new();
+        for key in keys {
+            if let Some((slot, _)) = self.find_slot(key)? {
+                slots.push(Some(slot));
+            } else if let Some(i) = new_keys.iter().position(|new_key| *new_key == key) {
+                slots.push(Some(taken[i].clone()));
+            } else {
+                let slot = free_slot(&self.keydir, &digest(key), &taken)?;
+                new_keys.push(key);
+                taken.push(slot.clone());
+                slots.push(Some(slot));
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Returns the key indexed under `keydir_key`, read back from disk if it's a slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if reading the key fails.
+    pub(super) fn stored_key(
+        &mut self,
+        keydir_key: Vec<u8>,
+        entry: &KeyDirEntry,
+    ) -> Result<Vec<u8>, Error> {
+        if !self.options.hash_keys {
+            return Ok(keydir_key);
+        }
+        let mut key = vec![0; key_len(&keydir_key, true)];
+        let reader = self.reader(entry.file_id)?;
+        reader.seek(SeekFrom::Start(entry.value_position - key.len() as u64))?;
+        reader.read_exact(&mut key)?;
+        Ok(key)
+    }
+
+    /// Returns the slot `key` is indexed under with its entry, reading back the key of
+    /// every slot sharing its hash.
+    fn find_slot(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, KeyDirEntry)>, Error> {
+        let candidates: Vec<_> = candidates(&self.keydir, key, true)
+            .map(|(slot, entry)| (slot.clone(), *entry))
+            .collect();
+        for (slot, entry) in candidates {
+            if self.stored_key(slot.clone(), &entry)? == key {
+                return Ok(Some((slot, entry)));
+            }
+        }
+        Ok(None)
+    }
+}
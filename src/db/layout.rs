@@ -0,0 +1,235 @@
+//! Names of the files making up a database inside its directory.
+
+use std::path::{Path, PathBuf};
+
+use super::{
+    consts::{LOCK_FILE_NAME, MANIFEST_FILE_NAME},
+    Error,
+};
+
+/// Name of the file a new manifest is written to before replacing the current one
+const MANIFEST_TMP_PATH: &str = "MANIFEST.tmp";
+
+/// A data file of a database, identified by the timestamp in its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DataFile {
+    /// The active file, `<timestamp>.active.log`
+    Active(u64),
+    /// A sealed file, `<timestamp>.log`
+    Sealed(u64),
+    /// A compaction output still being written, `<timestamp>.compacting`
+    Compacting(u64),
+    /// A sealed file compaction replaced while a snapshot held it, `<timestamp>.retired`
+    Retired(u64),
+}
+
+/// Paths of the files of one database inside its directory.
+///
+/// A database opened with a name prefixes every file with `<name>.`, including its
+/// lock file and manifest, so several named databases can share a directory with
+/// each other and with an unnamed one. The data and hint files can be kept in a
+/// subdirectory, while the lock file and manifest stay at the top.
+#[derive(Debug, Clone)]
+pub(super) struct Layout {
+    /// Directory holding the lock file and manifest
+    dir: PathBuf,
+    /// Directory holding the data and hint files, `dir` or a subdirectory of it
+    data_dir: PathBuf,
+    /// Prefix of every file name, empty for an unnamed database
+    prefix: String,
+}
+
+impl Layout {
+    /// Creates the layout of an unnamed database stored in `dir`.
+    pub(super) fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            data_dir: dir.as_ref().to_path_buf(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Creates the layout of a database named `name` stored in `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidName`] if `name` is empty, numeric, or contains a dot
+    /// or path separator, since its files couldn't be told apart from other databases.
+    pub(super) fn named(dir: impl AsRef<Path>, name: &str) -> Result<Self, Error> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidName {
+                name: name.to_string(),
+            });
+        }
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            data_dir: dir.as_ref().to_path_buf(),
+            prefix: format!("{}.", name),
+        })
+    }
+
+    /// Returns the same layout with the data and hint files stored in `subdir`, a path
+    /// relative to the database directory. Returns it unchanged for `None`.
+    pub(super) fn with_data_subdir(self, subdir: Option<&Path>) -> Self {
+        match subdir {
+            Some(subdir) => Self {
+                data_dir: self.dir.join(subdir),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Returns the layout of the same database stored in `dir` instead, keeping its
+    /// name and data subdirectory.
+    pub(super) fn with_dir(&self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        let data_dir = match self.data_dir.strip_prefix(&self.dir) {
+            Ok(subdir) => dir.join(subdir),
+            Err(_) => dir.clone(),
+        };
+        Self {
+            dir,
+            data_dir,
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    /// Returns the directory holding the lock file and manifest.
+    pub(super) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the directory holding the data and hint files.
+    pub(super) fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Returns the path of a file of this database from its unprefixed name.
+    fn file_path(&self, file_name: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", self.prefix, file_name))
+    }
+
+    /// Returns the path of a data or hint file of this database from its unprefixed
+    /// name.
+    fn data_file_path(&self, file_name: &str) -> PathBuf {
+        self.data_dir.join(format!("{}{}", self.prefix, file_name))
+    }
+
+    /// Returns the path of the lock file: `<dir>/[<name>.]db.lock`
+    pub(super) fn lock_path(&self) -> PathBuf {
+        self.file_path(LOCK_FILE_NAME)
+    }
+
+    /// Returns the path of the manifest: `<dir>/[<name>.]MANIFEST`
+    pub(super) fn manifest_path(&self) -> PathBuf {
+        self.file_path(MANIFEST_FILE_NAME)
+    }
+
+    /// Returns the path a new manifest is written to: `<dir>/[<name>.]MANIFEST.tmp`
+    pub(super) fn manifest_tmp_path(&self) -> PathBuf {
+        self.file_path(MANIFEST_TMP_PATH)
+    }
+
+    /// Returns the path of an active log file: `<data_dir>/[<name>.]<timestamp>.active.log`
+    pub(super) fn active_log_path(&self, timestamp: u64) -> PathBuf {
+        self.data_file_path(&format!("{}.active.log", timestamp))
+    }
+
+    /// Returns the path of a sealed log file: `<data_dir>/[<name>.]<timestamp>.log`
+    pub(super) fn log_path(&self, timestamp: u64) -> PathBuf {
+        self.data_file_path(&format!("{}.log", timestamp))
+    }
+
+    /// Returns the path of a log file, active or sealed.
+    pub(super) fn path_for(&self, timestamp: u64, active: bool) -> PathBuf {
+        match active {
+            true => self.active_log_path(timestamp),
+            false => self.log_path(timestamp),
+        }
+    }
+
+    /// Returns the path of the hint file of a sealed log file:
+    /// `<data_dir>/[<name>.]<timestamp>.hint`
+    pub(super) fn hint_path(&self, timestamp: u64) -> PathBuf {
+        self.data_file_path(&format!("{}.hint", timestamp))
+    }
+
+    /// Returns the path of a compaction file still being written:
+    /// `<data_dir>/[<name>.]<timestamp>.compacting`
+    pub(super) fn compacting_path(&self, timestamp: u64) -> PathBuf {
+        self.data_file_path(&format!("{}.compacting", timestamp))
+    }
+
+    /// Returns the path a replaced sealed file is moved to while snapshots still read it:
+    /// `<data_dir>/[<name>.]<timestamp>.retired`
+    pub(super) fn retired_path(&self, timestamp: u64) -> PathBuf {
+        self.data_file_path(&format!("{}.retired", timestamp))
+    }
+
+    /// Identifies a data file of this database from a file name in its data directory.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` for the lock file, the manifest, files of other databases
+    /// sharing the directory, and files with an unknown extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The file name has no extension ([`Error::InvalidLogFileName`])
+    /// * The timestamp in the file name is invalid ([`Error::TimestampParse`])
+    pub(super) fn parse(&self, file_name: &str) -> Result<Option<DataFile>, Error> {
+        let Some(rest) = file_name.strip_prefix(&self.prefix) else {
+            return Ok(None);
+        };
+        if rest == LOCK_FILE_NAME || rest.starts_with(MANIFEST_FILE_NAME) {
+            return Ok(None);
+        }
+        if self.prefix.is_empty() && is_named_file(rest) {
+            return Ok(None);
+        }
+
+        let (timestamp, extension) =
+            rest.split_once('.')
+                .ok_or_else(|| Error::InvalidLogFileName {
+                    filename: file_name.to_string(),
+                })?;
+        let timestamp = timestamp.parse().map_err(|e| Error::TimestampParse {
+            value: file_name.to_string(),
+            source: e,
+        })?;
+
+        Ok(match extension {
+            "active.log" => Some(DataFile::Active(timestamp)),
+            "log" => Some(DataFile::Sealed(timestamp)),
+            "compacting" => Some(DataFile::Compacting(timestamp)),
+            "retired" => Some(DataFile::Retired(timestamp)),
+            _ => None,
+        })
+    }
+}
+
+/// Returns `true` if `name` can prefix the files of a database.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.bytes().all(|b| b.is_ascii_digit())
+        && !name.contains(['.', '/', '\\'])
+}
+
+/// Returns `true` if `file_name` belongs to a named database.
+fn is_named_file(file_name: &str) -> bool {
+    let Some((name, rest)) = file_name.split_once('.') else {
+        return false;
+    };
+    if !is_valid_name(name) {
+        return false;
+    }
+
+    rest == LOCK_FILE_NAME
+        || rest.starts_with(MANIFEST_FILE_NAME)
+        || rest
+            .split_once('.')
+            .is_some_and(|(timestamp, _)| timestamp.parse::<u64>().is_ok())
+}
@@ -0,0 +1,103 @@
+//! Manifest listing the data files of a database, so opening it doesn't need to
+//! scan the directory.
+
+use std::{collections::BTreeSet, io::Write};
+
+use super::{layout::Layout, Error, Storage};
+
+/// The data files making up a database.
+///
+/// Stored as text, one file per line: `active <id>` for the active file followed by
+/// `sealed <id>` for every sealed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Manifest {
+    /// File ID of the active file
+    pub(super) active: u64,
+    /// File IDs of the sealed files
+    pub(super) sealed: BTreeSet<u64>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the database laid out as `layout`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if there is no manifest, or if it can't be parsed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the manifest exists but can't be read.
+    pub(super) fn read(storage: &dyn Storage, layout: &Layout) -> Result<Option<Self>, Error> {
+        let contents = match storage.read(&layout.manifest_path()) {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut active = None;
+        let mut sealed = BTreeSet::new();
+        for line in contents.lines() {
+            let parsed = match line.split_once(' ') {
+                Some(("active", id)) => id.parse().map(|id| active = Some(id)),
+                Some(("sealed", id)) => id.parse().map(|id| {
+                    sealed.insert(id);
+                }),
+                _ => {
+                    log::warn!("Ignoring manifest with unexpected line '{}'", line);
+                    return Ok(None);
+                }
+            };
+            if parsed.is_err() {
+                log::warn!("Ignoring manifest with invalid file id in '{}'", line);
+                return Ok(None);
+            }
+        }
+
+        Ok(active.map(|active| Self { active, sealed }))
+    }
+
+    /// Returns `true` if every file listed in the manifest exists under the expected name.
+    ///
+    /// A crash between renaming a file and updating the manifest leaves it stale.
+    pub(super) fn is_current(&self, storage: &dyn Storage, layout: &Layout) -> bool {
+        storage.exists(&layout.active_log_path(self.active))
+            && self
+                .sealed
+                .iter()
+                .all(|&file_id| storage.exists(&layout.log_path(file_id)))
+    }
+
+    /// Writes the manifest of the database laid out as `layout`, replacing the current
+    /// one atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the manifest can't be written.
+    pub(super) fn write(&self, storage: &dyn Storage, layout: &Layout) -> Result<(), Error> {
+        let mut contents = format!("active {}\n", self.active);
+        for file_id in &self.sealed {
+            contents.push_str(&format!("sealed {}\n", file_id));
+        }
+
+        let tmp_path = layout.manifest_tmp_path();
+        let mut file = storage.create(&tmp_path)?;
+        file.set_len(0)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync()?;
+        storage.rename(&tmp_path, &layout.manifest_path())?;
+        Ok(())
+    }
+
+    /// Removes the manifest of the database laid out as `layout`, so the next open
+    /// scans the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the manifest exists but can't be removed.
+    pub(super) fn remove(storage: &dyn Storage, layout: &Layout) -> Result<(), Error> {
+        match storage.remove(&layout.manifest_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Operation counters of a [`Bitask`](super::Bitask) database.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "prometheus")]
+use std::sync::Arc;
+
+#[cfg(feature = "prometheus")]
+use prometheus::core::Collector;
+
+/// Counters of the operations performed since the database was opened.
+///
+/// Returned by [`Bitask::metrics`](super::Bitask::metrics) as a copy, so the values
+/// don't change while being exported.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// let metrics = db.metrics();
+/// println!("{} hits, {} misses", metrics.get_hits, metrics.get_misses);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Metrics {
+    /// Key-value pairs written
+    pub puts: u64,
+    /// Keys looked up
+    pub gets: u64,
+    /// Keys removed, counting removals of missing keys
+    pub removes: u64,
+    /// Lookups that found their key
+    pub get_hits: u64,
+    /// Lookups that didn't find their key
+    pub get_misses: u64,
+    /// Active file rotations
+    pub rotations: u64,
+    /// Completed compactions, not counting skipped ones
+    pub compactions: u64,
+    /// Bytes of records appended to the active file
+    pub bytes_written: u64,
+    /// Bytes of records read to answer lookups
+    pub bytes_read: u64,
+    /// Flushes of the active file to the operating system
+    pub flushes: u64,
+    /// Syncs of the active file to stable storage
+    pub syncs: u64,
+}
+
+/// One counter of [`Counters`].
+#[derive(Debug, Default)]
+pub(super) struct Counter(AtomicU64);
+
+impl Counter {
+    /// Adds `n` to the counter.
+    pub(super) fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Returns the current value.
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Live counters behind [`Metrics`], which exporters can read while the database
+/// keeps updating them.
+#[derive(Debug, Default)]
+pub(super) struct Counters {
+    pub(super) puts: Counter,
+    pub(super) gets: Counter,
+    pub(super) removes: Counter,
+    pub(super) get_hits: Counter,
+    pub(super) get_misses: Counter,
+    pub(super) rotations: Counter,
+    pub(super) compactions: Counter,
+    pub(super) bytes_written: Counter,
+    pub(super) bytes_read: Counter,
+    pub(super) flushes: Counter,
+    pub(super) syncs: Counter,
+}
+
+impl Counters {
+    /// Returns the current values.
+    pub(super) fn snapshot(&self) -> Metrics {
+        Metrics {
+            puts: self.puts.get(),
+            gets: self.gets.get(),
+            removes: self.removes.get(),
+            get_hits: self.get_hits.get(),
+            get_misses: self.get_misses.get(),
+            rotations: self.rotations.get(),
+            compactions: self.compactions.get(),
+            bytes_written: self.bytes_written.get(),
+            bytes_read: self.bytes_read.get(),
+            flushes: self.flushes.get(),
+            syncs: self.syncs.get(),
+        }
+    }
+}
+
+/// Name, help and value of a counter exported to Prometheus
+#[cfg(feature = "prometheus")]
+type Exported = (&'static str, &'static str, fn(&Metrics) -> u64);
+
+/// Prometheus collector reading [`Counters`] on every gather, with the `prometheus`
+/// feature.
+#[cfg(feature = "prometheus")]
+pub(super) struct MetricsCollector {
+    /// Counters of the database
+    counters: Arc<Counters>,
+    /// Name, help and value of every exported counter
+    exported: Vec<Exported>,
+    /// Descriptions of the exported counters
+    descs: Vec<prometheus::core::Desc>,
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsCollector {
+    /// Creates a collector of `counters`.
+    pub(super) fn new(counters: Arc<Counters>) -> Self {
+        let exported: Vec<Exported> = vec![
+            ("bitask_puts_total", "Key-value pairs written", |m| m.puts),
+            ("bitask_gets_total", "Keys looked up", |m| m.gets),
+            ("bitask_removes_total", "Keys removed", |m| m.removes),
+            (
+                "bitask_get_hits_total",
+                "Lookups that found their key",
+                |m| m.get_hits,
+            ),
+            (
+                "bitask_get_misses_total",
+                "Lookups that didn't find their key",
+                |m| m.get_misses,
+            ),
+            ("bitask_rotations_total", "Active file rotations", |m| {
+                m.rotations
+            }),
+            ("bitask_compactions_total", "Completed compactions", |m| {
+                m.compactions
+            }),
+            (
+                "bitask_bytes_written_total",
+                "Bytes of records appended",
+                |m| m.bytes_written,
+            ),
+            (
+                "bitask_bytes_read_total",
+                "Bytes of records read by lookups",
+                |m| m.bytes_read,
+            ),
+            ("bitask_flushes_total", "Flushes of the active file", |m| {
+                m.flushes
+            }),
+            ("bitask_syncs_total", "Syncs of the active file", |m| {
+                m.syncs
+            }),
+        ];
+        let descs = exported
+            .iter()
+            .map(|(name, help, _)| counter(name, help, 0).desc()[0].clone())
+            .collect();
+
+        Self {
+            counters,
+            exported,
+            descs,
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl Collector for MetricsCollector {
+    fn desc(&self) -> Vec<&prometheus::core::Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let metrics = self.counters.snapshot();
+        self.exported
+            .iter()
+            .flat_map(|(name, help, value)| counter(name, help, value(&metrics)).collect())
+            .collect()
+    }
+}
+
+/// Creates a Prometheus counter holding `value`.
+#[cfg(feature = "prometheus")]
+fn counter(name: &str, help: &str, value: u64) -> prometheus::IntCounter {
+    let counter = prometheus::IntCounter::new(name, help).expect("counter names are valid");
+    counter.inc_by(value);
+    counter
+}
@@ -0,0 +1,140 @@
+//! Zero-copy reads of values stored in memory-mapped sealed files.
+
+use std::{marker::PhantomData, ops::Deref, ops::Range, sync::Arc};
+
+use memmap2::Mmap;
+
+use super::{Bitask, ChecksumKind, CompressionKind, Error, KeyDirEntry, RecordHeader};
+
+/// A value returned by [`Bitask::ask_mmap`], borrowed from the database.
+///
+/// Dereferences to the value bytes. Values stored uncompressed in a sealed file are
+/// borrowed straight from the file's memory mapping; others are read into an owned
+/// buffer, see [`MmapGuard::is_mapped`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// let value = db.ask_mmap(b"my_key")?;
+/// println!("{} bytes", value.len());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct MmapGuard<'a> {
+    /// Where the value bytes live
+    value: Value,
+    /// Keeps the database borrowed, so it can't be compacted or closed meanwhile
+    _db: PhantomData<&'a Bitask>,
+}
+
+/// Value bytes held by a [`MmapGuard`].
+#[derive(Debug)]
+enum Value {
+    /// Slice of a mapped sealed file
+    Mapped {
+        /// Mapping of the whole file
+        map: Arc<Mmap>,
+        /// Position of the value within the file
+        range: Range<usize>,
+    },
+    /// Value read or decompressed into memory
+    Owned(Vec<u8>),
+}
+
+impl MmapGuard<'_> {
+    /// Wraps a value read into memory.
+    pub(super) fn owned(value: Vec<u8>) -> Self {
+        Self {
+            value: Value::Owned(value),
+            _db: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the value is borrowed from a memory mapping without a copy.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.value, Value::Mapped { .. })
+    }
+}
+
+impl Deref for MmapGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.value {
+            Value::Mapped { map, range } => &map[range.clone()],
+            Value::Owned(value) => value,
+        }
+    }
+}
+
+impl AsRef<[u8]> for MmapGuard<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Reads the value of `key` from the mapping of the sealed file `entry` points into.
+///
+/// Performs the same checks as [`read_record`](super::read_record).
+///
+/// # Errors
+///
+/// Returns an [`Error`] if:
+/// * The file ends before the value does ([`Error::TruncatedValue`])
+/// * The stored header or key doesn't match the entry ([`Error::RecordMismatch`])
+/// * The stored checksum doesn't match ([`Error::ChecksumMismatch`])
+/// * The value can't be decompressed ([`Error::UnsupportedCompression`], [`Error::Io`])
+pub(super) fn read_mapped<'a>(
+    map: Arc<Mmap>,
+    key: &[u8],
+    entry: &KeyDirEntry,
+    paranoid: bool,
+) -> Result<MmapGuard<'a>, Error> {
+    let value_start = entry.value_position as usize;
+    let value_end = value_start + entry.value_size as usize;
+    let header_start = value_start - key.len() - RecordHeader::SIZE;
+    if value_end > map.len() {
+        return Err(Error::TruncatedValue {
+            key: key.to_vec(),
+            file_id: entry.file_id,
+            expected: entry.value_size,
+            available: (map.len() as u64).saturating_sub(entry.value_position),
+        });
+    }
+
+    let header = RecordHeader::deserialize(&map[header_start..])?;
+    let stored_key = &map[header_start + RecordHeader::SIZE..value_start];
+    if paranoid
+        && (header.key_len as usize != key.len()
+            || header.value_size != entry.value_size
+            || stored_key != key)
+    {
+        return Err(Error::RecordMismatch {
+            file_id: entry.file_id,
+            position: header_start as u64,
+        });
+    }
+
+    let value = &map[value_start..value_end];
+    let checksum = ChecksumKind::from_flags(header.flags).ok_or(Error::UnknownChecksum {
+        flags: header.flags,
+    })?;
+    if checksum.checksum(key, value) != header.crc {
+        return Err(Error::ChecksumMismatch {
+            file_id: entry.file_id,
+            position: header_start as u64,
+        });
+    }
+
+    if let Some(compression) = CompressionKind::from_flags(header.flags)? {
+        return Ok(MmapGuard::owned(compression.decompress(value)?));
+    }
+    Ok(MmapGuard {
+        value: Value::Mapped {
+            map,
+            range: value_start..value_end,
+        },
+        _db: PhantomData,
+    })
+}
@@ -0,0 +1,358 @@
+//! Configuration for opening a [`Bitask`] database.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use super::{
+    layout::Layout, Bitask, ChecksumKind, Clock, CompressionKind, Error, FsStorage, OpenError,
+    Storage, SystemClock, MAX_ACTIVE_FILE_SIZE,
+};
+
+/// Controls how eagerly writes are pushed to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Flush buffered writes to the operating system after every operation.
+    ///
+    /// Survives a process crash, but not necessarily a power loss.
+    #[default]
+    Flush,
+    /// Flush and `fsync` the active file after every operation.
+    ///
+    /// Survives a power loss at the cost of one disk sync per write. The database
+    /// directory is also synced after files are renamed, created or removed, on
+    /// rotation and compaction, so the file set survives along with the records.
+    Sync,
+    /// Flush after every operation and `fsync` the active file once the interval has
+    /// elapsed since the last sync.
+    ///
+    /// The elapsed time is checked on every write and read, so a power loss loses at
+    /// most about one interval of writes while the database is in use. Call
+    /// [`Bitask::sync`] to sync an idle database.
+    /// The directory is synced on rotation and compaction, as with [`Durability::Sync`].
+    SyncInterval(Duration),
+    /// Keep writes in the write buffer until it fills up.
+    ///
+    /// The buffer is also flushed when the active file rotates, before a read from the
+    /// active file, by [`Bitask::sync`] and when the database is closed, so reads
+    /// through the handle always see its writes. A process crash loses the writes
+    /// still buffered.
+    Buffered,
+}
+
+/// What a write does when it would grow the data files past
+/// [`Options::max_total_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeLimitPolicy {
+    /// Fail the write with [`Error::StorageFull`].
+    #[default]
+    Reject,
+    /// Merge every data file with [`Bitask::merge`] to drop dead records, failing the
+    /// write if the live data alone is too large.
+    ///
+    /// Every write over the limit merges again, so the limit should leave room above
+    /// the live data.
+    Compact,
+    /// Remove the keys written longest ago until the live data leaves room for the
+    /// write, then merge, turning the database into a size-bounded cache.
+    ///
+    /// [`Options::eviction`] can rank keys by their last access instead.
+    EvictOldest,
+}
+
+/// Which keys [`SizeLimitPolicy::EvictOldest`] removes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// The keys written longest ago.
+    #[default]
+    Oldest,
+    /// The keys read or written longest ago through [`Bitask::ask`], [`Bitask::ask_many`]
+    /// or a write, as in an LRU cache.
+    ///
+    /// Accesses are tracked in memory, using an extra copy of every key accessed. Keys
+    /// not accessed since the database was opened go first, oldest write first.
+    LeastRecentlyUsed,
+}
+
+/// Options used when opening a database.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bitask::db::{Bitask, Options};
+///
+/// let options = Options {
+///     max_file_size: 1024 * 1024,
+///     ..Options::default()
+/// };
+/// let mut db = Bitask::open_with_options("my_db", options)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Size in bytes the active file is kept under: it's rotated before a record
+    /// would take it past this size, and a larger record gets a file of its own
+    pub max_file_size: u64,
+    /// Durability mode applied to every write
+    pub durability: Durability,
+    /// Compact automatically after a rotation when [`Bitask::should_compact`] says so
+    pub auto_compact: bool,
+    /// Fraction of sealed bytes that must be dead before [`Bitask::should_compact`]
+    /// recommends a compaction, between `0.0` and `1.0`
+    pub compaction_dead_ratio: f64,
+    /// Reserve `max_file_size` bytes when creating an active file to reduce fragmentation.
+    ///
+    /// The reserved space is trimmed when the file is rotated or the database is closed.
+    /// Space still reserved after a crash is trimmed by the next open with this option
+    /// set, and otherwise left in place, as zeros past the last record are skipped.
+    pub preallocate: bool,
+    /// Algorithm used to checksum newly written records, recorded in each record's
+    /// header flags rather than per file
+    pub checksum: ChecksumKind,
+    /// Codec used to compress newly written values, `None` to store them as is
+    pub compression: Option<CompressionKind>,
+    /// Check the header and key stored before every value against the key directory
+    /// entry on each read, at the cost of reading the key back from disk
+    pub paranoid_reads: bool,
+    /// Capacity in bytes of the buffer values are read through, 8KB by default. 0
+    /// reads straight from the file, one read each for the header, key and value,
+    /// which saves a copy when values are much larger than the buffer.
+    pub read_buffer_size: usize,
+    /// Accept empty values, stored distinctly from removals. Files holding empty
+    /// values are misread by bitask versions before 0.2, which see them as removals.
+    pub allow_empty_values: bool,
+    /// How long opening waits for another writer to release the database, failing
+    /// right away when `None`
+    pub lock_timeout: Option<Duration>,
+    /// Source of record timestamps and new file IDs
+    pub clock: Arc<dyn Clock>,
+    /// Backend holding the database files
+    pub storage: Arc<dyn Storage>,
+    /// Subdirectory of the database directory holding the data and hint files, which
+    /// otherwise sit next to the lock file and manifest. Must be the same every time
+    /// the database is opened.
+    pub data_subdir: Option<PathBuf>,
+    /// Cap in bytes on the total size of the data files, checked before every write
+    /// of a value. Hint files, the manifest and the lock file aren't counted.
+    pub max_total_size: Option<u64>,
+    /// What a write does when it would exceed [`Options::max_total_size`]
+    pub size_limit_policy: SizeLimitPolicy,
+    /// Which keys go first when [`SizeLimitPolicy::EvictOldest`] makes room
+    pub eviction: EvictionPolicy,
+    /// Keep a fixed-size hash of every key in memory instead of the key itself, for
+    /// databases with long keys. Lookups and overwrites read the key back from disk to
+    /// check it, and operations needing the keys in order, such as
+    /// [`Bitask::list_keys`] or [`Bitask::snapshot`], aren't available. Opening still
+    /// holds every key until the key directory is built, and compaction writes no
+    /// hint files. See [`Bitask::keydir_memory_bytes`] for the memory saved.
+    pub hash_keys: bool,
+    /// Watch the directory so a [`ReadOnlyBitask`](super::ReadOnlyBitask) refreshes
+    /// itself when the writer changes the files. Ignored by [`Bitask`].
+    #[cfg(feature = "watch")]
+    pub watch: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_file_size: MAX_ACTIVE_FILE_SIZE,
+            durability: Durability::default(),
+            auto_compact: false,
+            compaction_dead_ratio: 0.5,
+            preallocate: false,
+            checksum: ChecksumKind::default(),
+            compression: None,
+            paranoid_reads: false,
+            read_buffer_size: 8 * 1024,
+            allow_empty_values: false,
+            lock_timeout: None,
+            clock: Arc::new(SystemClock),
+            storage: Arc::new(FsStorage),
+            data_subdir: None,
+            max_total_size: None,
+            size_limit_policy: SizeLimitPolicy::default(),
+            eviction: EvictionPolicy::default(),
+            hash_keys: false,
+            #[cfg(feature = "watch")]
+            watch: false,
+        }
+    }
+}
+
+/// Fluent builder for opening a [`Bitask`] database.
+///
+/// Created with [`Bitask::builder`]. Every setter is optional except [`BitaskBuilder::path`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use bitask::db::{Bitask, Durability};
+///
+/// let mut db = Bitask::builder()
+///     .path("my_db")
+///     .max_file_size(1024 * 1024)
+///     .durability(Durability::Sync)
+///     .auto_compact(true)
+///     .open()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BitaskBuilder {
+    path: Option<PathBuf>,
+    name: Option<String>,
+    options: Options,
+}
+
+impl BitaskBuilder {
+    /// Sets the directory where the database files are stored.
+    pub fn path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the name of the database within its directory, see [`Bitask::open_named`].
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the size in bytes the active file is rotated before exceeding.
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.options.max_file_size = max_file_size;
+        self
+    }
+
+    /// Sets the [`Durability`] mode applied to every write.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.options.durability = durability;
+        self
+    }
+
+    /// Enables or disables automatic compaction after rotation.
+    pub fn auto_compact(mut self, auto_compact: bool) -> Self {
+        self.options.auto_compact = auto_compact;
+        self
+    }
+
+    /// Sets the fraction of dead sealed bytes that triggers a compaction.
+    pub fn compaction_dead_ratio(mut self, compaction_dead_ratio: f64) -> Self {
+        self.options.compaction_dead_ratio = compaction_dead_ratio;
+        self
+    }
+
+    /// Enables or disables reserving the full file size when creating an active file.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.options.preallocate = preallocate;
+        self
+    }
+
+    /// Sets the [`ChecksumKind`] used for newly written records.
+    pub fn checksum(mut self, checksum: ChecksumKind) -> Self {
+        self.options.checksum = checksum;
+        self
+    }
+
+    /// Sets the [`CompressionKind`] used for newly written values.
+    pub fn compression(mut self, compression: CompressionKind) -> Self {
+        self.options.compression = Some(compression);
+        self
+    }
+
+    /// Enables or disables verifying stored headers and keys on every read.
+    pub fn paranoid_reads(mut self, paranoid_reads: bool) -> Self {
+        self.options.paranoid_reads = paranoid_reads;
+        self
+    }
+
+    /// Sets the capacity of the buffer values are read through, 0 to read straight
+    /// from the file.
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.options.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Enables or disables storing empty values.
+    pub fn allow_empty_values(mut self, allow_empty_values: bool) -> Self {
+        self.options.allow_empty_values = allow_empty_values;
+        self
+    }
+
+    /// Sets how long opening waits for another writer to release the database.
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.options.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    /// Sets the [`Clock`] timestamps and file IDs are read from.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.options.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets the [`Storage`] backend holding the database files.
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.options.storage = Arc::new(storage);
+        self
+    }
+
+    /// Sets the subdirectory holding the data and hint files, relative to the database
+    /// directory.
+    pub fn data_subdir(mut self, data_subdir: impl AsRef<Path>) -> Self {
+        self.options.data_subdir = Some(data_subdir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the cap on the total size of the data files.
+    pub fn max_total_size(mut self, max_total_size: u64) -> Self {
+        self.options.max_total_size = Some(max_total_size);
+        self
+    }
+
+    /// Sets the [`SizeLimitPolicy`] applied when a write would exceed the size cap.
+    pub fn size_limit_policy(mut self, size_limit_policy: SizeLimitPolicy) -> Self {
+        self.options.size_limit_policy = size_limit_policy;
+        self
+    }
+
+    /// Sets the [`EvictionPolicy`] picking the keys evicted at the size cap.
+    pub fn eviction(mut self, eviction: EvictionPolicy) -> Self {
+        self.options.eviction = eviction;
+        self
+    }
+
+    /// Enables or disables keeping key hashes in memory instead of keys.
+    pub fn hash_keys(mut self, hash_keys: bool) -> Self {
+        self.options.hash_keys = hash_keys;
+        self
+    }
+
+    /// Enables or disables watching the directory from a read-only handle.
+    #[cfg(feature = "watch")]
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.options.watch = watch;
+        self
+    }
+
+    /// Opens the database with the configured options.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OpenError`] if:
+    /// * No path was configured ([`OpenError::Other`] holding [`Error::MissingPath`])
+    /// * The configured name is invalid ([`OpenError::Other`] holding
+    ///   [`Error::InvalidName`])
+    /// * Opening the database fails, see [`Bitask::open`]
+    pub fn open(self) -> Result<Bitask, OpenError> {
+        let path = self
+            .path
+            .ok_or(Error::MissingPath)
+            .map_err(OpenError::from_error)?;
+        let layout = match &self.name {
+            Some(name) => Layout::named(path, name).map_err(OpenError::from_error)?,
+            None => Layout::new(path),
+        };
+        Bitask::open_layout(layout, self.options).map_err(OpenError::from_error)
+    }
+}
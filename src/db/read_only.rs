@@ -0,0 +1,330 @@
+//! Read-only handles on a database written by another handle or process.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufReader, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    index_entry, layout::DataFile, layout::Layout, read_record, Bitask, Error, KeyDirEntry,
+    LogReader, Options,
+};
+
+/// How many times [`ReadOnlyBitask::refresh`] lists the directory again when a file
+/// is renamed or removed between listing and opening it
+const MAX_REFRESH_ATTEMPTS: usize = 3;
+
+/// A read-only handle on a database, for readers running next to the writer.
+///
+/// Takes no lock, so it can be opened while another handle or process writes to the
+/// database. Its view is the state of the files when it was opened or last
+/// refreshed: [`ReadOnlyBitask::refresh`] picks up records appended since, files
+/// sealed by a rotation and the results of a compaction. With the `watch` feature
+/// and `Options::watch` set, the directory is watched for changes and the handle
+/// refreshes itself before the next lookup, so polling isn't needed.
+///
+/// Buffered writes of the writer, see [`Durability::Buffered`](super::Durability::Buffered),
+/// are only seen once flushed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bitask::db::{Options, ReadOnlyBitask};
+///
+/// let mut replica = ReadOnlyBitask::open("my_db", Options::default())?;
+/// // ... the writer puts `my_key` ...
+/// replica.refresh()?;
+/// let value = replica.ask(b"my_key")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct ReadOnlyBitask {
+    /// Paths of the database files
+    layout: Layout,
+    /// Options the handle was opened with
+    options: Options,
+    /// Key directory, keeping tombstones so a record replayed later can't resurrect
+    /// a removed key
+    keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+    /// Every data file replayed so far, by file ID
+    files: BTreeMap<u64, ReplayedFile>,
+    /// Watcher flagging changes to the directory
+    #[cfg(feature = "watch")]
+    watcher: Option<watch::DirWatcher>,
+}
+
+/// A handle returned by [`Bitask::open_rw_or_ro`], telling which mode was obtained.
+///
+/// Handles are boxed, as they differ widely in size.
+#[derive(Debug)]
+pub enum OpenedBitask {
+    /// The lock was free, the database is open for writing
+    ReadWrite(Box<Bitask>),
+    /// Another writer holds the lock, the database is open for reading only
+    ReadOnly(Box<ReadOnlyBitask>),
+}
+
+/// A data file replayed into a [`ReadOnlyBitask`].
+#[derive(Debug)]
+struct ReplayedFile {
+    /// Reader over the file, kept open so it can still be read if compaction removes it
+    reader: LogReader,
+    /// Offset right after the last record replayed
+    scanned: u64,
+    /// Whether the file was sealed when last replayed, so it can't grow anymore
+    sealed: bool,
+}
+
+impl ReadOnlyBitask {
+    /// Opens the database at `path` for reading.
+    ///
+    /// Of `options`, only [`Options::paranoid_reads`], [`Options::storage`],
+    /// [`Options::data_subdir`] and `Options::watch` apply.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - Path where the database files are stored
+    /// * `options` - Configuration applied for the lifetime of the handle
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`ReadOnlyBitask`] if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The directory can't be read or watched ([`Error::Io`])
+    /// * Log file names are malformed ([`Error::InvalidLogFileName`])
+    /// * Log files contain invalid or corrupted data
+    pub fn open(path: impl AsRef<Path>, options: Options) -> Result<Self, Error> {
+        let layout = Layout::new(path).with_data_subdir(options.data_subdir.as_deref());
+        #[cfg(feature = "watch")]
+        let watcher = match options.watch {
+            true => Some(watch::DirWatcher::new(layout.data_dir())?),
+            false => None,
+        };
+
+        let mut db = Self {
+            layout,
+            options,
+            keydir: BTreeMap::new(),
+            files: BTreeMap::new(),
+            #[cfg(feature = "watch")]
+            watcher,
+        };
+        db.refresh()?;
+        Ok(db)
+    }
+
+    /// Retrieves the value associated with `key`.
+    ///
+    /// Refreshes the handle first if `Options::watch` is set and the directory
+    /// changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`], and those of
+    /// [`ReadOnlyBitask::refresh`].
+    pub fn ask(&mut self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+        #[cfg(feature = "watch")]
+        if self
+            .watcher
+            .as_ref()
+            .is_some_and(watch::DirWatcher::take_changed)
+        {
+            self.refresh()?;
+        }
+
+        let entry = match self.keydir.get(key) {
+            Some(entry) if !entry.tombstone => *entry,
+            _ => return Err(Error::KeyNotFound),
+        };
+        let file = self
+            .files
+            .get_mut(&entry.file_id)
+            .ok_or(Error::FileNotFound(format!("{}", entry.file_id)))?;
+        read_record(&mut file.reader, key, &entry, self.options.paranoid_reads)
+    }
+
+    /// Returns the number of keys in the handle's view.
+    pub fn len(&self) -> usize {
+        self.keydir
+            .values()
+            .filter(|entry| !entry.tombstone)
+            .count()
+    }
+
+    /// Returns `true` if the handle's view holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Catches up with the files in the directory.
+    ///
+    /// Records appended since the last refresh are replayed, new files are opened and
+    /// replayed, using their hint file if they have one. When files the handle knew
+    /// about are gone, because the writer compacted them, the view is rebuilt from
+    /// scratch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The directory or a data file can't be read ([`Error::Io`])
+    /// * Log file names are malformed ([`Error::InvalidLogFileName`])
+    /// * Log files contain invalid or corrupted data
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let mut attempt = 1;
+        loop {
+            match self.try_refresh() {
+                // The writer renamed or removed a file after it was listed
+                Err(Error::Io(e))
+                    if e.kind() == io::ErrorKind::NotFound && attempt < MAX_REFRESH_ATTEMPTS =>
+                {
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Lists the directory once and replays what changed, see
+    /// [`ReadOnlyBitask::refresh`].
+    fn try_refresh(&mut self) -> Result<(), Error> {
+        let storage = &*self.options.storage;
+        let mut active = BTreeSet::new();
+        let mut sealed = BTreeSet::new();
+        for name in storage.list(self.layout.data_dir())? {
+            match self.layout.parse(&name)? {
+                Some(DataFile::Active(file_id)) => {
+                    active.insert(file_id);
+                }
+                Some(DataFile::Sealed(file_id)) => {
+                    sealed.insert(file_id);
+                }
+                // Compaction output still being written
+                Some(DataFile::Compacting(_) | DataFile::Retired(_)) | None => (),
+            }
+        }
+
+        if self
+            .files
+            .keys()
+            .any(|file_id| !active.contains(file_id) && !sealed.contains(file_id))
+        {
+            log::debug!("Data files were removed, rebuilding the read-only view");
+            self.keydir.clear();
+            self.files.clear();
+        }
+
+        for file_id in active.union(&sealed).copied().collect::<BTreeSet<_>>() {
+            let is_sealed = sealed.contains(&file_id);
+            if !self.files.contains_key(&file_id) {
+                let (reader, scanned) = if is_sealed {
+                    let mut reader =
+                        Bitask::replay_sealed(storage, &self.layout, file_id, &mut self.keydir)?;
+                    let scanned = reader.get_ref().size()?;
+                    reader.seek(SeekFrom::Start(scanned))?;
+                    (reader, scanned)
+                } else {
+                    let path = self.layout.active_log_path(file_id);
+                    (BufReader::new(storage.open(&path)?), 0)
+                };
+                self.files.insert(
+                    file_id,
+                    ReplayedFile {
+                        reader,
+                        scanned,
+                        sealed: is_sealed,
+                    },
+                );
+                if is_sealed {
+                    continue;
+                }
+            }
+            let Some(file) = self.files.get_mut(&file_id) else {
+                continue;
+            };
+            if file.sealed {
+                continue;
+            }
+
+            // Replay what was appended since the last refresh
+            file.reader.seek(SeekFrom::Start(file.scanned))?;
+            let keydir = &mut self.keydir;
+            file.scanned = Bitask::scan_log(&mut file.reader, file_id, |key, entry| {
+                index_entry(keydir, key, entry);
+            })?;
+            file.sealed = is_sealed;
+        }
+        Ok(())
+    }
+}
+
+/// Watching the database directory for changes, with the `watch` feature.
+#[cfg(feature = "watch")]
+mod watch {
+    use std::{
+        io,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    use super::Error;
+
+    /// Flags changes to the files of a directory.
+    #[derive(Debug)]
+    pub(super) struct DirWatcher {
+        /// Keeps the directory watched until dropped
+        _watcher: notify::RecommendedWatcher,
+        /// Set when a file changed, cleared by [`DirWatcher::take_changed`]
+        changed: Arc<AtomicBool>,
+    }
+
+    impl DirWatcher {
+        /// Starts watching `dir`.
+        ///
+        /// # Errors
+        ///
+        /// Returns [`Error::Io`] if the directory can't be watched.
+        pub(super) fn new(dir: &Path) -> Result<Self, Error> {
+            let changed = Arc::new(AtomicBool::new(false));
+            let flag = changed.clone();
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<_>| {
+                // Errors may mean missed events, so they count as changes too
+                let is_read = matches!(
+                    event,
+                    Ok(notify::Event {
+                        kind: EventKind::Access(_),
+                        ..
+                    })
+                );
+                if !is_read {
+                    flag.store(true, Ordering::Release);
+                }
+            })
+            .map_err(io::Error::other)?;
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(io::Error::other)?;
+
+            Ok(Self {
+                _watcher: watcher,
+                changed,
+            })
+        }
+
+        /// Returns `true` if a file changed since the last call.
+        pub(super) fn take_changed(&self) -> bool {
+            self.changed.swap(false, Ordering::AcqRel)
+        }
+    }
+}
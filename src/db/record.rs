@@ -0,0 +1,181 @@
+//! On-disk format of the records stored in the log files.
+//!
+//! Every record is a fixed-size [`RecordHeader`] followed by the key bytes and the
+//! value bytes. A record with a value size of 0 is a tombstone marking the key as
+//! removed, unless its header flags it as an empty value. This layout is stable:
+//! files written by any version of bitask can be parsed with it, so external tools
+//! can read log files without going through [`Bitask`](super::Bitask).
+
+use super::{ChecksumKind, Error, MAX_KEY_SIZE};
+
+/// Header written before the key and value of every record.
+///
+/// Serialized as [`RecordHeader::SIZE`] bytes, all integers little-endian:
+///
+/// | Offset | Size | Field                                              |
+/// |--------|------|----------------------------------------------------|
+/// | 0      | 4    | `crc`, checksum of the key followed by the value   |
+/// | 4      | 8    | `timestamp`, milliseconds since UNIX epoch         |
+/// | 12     | 4    | `key_len` in the low 24 bits, `flags` in the top 8 |
+/// | 16     | 4    | `value_size`, 0 for a tombstone or an empty value  |
+///
+/// The low 2 bits of `flags` select the checksum algorithm, see
+/// [`RecordHeader::checksum_kind`]. The next 2 bits select the codec the value was
+/// compressed with, 0 when stored as is, 1 for LZ4 and 2 for Zstandard. The next
+/// bit, [`RecordHeader::EMPTY_VALUE_FLAG`], tells an empty value apart from a
+/// tombstone; it was added in bitask 0.2, older versions read such records as
+/// tombstones. The next bit, [`RecordHeader::BATCH_MARKER_FLAG`], marks the records
+/// framing a batch written by [`Bitask::write_batch`](super::Bitask::write_batch).
+/// The other flag bits are reserved and written as 0. When compressed, `value_size` and `crc`
+/// cover the compressed bytes.
+///
+/// # Examples
+///
+/// ```
+/// use bitask::db::RecordHeader;
+///
+/// let header = RecordHeader::new(0xdead_beef, 1_000, 3, 0, 5);
+/// let mut buffer = [0u8; RecordHeader::SIZE];
+/// header.serialize(&mut buffer)?;
+/// assert_eq!(RecordHeader::deserialize(&buffer)?, header);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// Checksum of the key and value
+    pub crc: u32,
+    /// Timestamp when the record was written, in milliseconds since UNIX epoch
+    pub timestamp: u64,
+    /// Length of the key in bytes, at most [`MAX_KEY_SIZE`]
+    pub key_len: u32,
+    /// Record flags, stored in the top byte of the key length field.
+    /// The low bits hold the [`ChecksumKind`] used for `crc`.
+    pub flags: u8,
+    /// Size of the value in bytes (0 for tombstones and empty values)
+    pub value_size: u32,
+}
+
+impl RecordHeader {
+    /// Size of the header in bytes, computed from its field types.
+    pub const SIZE: usize = std::mem::size_of::<u32>()
+        + std::mem::size_of::<u64>()
+        + std::mem::size_of::<u32>()
+        + std::mem::size_of::<u32>();
+
+    /// Flag set on a record holding an empty value, which would otherwise read as a
+    /// tombstone.
+    pub const EMPTY_VALUE_FLAG: u8 = 0b0001_0000;
+
+    /// Flag set on the begin and commit markers of a batch, which hold no key of
+    /// the database.
+    pub const BATCH_MARKER_FLAG: u8 = 0b0010_0000;
+
+    /// Creates a new record header with the specified metadata.
+    ///
+    /// # Parameters
+    ///
+    /// * `crc` - Checksum of the key and value data
+    /// * `timestamp` - Timestamp when the record was created (milliseconds since UNIX epoch)
+    /// * `key_len` - Length of the key in bytes, at most [`MAX_KEY_SIZE`]
+    /// * `flags` - Record flags
+    /// * `value_len` - Length of the value in bytes (0 for tombstones)
+    ///
+    /// # Returns
+    ///
+    /// Returns a new [`RecordHeader`] initialized with the provided values
+    pub fn new(crc: u32, timestamp: u64, key_len: u32, flags: u8, value_len: u32) -> Self {
+        Self {
+            crc,
+            timestamp,
+            key_len,
+            flags,
+            value_size: value_len,
+        }
+    }
+
+    /// Returns the checksum algorithm selected by the flags, or `None` if this
+    /// version doesn't know it.
+    pub fn checksum_kind(&self) -> Option<ChecksumKind> {
+        ChecksumKind::from_flags(self.flags)
+    }
+
+    /// Returns `true` if the record marks its key as removed.
+    pub fn is_tombstone(&self) -> bool {
+        self.value_size == 0 && self.flags & Self::EMPTY_VALUE_FLAG == 0
+    }
+
+    /// Returns `true` if the record is a batch marker rather than a write.
+    pub fn is_batch_marker(&self) -> bool {
+        self.flags & Self::BATCH_MARKER_FLAG != 0
+    }
+
+    /// Serializes the header into a byte buffer.
+    ///
+    /// The header is written in little-endian byte order with the following layout:
+    /// - Checksum (4 bytes)
+    /// - Timestamp (8 bytes)
+    /// - Key length (3 bytes) and flags (1 byte)
+    /// - Value size (4 bytes)
+    ///
+    /// # Parameters
+    ///
+    /// * `buffer` - The buffer to write the serialized header to, at least
+    ///   [`Self::SIZE`] bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the buffer is smaller than [`Self::SIZE`] bytes
+    pub fn serialize(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        // Verify buffer has enough space
+        if buffer.len() < Self::SIZE {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "buffer too small for header",
+            )));
+        }
+
+        buffer[0..4].copy_from_slice(&self.crc.to_le_bytes());
+        buffer[4..12].copy_from_slice(&self.timestamp.to_le_bytes());
+        let key_len_and_flags = self.key_len | (self.flags as u32) << 24;
+        buffer[12..16].copy_from_slice(&key_len_and_flags.to_le_bytes());
+        buffer[16..20].copy_from_slice(&self.value_size.to_le_bytes());
+        Ok(())
+    }
+
+    /// Deserializes a header from a byte buffer.
+    ///
+    /// # Parameters
+    ///
+    /// * `buf` - Buffer containing the serialized header data (must be at least [`Self::SIZE`] bytes)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if:
+    /// * The buffer is smaller than [`Self::SIZE`] bytes
+    /// * The buffer contains invalid data that can't be converted to header fields
+    ///
+    /// # Panics
+    ///
+    /// Will not panic as buffer size is checked before conversion
+    pub fn deserialize(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < Self::SIZE {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "buffer too small for header",
+            )));
+        }
+
+        let crc = u32::from_le_bytes(buf[0..4].try_into()?);
+        let timestamp = u64::from_le_bytes(buf[4..12].try_into()?);
+        let key_len_and_flags = u32::from_le_bytes(buf[12..16].try_into()?);
+        let value_size = u32::from_le_bytes(buf[16..20].try_into()?);
+
+        Ok(Self {
+            crc,
+            timestamp,
+            key_len: key_len_and_flags & MAX_KEY_SIZE as u32,
+            flags: (key_len_and_flags >> 24) as u8,
+            value_size,
+        })
+    }
+}
@@ -0,0 +1,300 @@
+//! Offline recovery of a damaged database into a fresh one.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    batch::{Marker, PendingBatch},
+    create_dirs, hint,
+    layout::DataFile,
+    layout::Layout,
+    lock_database, Bitask, ChecksumKind, Clock, CompressionKind, Error, FsStorage, KeyDirEntry,
+    Manifest, RecordHeader, Storage, SystemClock,
+};
+
+/// What [`Bitask::repair`] salvaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RepairReport {
+    /// Number of data files read
+    pub files_scanned: usize,
+    /// Number of intact records found, sets and removals
+    pub records_salvaged: usize,
+    /// Number of keys whose latest salvaged record is a value, written to the new database
+    pub keys_recovered: usize,
+    /// Number of damaged stretches skipped between intact records
+    pub corrupt_regions: usize,
+    /// Bytes skipped in damaged stretches
+    pub bytes_skipped: u64,
+}
+
+/// The latest intact record salvaged for a key.
+#[derive(Debug)]
+struct Salvaged {
+    /// Data file holding the record
+    path: PathBuf,
+    /// Offset of the record header in the file
+    offset: u64,
+    /// Header of the record
+    header: RecordHeader,
+}
+
+impl Bitask {
+    /// Rebuilds the database at `src` into a fresh database at `dest`, salvaging every
+    /// intact record.
+    ///
+    /// Meant for disaster recovery when [`Bitask::open`] fails or reads return
+    /// corruption errors. Neither the manifest, hint files nor the key directory are
+    /// trusted: every data file is read record by record and each record's checksum
+    /// verified. Past a damaged record the scan moves forward one byte at a time until
+    /// an intact record is found again. The latest intact record of each key wins, and
+    /// keys whose latest record is a removal are left out. The markers of batches
+    /// written by [`Bitask::write_batch`] are skipped, so the records of a batch cut
+    /// short by a crash are salvaged like any other. `src` is only read, and must not
+    /// be open for writing while it's repaired.
+    ///
+    /// The rebuilt database holds a single sealed file with its hint file, like after
+    /// [`Bitask::merge`].
+    ///
+    /// # Parameters
+    ///
+    /// * `src` - Directory of the damaged database
+    /// * `dest` - Directory to write the rebuilt database to, created if it doesn't exist
+    ///
+    /// # Returns
+    ///
+    /// Returns a [`RepairReport`] of what was salvaged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * `dest` is locked by an open database ([`Error::WriterLock`])
+    /// * `dest` or one of its parents is a file ([`Error::NotADirectory`])
+    /// * `dest` already holds a database ([`Error::Io`])
+    /// * Reading `src` or writing `dest` fails ([`Error::Io`])
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bitask::db::Bitask;
+    ///
+    /// let report = Bitask::repair("my_db", "my_db.repaired")?;
+    /// println!("{} keys recovered", report.keys_recovered);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn repair(src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<RepairReport, Error> {
+        let storage = FsStorage;
+        let src = Layout::new(src);
+        let dest = Layout::new(dest);
+        create_dirs(&storage, &dest)?;
+        // Keeps the rebuilt database from being opened while it's written
+        let _lock = lock_database(&storage, &dest, None)?;
+        if storage.exists(&dest.manifest_path()) || Self::has_data_files(&storage, &dest)? {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already holds a database", dest.dir().display()),
+            )));
+        }
+
+        // Every data file by ID, active and sealed alike
+        let mut files = BTreeMap::new();
+        for name in storage.list(src.dir())? {
+            match src.parse(&name) {
+                Ok(Some(DataFile::Active(file_id))) => {
+                    files.insert(file_id, src.active_log_path(file_id));
+                }
+                Ok(Some(DataFile::Sealed(file_id))) => {
+                    files.insert(file_id, src.log_path(file_id));
+                }
+                // Compaction output never completed, its records are still in the
+                // originals, and files compaction already replaced
+                Ok(Some(DataFile::Compacting(_) | DataFile::Retired(_))) | Ok(None) => (),
+                Err(e) => log::warn!("Skipping file {} during repair: {}", name, e),
+            }
+        }
+
+        let mut report = RepairReport::default();
+        let mut latest: BTreeMap<Vec<u8>, Salvaged> = BTreeMap::new();
+        for path in files.values() {
+            let data = storage.read(path)?;
+            report.files_scanned += 1;
+            salvage_file(&data, &mut report, |key, offset, header| {
+                // Same resolution as replay: a later record with the same timestamp wins
+                if latest
+                    .get(&key)
+                    .is_some_and(|existing| existing.header.timestamp > header.timestamp)
+                {
+                    return;
+                }
+                latest.insert(
+                    key,
+                    Salvaged {
+                        path: path.clone(),
+                        offset,
+                        header,
+                    },
+                );
+            });
+        }
+        latest.retain(|_, salvaged| !salvaged.header.is_tombstone());
+        report.keys_recovered = latest.len();
+
+        // Same crash safety as compaction: the copy is only a database once its
+        // manifest is written, and a leftover temporary file is removed on open
+        let newest = files.keys().next_back().map_or(0, |&file_id| file_id + 1);
+        let file_id = SystemClock.now()?.max(newest);
+        let compacting_path = dest.compacting_path(file_id);
+        let mut writer = BufWriter::new(storage.create(&compacting_path)?);
+        let mut keydir = BTreeMap::new();
+        let mut position = 0;
+        let mut sources = BTreeMap::new();
+        for (key, salvaged) in &latest {
+            let source = match sources.entry(salvaged.path.as_path()) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(storage.open(&salvaged.path)?),
+            };
+            let record_len = RecordHeader::SIZE + key.len() + salvaged.header.value_size as usize;
+            let mut record = vec![0; record_len];
+            source.seek(SeekFrom::Start(salvaged.offset))?;
+            source.read_exact(&mut record)?;
+            writer.write_all(&record)?;
+
+            let value_position = position + RecordHeader::SIZE as u64 + key.len() as u64;
+            keydir.insert(
+                key.clone(),
+                KeyDirEntry {
+                    file_id,
+                    value_size: salvaged.header.value_size,
+                    value_position,
+                    timestamp: salvaged.header.timestamp,
+                    tombstone: false,
+                },
+            );
+            position = value_position + salvaged.header.value_size as u64;
+        }
+        writer.flush()?;
+        writer.get_ref().sync()?;
+        drop(writer);
+        storage.rename(&compacting_path, &dest.log_path(file_id))?;
+        hint::write(&storage, &dest, file_id, position, &keydir)?;
+
+        let active = file_id + 1;
+        storage.create(&dest.active_log_path(active))?.sync()?;
+        Manifest {
+            active,
+            sealed: BTreeSet::from([file_id]),
+        }
+        .write(&storage, &dest)?;
+
+        log::info!(
+            "Repaired {} into {}: {} records salvaged, {} keys recovered, {} bytes skipped",
+            src.dir().display(),
+            dest.dir().display(),
+            report.records_salvaged,
+            report.keys_recovered,
+            report.bytes_skipped
+        );
+        Ok(report)
+    }
+}
+
+/// Visits every intact record of a data file's contents with its key, offset and
+/// header, counting damaged stretches in `report`.
+///
+/// Batches are resolved as replay does: their records are only visited once the
+/// matching commit marker is found, so a batch cut short stays invisible.
+fn salvage_file(
+    data: &[u8],
+    report: &mut RepairReport,
+    mut visit: impl FnMut(Vec<u8>, u64, RecordHeader),
+) {
+    let mut at = 0;
+    let mut in_corrupt_region = false;
+    let mut batch: Option<PendingBatch<(u64, RecordHeader)>> = None;
+    while at < data.len() {
+        if let Some((header, record_len)) = intact_record(&data[at..]) {
+            in_corrupt_region = false;
+            let start = at;
+            at += record_len;
+            let key_end = start + RecordHeader::SIZE + header.key_len as usize;
+            let key = data[start + RecordHeader::SIZE..key_end].to_vec();
+
+            if header.is_batch_marker() {
+                match (Marker::decode(&key, &data[key_end..at]), batch.take()) {
+                    (Some(Marker::Begin { count }), previous) => {
+                        if let Some(previous) = previous {
+                            log::warn!("Dropping uncommitted batch at offset {}", previous.start);
+                        }
+                        batch = Some(PendingBatch::new(start as u64, count));
+                    }
+                    (Some(Marker::Commit { count, crc }), Some(pending))
+                        if pending.matches(count, crc) =>
+                    {
+                        for (key, (offset, header)) in pending.records {
+                            visit(key, offset, header);
+                            report.records_salvaged += 1;
+                        }
+                    }
+                    (_, pending) => {
+                        log::warn!("Skipping unexpected batch marker at offset {}", start);
+                        batch = pending;
+                    }
+                }
+                continue;
+            }
+
+            match &mut batch {
+                Some(pending) if !pending.is_full() => {
+                    let header_bytes = &data[start..start + RecordHeader::SIZE];
+                    pending.push(header_bytes, key, (start as u64, header));
+                }
+                _ => {
+                    if let Some(pending) = batch.take() {
+                        log::warn!("Dropping uncommitted batch at offset {}", pending.start);
+                    }
+                    visit(key, start as u64, header);
+                    report.records_salvaged += 1;
+                }
+            }
+            continue;
+        }
+
+        // Space reserved by preallocation is zeroed up to the end of the file
+        if data[at..].iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if !in_corrupt_region {
+            log::warn!("Skipping damaged data at offset {}", at);
+            report.corrupt_regions += 1;
+            in_corrupt_region = true;
+        }
+        report.bytes_skipped += 1;
+        at += 1;
+    }
+
+    if let Some(pending) = batch {
+        log::warn!("Dropping uncommitted batch at offset {}", pending.start);
+    }
+}
+
+/// Returns the header and length of the record at the start of `data`, or `None` if
+/// there is no intact record there.
+fn intact_record(data: &[u8]) -> Option<(RecordHeader, usize)> {
+    let header = RecordHeader::deserialize(data).ok()?;
+    if header.key_len == 0 {
+        return None;
+    }
+    let key_end = RecordHeader::SIZE + header.key_len as usize;
+    let record_len = key_end + header.value_size as usize;
+    if record_len > data.len() {
+        return None;
+    }
+
+    CompressionKind::from_flags(header.flags).ok()?;
+    let checksum = ChecksumKind::from_flags(header.flags)?;
+    let key = &data[RecordHeader::SIZE..key_end];
+    let value = &data[key_end..record_len];
+    (checksum.checksum(key, value) == header.crc).then_some((header, record_len))
+}
@@ -0,0 +1,122 @@
+//! Thread-safe handle around a [`Bitask`] database.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{Bitask, Error, OpenError, Options};
+
+/// A cloneable, thread-safe handle to a [`Bitask`] database.
+///
+/// [`Bitask`] requires `&mut self` for every operation, so sharing it between threads
+/// means wrapping it in `Arc<Mutex<_>>`. `SharedBitask` packages that pattern: every
+/// clone refers to the same database and operations take `&self`, serialized by an
+/// internal mutex.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bitask::db::SharedBitask;
+///
+/// let db = SharedBitask::open("my_db")?;
+/// let handle = {
+///     let db = db.clone();
+///     std::thread::spawn(move || db.put(b"key".to_vec(), b"value".to_vec()))
+/// };
+/// handle.join().unwrap()?;
+/// assert_eq!(db.ask(b"key")?, b"value");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedBitask {
+    /// The wrapped database, shared by every clone
+    inner: Arc<Mutex<Bitask>>,
+}
+
+impl SharedBitask {
+    /// Wraps an already opened database.
+    pub fn new(db: Bitask) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(db)),
+        }
+    }
+
+    /// Opens a database at `path` and wraps it, see [`Bitask::open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, OpenError> {
+        Bitask::open(path).map(Self::new)
+    }
+
+    /// Opens a database at `path` with `options` and wraps it, see [`Bitask::open_with_options`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::open_with_options`].
+    pub fn open_with_options(path: impl AsRef<Path>, options: Options) -> Result<Self, OpenError> {
+        Bitask::open_with_options(path, options).map(Self::new)
+    }
+
+    /// Retrieves the value associated with `key`, see [`Bitask::ask`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::ask`].
+    pub fn ask(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        self.lock().ask(key)
+    }
+
+    /// Stores a key-value pair, see [`Bitask::put`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::put`].
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.lock().put(key, value)
+    }
+
+    /// Removes a key, see [`Bitask::remove`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::remove`].
+    pub fn remove(&self, key: Vec<u8>) -> Result<(), Error> {
+        self.lock().remove(key)
+    }
+
+    /// Compacts the database, see [`Bitask::compact`].
+    ///
+    /// Goes through a [`Compaction`](super::Compaction) so live entries are copied without holding the
+    /// lock, and other threads keep reading and writing meanwhile. Does nothing while
+    /// another thread compacts.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Bitask::finish_compaction`].
+    pub fn compact(&self) -> Result<(), Error> {
+        let Some(mut compaction) = self.lock().begin_compaction()? else {
+            return Ok(());
+        };
+        compaction.run()?;
+        self.lock().finish_compaction(compaction)?;
+        Ok(())
+    }
+
+    /// Acquires the database lock for the duration of the returned guard.
+    ///
+    /// Useful to run several operations without other threads interleaving.
+    /// A panic in another thread while holding the lock doesn't leave the database in
+    /// an inconsistent state, so a poisoned mutex is recovered rather than propagated.
+    pub fn lock(&self) -> MutexGuard<'_, Bitask> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<Bitask> for SharedBitask {
+    fn from(db: Bitask) -> Self {
+        Self::new(db)
+    }
+}
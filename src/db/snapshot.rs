@@ -0,0 +1,275 @@
+//! Point-in-time, read-only views of a [`Bitask`](super::Bitask) database.
+
+use std::{
+    collections::{btree_map, BTreeMap, HashMap},
+    io,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use super::{read_record, Error, KeyDirEntry, LogReader, Storage};
+
+/// A frozen view of the database at the moment [`Bitask::snapshot`](super::Bitask::snapshot) was called.
+///
+/// The snapshot owns a copy of the key directory and its own readers over every file
+/// that copy points into. Records are never modified in place, so writes, removals and
+/// rotations performed after the snapshot was taken are invisible through it.
+///
+/// Files referenced by the snapshot are pinned until it's dropped, so compaction
+/// doesn't delete them from under it. Files compaction made obsolete in the meantime
+/// are renamed out of the way and deleted when the last snapshot referencing them is
+/// dropped, or on the next open if the process stops first.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut db = bitask::db::Bitask::open("my_db")?;
+/// db.put(b"key".to_vec(), b"old".to_vec())?;
+/// let mut snapshot = db.snapshot()?;
+/// db.put(b"key".to_vec(), b"new".to_vec())?;
+/// assert_eq!(snapshot.ask(b"key")?, b"old");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Snapshot {
+    /// Copy of the key directory at the time of the snapshot
+    keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+    /// Readers for every file referenced by the key directory copy
+    readers: HashMap<u64, LogReader>,
+    /// Pin registry shared with the database, released on drop
+    pins: FilePins,
+    /// Whether reads verify the stored header and key, see [`Options::paranoid_reads`](super::Options::paranoid_reads)
+    paranoid: bool,
+}
+
+impl Snapshot {
+    /// Creates a snapshot from a key directory copy and readers over its files,
+    /// pinning those files in `pins` until the snapshot is dropped.
+    pub(super) fn new(
+        keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+        readers: HashMap<u64, LogReader>,
+        pins: FilePins,
+        paranoid: bool,
+    ) -> Self {
+        pins.pin(readers.keys().copied());
+        Self {
+            keydir,
+            readers,
+            pins,
+            paranoid,
+        }
+    }
+
+    /// Retrieves the value `key` had when the snapshot was taken.
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns the value as a [`Vec<u8>`] if the key existed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if:
+    /// * The key is empty ([`Error::InvalidEmptyKey`])
+    /// * The key didn't exist ([`Error::KeyNotFound`])
+    /// * The data file ends before the value ([`Error::TruncatedValue`])
+    /// * Paranoid reads are on and the record doesn't match ([`Error::RecordMismatch`])
+    /// * The stored checksum doesn't match the record ([`Error::ChecksumMismatch`])
+    /// * IO operations fail ([`Error::Io`])
+    pub fn ask(&mut self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidEmptyKey);
+        }
+
+        let entry = self.keydir.get(key).ok_or(Error::KeyNotFound)?;
+        let reader = self
+            .readers
+            .get_mut(&entry.file_id)
+            .ok_or(Error::FileNotFound(format!("{}", entry.file_id)))?;
+        read_record(reader, key, entry, self.paranoid)
+    }
+
+    /// Returns the number of keys in the snapshot.
+    pub fn len(&self) -> usize {
+        self.keydir.len()
+    }
+
+    /// Returns `true` if the snapshot holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keydir.is_empty()
+    }
+
+    /// Iterates over every key-value pair in the snapshot, in key order.
+    ///
+    /// Values are read from disk as the iterator advances.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let mut snapshot = db.snapshot()?;
+    /// for pair in snapshot.iter() {
+    ///     let (key, value) = pair?;
+    ///     println!("{:?} = {:?}", key, value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn iter(&mut self) -> SnapshotIter<'_> {
+        self.range(..)
+    }
+
+    /// Iterates over the key-value pairs whose keys fall within `range`, in key order.
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - Bounds on the keys to visit
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut db = bitask::db::Bitask::open("my_db")?;
+    /// let mut snapshot = db.snapshot()?;
+    /// for pair in snapshot.range(b"a".to_vec()..b"c".to_vec()) {
+    ///     let (key, value) = pair?;
+    ///     println!("{:?} = {:?}", key, value);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn range<R: RangeBounds<Vec<u8>>>(&mut self, range: R) -> SnapshotIter<'_> {
+        SnapshotIter {
+            entries: self.keydir.range(range),
+            readers: &mut self.readers,
+            paranoid: self.paranoid,
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.pins.unpin(self.readers.keys().copied());
+    }
+}
+
+/// Iterator over the key-value pairs of a [`Snapshot`].
+///
+/// Created by [`Snapshot::iter`] and [`Snapshot::range`]. Each item fails for the
+/// same reasons as [`Snapshot::ask`].
+#[derive(Debug)]
+pub struct SnapshotIter<'a> {
+    /// Remaining key directory entries to visit
+    entries: btree_map::Range<'a, Vec<u8>, KeyDirEntry>,
+    /// Readers owned by the snapshot
+    readers: &'a mut HashMap<u64, LogReader>,
+    /// Whether reads verify the stored header and key
+    paranoid: bool,
+}
+
+impl Iterator for SnapshotIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = self.entries.next()?;
+        let value = match self.readers.get_mut(&entry.file_id) {
+            Some(reader) => read_record(reader, key, entry, self.paranoid),
+            None => Err(Error::FileNotFound(format!("{}", entry.file_id))),
+        };
+        Some(value.map(|value| (key.clone(), value)))
+    }
+}
+
+/// Files pinned by live snapshots, shared between a database and its snapshots,
+/// which may live on other threads.
+#[derive(Debug, Clone)]
+pub(super) struct FilePins {
+    /// Pin counts and deferred removals
+    state: Arc<Mutex<PinState>>,
+    /// Storage deferred files are removed from
+    storage: Arc<dyn Storage>,
+}
+
+/// State behind [`FilePins`].
+#[derive(Debug, Default)]
+struct PinState {
+    /// Number of live snapshots referencing each file ID
+    counts: HashMap<u64, usize>,
+    /// Files compaction wanted to delete while they were pinned
+    deferred: HashMap<u64, PathBuf>,
+}
+
+impl FilePins {
+    /// Creates an empty registry for files held in `storage`.
+    pub(super) fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            state: Arc::default(),
+            storage,
+        }
+    }
+
+    /// Increments the pin count of every file in `file_ids`.
+    pub(super) fn pin(&self, file_ids: impl IntoIterator<Item = u64>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for file_id in file_ids {
+            *state.counts.entry(file_id).or_insert(0) += 1;
+        }
+    }
+
+    /// Decrements the pin count of every file in `file_ids`.
+    ///
+    /// Files no snapshot references anymore are forgotten, and deleted if compaction
+    /// deferred their removal. Leaving them behind would let replay on the next open
+    /// pick up values compaction already discarded.
+    pub(super) fn unpin(&self, file_ids: impl IntoIterator<Item = u64>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for file_id in file_ids {
+            let Some(count) = state.counts.get_mut(&file_id) else {
+                continue;
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+
+            state.counts.remove(&file_id);
+            if let Some(path) = state.deferred.remove(&file_id) {
+                match self.storage.remove(&path) {
+                    Ok(()) => log::debug!("Removed file {} released by a snapshot", file_id),
+                    Err(e) => log::warn!("Failed to remove file {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+
+    /// Defers deleting the file at `path` until no snapshot references `file_id`.
+    ///
+    /// A pinned file is first renamed to `retired_path`, which opening the database
+    /// removes, so a crash before the last snapshot is dropped can't leave it behind
+    /// for replay to pick up. Snapshots keep reading it through their open handles.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the file isn't pinned and can be deleted right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if renaming the pinned file fails.
+    pub(super) fn defer_removal(
+        &self,
+        file_id: u64,
+        path: &Path,
+        retired_path: PathBuf,
+    ) -> io::Result<bool> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.counts.contains_key(&file_id) {
+            return Ok(false);
+        }
+
+        // Renamed under the lock so a concurrent unpin removes the new name
+        self.storage.rename(path, &retired_path)?;
+        state.deferred.insert(file_id, retired_path);
+        Ok(true)
+    }
+}
@@ -0,0 +1,484 @@
+//! Storage backends holding the files of a database.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use fs2::FileExt;
+
+/// Handle keeping the lock of a database held until it's dropped.
+pub type StorageLock = Box<dyn Debug + Send + Sync>;
+
+/// An open file of a [`Storage`] backend.
+///
+/// Every handle has its own position, and handles opened on the same file see each
+/// other's writes, like files opened twice on a filesystem.
+pub trait Segment: Read + Write + Seek + Debug + Send + Sync {
+    /// Returns the current size of the file in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the size can't be read.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Truncates or extends the file to `len` bytes, extending it with zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the file can't be resized.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    /// Pushes written data to stable storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if syncing fails.
+    fn sync(&self) -> io::Result<()>;
+
+    /// Maps the whole file into memory, or returns `None` if the backend can't.
+    ///
+    /// Only called on sealed files, which are never modified.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if mapping fails.
+    #[cfg(feature = "mmap")]
+    fn map(&self) -> io::Result<Option<memmap2::Mmap>> {
+        Ok(None)
+    }
+}
+
+/// Backend storing the files of a database, addressed by path.
+///
+/// Every file the database uses, data files, hints, the manifest and the lock, goes
+/// through the backend set in [`Options::storage`](super::Options::storage), so a
+/// database can live somewhere other than the local filesystem. [`FsStorage`] is used
+/// by default and [`MemStorage`] keeps everything in memory.
+pub trait Storage: Debug + Send + Sync {
+    /// Creates the directory `dir` and its parents if missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the directory can't be created.
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+
+    /// Returns the names of the files in directory `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the directory can't be read.
+    fn list(&self, dir: &Path) -> io::Result<Vec<String>>;
+
+    /// Returns `true` if the file at `path` exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Opens the existing file at `path` for reading, positioned at its start.
+    ///
+    /// Readers get handles of their own, separate from the writer's, so reading never
+    /// moves the position writes go to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] of kind [`io::ErrorKind::NotFound`] if the file is
+    /// missing, or another one if it can't be opened.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Segment>>;
+
+    /// Opens the file at `path` for reading and writing, creating it empty if missing.
+    /// Existing contents are kept.
+    ///
+    /// The handle is positioned at the start of the file and must not be in append
+    /// mode: the database seeks to where the next record goes, which is before any
+    /// space reserved by [`Options::preallocate`](super::Options::preallocate).
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the file can't be opened or created.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Segment>>;
+
+    /// Renames the file at `from` to `to`, replacing any file at `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the file can't be renamed.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] of kind [`io::ErrorKind::NotFound`] if the file is
+    /// missing, or another one if it can't be removed.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Pushes the entries of directory `dir` to stable storage, so files created,
+    /// renamed or removed in it stay that way after a power loss.
+    ///
+    /// Does nothing by default, for backends without directories to sync.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if syncing fails.
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes the exclusive lock named by `path` without waiting.
+    ///
+    /// # Returns
+    ///
+    /// Returns a handle holding the lock, or `None` if someone else holds it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the lock can't be checked.
+    fn try_lock(&self, path: &Path) -> io::Result<Option<StorageLock>>;
+
+    /// Reads the whole file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Storage::open`], or an [`io::Error`] if reading
+    /// fails.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.open(path)?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Returns the size in bytes of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Storage::open`].
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.open(path)?.size()
+    }
+}
+
+/// The local filesystem, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsStorage;
+
+impl Segment for File {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    #[cfg(feature = "mmap")]
+    fn map(&self) -> io::Result<Option<memmap2::Mmap>> {
+        // SAFETY: only sealed files are mapped and bitask never writes to them. A
+        // mapping stays valid after compaction removes its file.
+        unsafe { memmap2::Mmap::map(self) }.map(Some)
+    }
+}
+
+impl Storage for FsStorage {
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<String>> {
+        fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Segment>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Segment>> {
+        // Not in append mode, which would write past preallocated space
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    /// Syncs the directory through a handle opened on it. Directories can't be opened
+    /// that way outside Unix, where this does nothing.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        if cfg!(unix) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Locks the file at `path` with an advisory lock, creating it if missing.
+    fn try_lock(&self, path: &Path) -> io::Result<Option<StorageLock>> {
+        loop {
+            let lock_file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .truncate(false)
+                .open(path)?;
+
+            if lock_file.try_lock_exclusive().is_err() {
+                return Ok(None);
+            }
+            if path.exists() {
+                return Ok(Some(Box::new(lock_file)));
+            }
+            // The previous holder removed the file before we locked it, try a fresh one
+        }
+    }
+}
+
+/// Storage keeping every file in memory, for tests and embedding.
+///
+/// Clones share the same files, so a database can be closed and opened again on a
+/// clone of the storage it was opened with. Nothing survives dropping the last clone.
+///
+/// # Examples
+///
+/// ```
+/// use bitask::db::{Bitask, MemStorage};
+///
+/// let storage = MemStorage::new();
+/// let mut db = Bitask::builder().path("db").storage(storage.clone()).open()?;
+/// db.put(b"key".to_vec(), b"value".to_vec())?;
+/// drop(db);
+///
+/// let mut db = Bitask::builder().path("db").storage(storage).open()?;
+/// assert_eq!(db.ask(b"key")?, b"value");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemStorage {
+    /// Files and locks, shared by every clone
+    state: Arc<Mutex<MemState>>,
+}
+
+/// State behind [`MemStorage`].
+#[derive(Debug, Default)]
+struct MemState {
+    /// Contents of every file by path
+    files: HashMap<PathBuf, Arc<Mutex<Vec<u8>>>>,
+    /// Paths of the locks currently held
+    locks: HashSet<PathBuf>,
+}
+
+impl MemStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the shared state, ignoring poisoning as every update leaves it consistent.
+    fn state(&self) -> std::sync::MutexGuard<'_, MemState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Storage for MemStorage {
+    /// Does nothing, directories exist implicitly.
+    fn create_dir_all(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<String>> {
+        Ok(self
+            .state()
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .filter_map(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.state().files.contains_key(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Segment>> {
+        let data = self.state().files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            )
+        })?;
+        Ok(Box::new(MemSegment {
+            data,
+            position: 0,
+            writable: false,
+        }))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Segment>> {
+        let data = self
+            .state()
+            .files
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+        Ok(Box::new(MemSegment {
+            data,
+            position: 0,
+            writable: true,
+        }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        let data = state.files.remove(from).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", from.display()),
+            )
+        })?;
+        state.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match self.state().files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            )),
+        }
+    }
+
+    fn try_lock(&self, path: &Path) -> io::Result<Option<StorageLock>> {
+        if !self.state().locks.insert(path.to_path_buf()) {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(MemLock {
+            storage: self.clone(),
+            path: path.to_path_buf(),
+        })))
+    }
+}
+
+/// An open file of a [`MemStorage`].
+#[derive(Debug)]
+struct MemSegment {
+    /// Contents of the file, shared with the storage and other handles
+    data: Arc<Mutex<Vec<u8>>>,
+    /// Offset the next read or write starts at
+    position: u64,
+    /// Whether the handle was opened for writing
+    writable: bool,
+}
+
+impl MemSegment {
+    /// Locks the file contents, ignoring poisoning as every update leaves them consistent.
+    fn data(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.data.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Read for MemSegment {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data();
+        let start = (self.position as usize).min(data.len());
+        let read = buf.len().min(data.len() - start);
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        drop(data);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for MemSegment {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file opened for reading",
+            ));
+        }
+
+        let mut data = self.data();
+        let start = self.position as usize;
+        let end = start + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        drop(data);
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemSegment {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.data().len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Segment for MemSegment {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.data().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A lock held on a [`MemStorage`], released on drop.
+#[derive(Debug)]
+struct MemLock {
+    /// Storage the lock is held on
+    storage: MemStorage,
+    /// Path naming the lock
+    path: PathBuf,
+}
+
+impl Drop for MemLock {
+    fn drop(&mut self) {
+        self.storage.state().locks.remove(&self.path);
+    }
+}
@@ -0,0 +1,20 @@
+use bitask::db::{AsyncBitask, Error};
+
+#[tokio::test]
+async fn test_async_put_then_ask() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = AsyncBitask::open(dir.path()).await?;
+
+    db.put(b"key".to_vec(), b"value".to_vec()).await?;
+    assert_eq!(db.ask(b"key".to_vec()).await?, b"value");
+
+    let other = db.clone();
+    other.remove(b"key".to_vec()).await?;
+    assert!(matches!(
+        db.ask(b"key".to_vec()).await,
+        Err(Error::KeyNotFound)
+    ));
+    db.compact().await?;
+
+    Ok(())
+}
@@ -33,4 +33,30 @@ mod tests {
             db.remove(key).unwrap();
         }
     }
+
+    #[test]
+    fn benchmark_small_puts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Bitask::builder()
+            .path(dir.path())
+            .max_file_size(256 * 1024)
+            .open()
+            .unwrap();
+
+        // Small records of varying sizes, with a large one in between
+        let value = |i: usize| vec![(i % 251) as u8; 1 + i % 64];
+        for i in 0..50000 {
+            db.put(format!("key{}", i % 20000).into_bytes(), value(i))
+                .unwrap();
+            if i == 25000 {
+                db.put(b"large".to_vec(), vec![7; 1024 * 1024]).unwrap();
+            }
+        }
+
+        for i in 30000..50000 {
+            let key = format!("key{}", i % 20000).into_bytes();
+            assert_eq!(db.ask(&key).unwrap(), value(i));
+        }
+        assert_eq!(db.ask(b"large").unwrap(), vec![7; 1024 * 1024]);
+    }
 }
@@ -44,12 +44,39 @@ fn test_open_twice() -> anyhow::Result<()> {
     let temp = tempfile::tempdir().unwrap();
     let _db = bitask::db::Bitask::open(temp.path())?;
     match bitask::db::Bitask::open(temp.path()) {
-        Err(bitask::db::Error::WriterLock) => Ok(()),
+        Err(bitask::db::OpenError::Locked) => Ok(()),
         Ok(_) => panic!("Expected second open to fail with lock error"),
-        Err(e) => panic!("Expected WriterLock error, got: {}", e),
+        Err(e) => panic!("Expected Locked error, got: {}", e),
     }
 }
 
+#[test]
+fn test_open_waits_for_lock() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let db = bitask::db::Bitask::open(temp.path())?;
+
+    let result = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .lock_timeout(std::time::Duration::from_millis(20))
+        .open();
+    assert!(matches!(result, Err(bitask::db::OpenError::Locked)));
+
+    let path = temp.path().to_path_buf();
+    let waiter = std::thread::spawn(move || {
+        bitask::db::Bitask::builder()
+            .path(path)
+            .lock_timeout(std::time::Duration::from_secs(5))
+            .open()
+    });
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    drop(db);
+
+    let mut db = waiter.join().unwrap()?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    Ok(())
+}
+
 #[test]
 fn test_ask_key_not_found() -> anyhow::Result<()> {
     setup();
@@ -371,6 +398,3674 @@ fn test_automatic_compaction_trigger() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_put_with_timestamp_out_of_order() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    // Import the newer version first, then an older one
+    db.put_with_timestamp(b"key1".to_vec(), b"newer".to_vec(), 2_000)?;
+    db.put_with_timestamp(b"key1".to_vec(), b"older".to_vec(), 1_000)?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+
+    assert_eq!(db.ask(b"key1")?, b"newer");
+    drop(db);
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key1")?, b"newer");
+    assert_eq!(db.ask(b"key2")?, b"value2");
+    Ok(())
+}
+
+#[test]
+fn test_builder_with_options() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64 * 1024)
+        .durability(bitask::db::Durability::Sync)
+        .auto_compact(true)
+        .open()?;
+
+    // Enough data for a single rotation with the smaller file size
+    let value = vec![42u8; 1024];
+    for i in 0..100 {
+        db.put(format!("key{}", i).into_bytes(), value.clone())?;
+    }
+
+    for i in 0..100 {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, value);
+    }
+
+    let sealed_files = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(".log") && !name.ends_with(".active.log")
+        })
+        .count();
+    assert_eq!(sealed_files, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_builder_without_path() {
+    setup();
+    let result = bitask::db::Bitask::builder().open();
+    assert!(matches!(
+        result,
+        Err(bitask::db::OpenError::Other(bitask::db::Error::MissingPath))
+    ));
+}
+
+#[test]
+fn test_get_or_insert_with() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    let mut calls = 0;
+
+    let value = db.get_or_insert_with(b"key1", || {
+        calls += 1;
+        b"computed".to_vec()
+    })?;
+    assert_eq!(value, b"computed");
+
+    let value = db.get_or_insert_with(b"key1", || {
+        calls += 1;
+        b"recomputed".to_vec()
+    })?;
+    assert_eq!(value, b"computed");
+    assert_eq!(calls, 1, "Expected the closure to run only on a miss");
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key1")?, b"computed");
+    Ok(())
+}
+
+#[test]
+fn test_open_ignores_stray_compacting_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    drop(db);
+
+    // Simulate a crash in the middle of a compaction
+    let stray = temp.path().join("1700000000000.compacting");
+    std::fs::write(&stray, b"partial garbage")?;
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    assert!(
+        !stray.exists(),
+        "Expected the stray compaction file to be removed"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ask_many() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    db.put(b"key3".to_vec(), b"value3".to_vec())?;
+    db.remove(b"key2".to_vec())?;
+
+    let results = db.ask_many(&[b"key3", b"missing", b"key1", b"key2", b""]);
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].as_ref().unwrap(), b"value3");
+    assert!(matches!(results[1], Err(bitask::db::Error::KeyNotFound)));
+    assert_eq!(results[2].as_ref().unwrap(), b"value1");
+    assert!(matches!(results[3], Err(bitask::db::Error::KeyNotFound)));
+    assert!(matches!(
+        results[4],
+        Err(bitask::db::Error::InvalidEmptyKey)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_preallocated_active_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let max_file_size = 64 * 1024;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(max_file_size)
+        .preallocate(true)
+        .open()?;
+
+    let log_files = |sealed: bool| -> anyhow::Result<Vec<u64>> {
+        Ok(std::fs::read_dir(temp.path())?
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                name.ends_with(".log") && name.ends_with(".active.log") != sealed
+            })
+            .map(|entry| entry.metadata().unwrap().len())
+            .collect())
+    };
+
+    // The active file starts at the reserved size
+    assert_eq!(log_files(false)?, vec![max_file_size]);
+
+    // Each record is 20 bytes of header, 6 of key and 1024 of value
+    let value = vec![42u8; 1024];
+    for i in 0..100 {
+        db.put(format!("key{:03}", i).into_bytes(), value.clone())?;
+    }
+
+    // Rotation trims the sealed file back to the records it holds
+    let sealed = log_files(true)?;
+    assert_eq!(sealed.len(), 1);
+    assert_eq!(sealed[0] % 1050, 0);
+    assert!(sealed[0] <= max_file_size && sealed[0] > max_file_size - 1050);
+    assert_eq!(log_files(false)?, vec![max_file_size]);
+
+    for i in 0..100 {
+        assert_eq!(db.ask(format!("key{:03}", i).as_bytes())?, value);
+    }
+
+    // Closing trims the active file too
+    drop(db);
+    let active = log_files(false)?;
+    assert_eq!(active[0] + sealed[0], 100 * 1050);
+
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(max_file_size)
+        .preallocate(true)
+        .open()?;
+    assert_eq!(db.ask(b"key099")?, value);
+    db.put(b"key100".to_vec(), value.clone())?;
+    assert_eq!(db.ask(b"key100")?, value);
+    Ok(())
+}
+
+#[test]
+fn test_first_and_last_key() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.first_key()?, None);
+    assert_eq!(db.last_key()?, None);
+
+    for key in ["m", "c", "x", "a", "q", "z", "b"] {
+        db.put(key.as_bytes().to_vec(), b"value".to_vec())?;
+    }
+    db.remove(b"z".to_vec())?;
+
+    assert_eq!(db.first_key()?, Some(&b"a"[..]));
+    assert_eq!(db.last_key()?, Some(&b"x"[..]));
+    Ok(())
+}
+
+#[test]
+fn test_oldest_and_newest() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.oldest()?, None);
+    assert_eq!(db.newest()?, None);
+
+    for key in ["m", "c", "x", "a", "z"] {
+        db.put(
+            key.as_bytes().to_vec(),
+            format!("value-{}", key).into_bytes(),
+        )?;
+    }
+    db.remove(b"z".to_vec())?;
+
+    assert_eq!(db.oldest()?, Some((b"a".to_vec(), b"value-a".to_vec())));
+    assert_eq!(db.newest()?, Some((b"x".to_vec(), b"value-x".to_vec())));
+
+    // Draining from the front like a queue
+    let mut drained = Vec::new();
+    while let Some((key, _)) = db.oldest()? {
+        db.remove(key.clone())?;
+        drained.push(key);
+    }
+    assert_eq!(drained, [b"a", b"c", b"m", b"x"].map(|key| key.to_vec()));
+    assert_eq!(db.newest()?, None);
+    Ok(())
+}
+
+#[test]
+fn test_checksum_kinds_round_trip() -> anyhow::Result<()> {
+    setup();
+    let kinds = [
+        bitask::db::ChecksumKind::Crc32,
+        bitask::db::ChecksumKind::Crc32c,
+        bitask::db::ChecksumKind::XxHash32,
+    ];
+
+    for kind in kinds {
+        let temp = tempfile::tempdir().unwrap();
+        let mut db = bitask::db::Bitask::builder()
+            .path(temp.path())
+            .checksum(kind)
+            .open()?;
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+        db.put(b"key2".to_vec(), vec![7u8; 64 * 1024])?;
+        db.remove(b"key1".to_vec())?;
+        assert_eq!(db.ask(b"key2")?, vec![7u8; 64 * 1024]);
+        drop(db);
+
+        let mut db = bitask::db::Bitask::builder()
+            .path(temp.path())
+            .checksum(kind)
+            .open()?;
+        assert!(matches!(
+            db.ask(b"key1"),
+            Err(bitask::db::Error::KeyNotFound)
+        ));
+        assert_eq!(db.ask(b"key2")?, vec![7u8; 64 * 1024]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mixed_checksum_kinds() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    drop(db);
+
+    // Records carry their own algorithm, so reopening with another one reads both
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .checksum(bitask::db::ChecksumKind::Crc32c)
+        .open()?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    assert_eq!(db.ask(b"key2")?, b"value2");
+    Ok(())
+}
+
+#[test]
+fn test_checksum_mismatch_detected() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+
+    // Flip the last byte of the value on disk
+    let active = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .unwrap()
+        .path();
+    let mut bytes = std::fs::read(&active)?;
+    *bytes.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&active, bytes)?;
+
+    assert!(matches!(
+        db.ask(b"key1"),
+        Err(bitask::db::Error::ChecksumMismatch { position: 0, .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_truncated_value_error() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), vec![42u8; 100])?;
+
+    // Cut the second value short, as a partial write would
+    let active = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .unwrap()
+        .path();
+    let len = std::fs::metadata(&active)?.len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&active)?
+        .set_len(len - 60)?;
+
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    let error = db.ask(b"key2").unwrap_err();
+    match &error {
+        bitask::db::Error::TruncatedValue {
+            key,
+            expected,
+            available,
+            ..
+        } => {
+            assert_eq!(key, b"key2");
+            assert_eq!(*expected, 100);
+            assert_eq!(*available, 40);
+        }
+        e => panic!("Expected TruncatedValue error, got: {}", e),
+    }
+    assert!(error.to_string().contains("key2"));
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_isolation() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"a".to_vec(), b"1".to_vec())?;
+    db.put(b"b".to_vec(), b"2".to_vec())?;
+    db.put(b"c".to_vec(), b"3".to_vec())?;
+
+    let mut snapshot = db.snapshot()?;
+    db.put(b"a".to_vec(), b"updated".to_vec())?;
+    db.remove(b"b".to_vec())?;
+    db.put(b"d".to_vec(), b"4".to_vec())?;
+
+    assert_eq!(db.ask(b"a")?, b"updated");
+    assert_eq!(snapshot.ask(b"a")?, b"1");
+    assert_eq!(snapshot.ask(b"b")?, b"2");
+    assert!(matches!(
+        snapshot.ask(b"d"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    let pairs = snapshot.iter().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        pairs,
+        vec![
+            (b"a".to_vec(), b"1".to_vec()),
+            (b"b".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"3".to_vec()),
+        ]
+    );
+
+    let pairs = snapshot
+        .range(b"b".to_vec()..)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].0, b"b");
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_pins_files_during_compaction() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+
+    for i in 0..20 {
+        db.put(format!("key{}", i).into_bytes(), vec![1u8; 256])?;
+    }
+    let sealed_files = || -> anyhow::Result<Vec<String>> {
+        let mut names = std::fs::read_dir(temp.path())?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".log") && !name.ends_with(".active.log"))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    };
+    let pinned = sealed_files()?;
+    assert!(pinned.len() >= 2);
+
+    let mut snapshot = db.snapshot()?;
+    for i in 0..10 {
+        db.put(format!("key{}", i).into_bytes(), vec![2u8; 256])?;
+    }
+
+    db.compact()?;
+
+    // Pinned files are moved out of the way of replay until the snapshot drops
+    let remaining = sealed_files()?;
+    for name in &pinned {
+        assert!(
+            !remaining.contains(name),
+            "Pinned file {} is still sealed",
+            name
+        );
+        let retired = temp.path().join(name.replace(".log", ".retired"));
+        assert!(retired.exists(), "Pinned file {} was removed", name);
+    }
+    for i in 0..20 {
+        let key = format!("key{}", i).into_bytes();
+        assert_eq!(snapshot.ask(&key)?, vec![1u8; 256]);
+        let expected = if i < 10 { 2u8 } else { 1u8 };
+        assert_eq!(db.ask(&key)?, vec![expected; 256]);
+    }
+
+    drop(snapshot);
+    db.compact()?;
+
+    let remaining = sealed_files()?;
+    for name in &pinned {
+        assert!(
+            !remaining.contains(name),
+            "File {} was kept after the snapshot dropped",
+            name
+        );
+        let retired = temp.path().join(name.replace(".log", ".retired"));
+        assert!(
+            !retired.exists(),
+            "File {} was kept after the snapshot dropped",
+            name
+        );
+    }
+    for i in 0..20 {
+        let key = format!("key{}", i).into_bytes();
+        let expected = if i < 10 { 2u8 } else { 1u8 };
+        assert_eq!(db.ask(&key)?, vec![expected; 256]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_extend() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+
+    let pairs = (0..50)
+        .map(|i| (format!("key{}", i).into_bytes(), vec![i as u8 + 1; 100]))
+        .collect::<Vec<_>>();
+    db.extend(pairs);
+    db.extend(vec![(b"key0".to_vec(), b"updated".to_vec())]);
+
+    assert_eq!(db.ask(b"key0")?, b"updated");
+    for i in 1..50 {
+        let key = format!("key{}", i).into_bytes();
+        assert_eq!(db.ask(&key)?, vec![i as u8 + 1; 100]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_put_many_rejects_invalid_batch() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let result = db.put_many(vec![
+        (b"key1".to_vec(), b"value1".to_vec()),
+        (b"key2".to_vec(), Vec::new()),
+    ]);
+    assert!(matches!(result, Err(bitask::db::Error::InvalidEmptyValue)));
+    assert!(matches!(
+        db.ask(b"key1"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    assert_eq!(
+        db.put_many(vec![(b"key1".to_vec(), b"value1".to_vec())])?,
+        1
+    );
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    Ok(())
+}
+
+#[test]
+fn test_should_compact_dead_ratio() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .compaction_dead_ratio(0.5)
+        .open()?;
+
+    for i in 0..20 {
+        db.put(format!("key{}", i).into_bytes(), vec![1u8; 200])?;
+    }
+    assert_eq!(db.dead_bytes_ratio()?, 0.0);
+    assert!(!db.should_compact());
+
+    // Overwrite a few keys many times so most sealed bytes are stale
+    for round in 0..20 {
+        for i in 0..5 {
+            db.put(format!("key{}", i).into_bytes(), vec![round as u8; 200])?;
+        }
+    }
+    assert!(db.dead_bytes_ratio()? > 0.5);
+    assert!(db.should_compact());
+
+    db.compact()?;
+    assert!(db.dead_bytes_ratio()? < 0.5);
+    assert!(!db.should_compact());
+    Ok(())
+}
+
+#[test]
+fn test_read_record_at() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.remove(b"key1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+
+    let file_id = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .find_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.strip_suffix(".active.log")
+                .map(|id| id.parse::<u64>().unwrap())
+        })
+        .unwrap();
+
+    let record = db.read_record_at(file_id, 0)?;
+    assert_eq!(record.key, b"key1");
+    assert_eq!(record.value.as_deref(), Some(&b"value1"[..]));
+    assert!(record.checksum_valid);
+
+    let record = db.read_record_at(file_id, record.next_offset)?;
+    assert_eq!(record.key, b"key1");
+    assert_eq!(record.value, None);
+    assert!(record.checksum_valid);
+
+    let record = db.read_record_at(file_id, record.next_offset)?;
+    assert_eq!(record.key, b"key2");
+    assert_eq!(record.value.as_deref(), Some(&b"value2"[..]));
+
+    assert!(matches!(
+        db.read_record_at(file_id, record.next_offset),
+        Err(bitask::db::Error::NoRecordAt { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_tombstone_masks_older_file_on_reopen() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(1024)
+            .open()
+    };
+
+    let mut db = open()?;
+    db.put(b"removed".to_vec(), b"old value".to_vec())?;
+    for i in 0..10 {
+        db.put(format!("key{}", i).into_bytes(), vec![1u8; 200])?;
+    }
+    // The tombstone lands in a newer file than the value it removes
+    db.remove(b"removed".to_vec())?;
+    drop(db);
+
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    for i in 0..10 {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, vec![1u8; 200]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_manifest_lists_sealed_files() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(1024)
+            .open()
+    };
+
+    let mut db = open()?;
+    for i in 0..20 {
+        db.put(format!("key{}", i).into_bytes(), vec![1u8; 200])?;
+    }
+    drop(db);
+
+    let mut sealed = Vec::new();
+    let mut active = None;
+    for entry in std::fs::read_dir(temp.path())? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".active.log") {
+            active = Some(id.to_string());
+        } else if let Some(id) = name.strip_suffix(".log") {
+            sealed.push(id.to_string());
+        }
+    }
+    sealed.sort();
+    assert!(sealed.len() >= 2);
+
+    let manifest = std::fs::read_to_string(temp.path().join("MANIFEST"))?;
+    let mut expected = format!("active {}\n", active.unwrap());
+    for id in &sealed {
+        expected.push_str(&format!("sealed {}\n", id));
+    }
+    assert_eq!(manifest, expected);
+
+    // A directory scan would reject this file name, so opening proves the manifest is used
+    std::fs::write(temp.path().join("unrelated.txt"), b"not a data file")?;
+    let mut db = open()?;
+    for i in 0..20 {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, vec![1u8; 200]);
+    }
+    drop(db);
+
+    // Without the manifest the scan is used, and fails on the unexpected file
+    std::fs::remove_file(temp.path().join("MANIFEST"))?;
+    assert!(matches!(
+        open(),
+        Err(bitask::db::OpenError::Corrupt(
+            bitask::db::Error::TimestampParse { .. }
+        ))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_delete_range() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for key in ["a", "b", "ba", "c", "cz", "d", "e"] {
+        db.put(key.as_bytes().to_vec(), b"value".to_vec())?;
+    }
+
+    assert_eq!(db.delete_range(b"b".to_vec()..b"d".to_vec())?, 4);
+    for key in ["b", "ba", "c", "cz"] {
+        assert!(matches!(
+            db.ask(key.as_bytes()),
+            Err(bitask::db::Error::KeyNotFound)
+        ));
+    }
+    for key in ["a", "d", "e"] {
+        assert_eq!(db.ask(key.as_bytes())?, b"value");
+    }
+
+    assert_eq!(db.delete_range(b"b".to_vec()..b"d".to_vec())?, 0);
+    drop(db);
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert!(matches!(db.ask(b"c"), Err(bitask::db::Error::KeyNotFound)));
+    assert_eq!(db.ask(b"d")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_read_after_rotation() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+
+    // Read from the active file first so its reader is already open
+    db.put(b"before".to_vec(), vec![1u8; 1100])?;
+    assert_eq!(db.ask(b"before")?, vec![1u8; 1100]);
+
+    // The next write rotates the file the first key lives in
+    db.put(b"after".to_vec(), vec![2u8; 100])?;
+    let sealed = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(".log") && !name.ends_with(".active.log")
+        })
+        .count();
+    assert_eq!(sealed, 1);
+
+    assert_eq!(db.ask(b"before")?, vec![1u8; 1100]);
+    assert_eq!(db.ask(b"after")?, vec![2u8; 100]);
+    Ok(())
+}
+
+#[test]
+fn test_metrics() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+    assert_eq!(db.metrics(), bitask::db::Metrics::default());
+
+    // Each record is a 20 byte header, a 4 byte key and its value
+    db.put(b"key1".to_vec(), vec![1u8; 600])?;
+    db.put(b"key2".to_vec(), vec![2u8; 600])?;
+    db.put(b"key3".to_vec(), vec![3u8; 100])?;
+    assert_eq!(db.ask(b"key1")?, vec![1u8; 600]);
+    assert!(db.ask(b"missing").is_err());
+    let results = db.ask_many(&[b"key3", b"nope"]);
+    assert!(results[0].is_ok() && results[1].is_err());
+    db.remove(b"key2".to_vec())?;
+
+    let metrics = db.metrics();
+    assert_eq!(metrics.puts, 3);
+    assert_eq!(metrics.gets, 4);
+    assert_eq!(metrics.get_hits, 2);
+    assert_eq!(metrics.get_misses, 2);
+    assert_eq!(metrics.removes, 1);
+    assert_eq!(metrics.rotations, 1);
+    assert_eq!(metrics.compactions, 0);
+    assert_eq!(metrics.bytes_written, 624 * 2 + 124 + 24);
+    assert_eq!(metrics.bytes_read, 624 + 124);
+    Ok(())
+}
+
+#[test]
+fn test_open_named_namespaces() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut users = bitask::db::Bitask::open_named(temp.path(), "users")?;
+    let mut orders = bitask::db::Bitask::open_named(temp.path(), "orders")?;
+    let mut unnamed = bitask::db::Bitask::open(temp.path())?;
+
+    users.put(b"key".to_vec(), b"user".to_vec())?;
+    orders.put(b"key".to_vec(), b"order".to_vec())?;
+    unnamed.put(b"other".to_vec(), b"unnamed".to_vec())?;
+    assert!(temp.path().join("users.db.lock").exists());
+    assert!(temp.path().join("orders.db.lock").exists());
+
+    assert!(matches!(
+        bitask::db::Bitask::open_named(temp.path(), "users"),
+        Err(bitask::db::OpenError::Locked)
+    ));
+    drop((users, orders, unnamed));
+
+    let mut users = bitask::db::Bitask::open_named(temp.path(), "users")?;
+    let mut orders = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .name("orders")
+        .open()?;
+    let mut unnamed = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(users.ask(b"key")?, b"user");
+    assert_eq!(orders.ask(b"key")?, b"order");
+    assert!(matches!(
+        users.ask(b"other"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert!(matches!(
+        unnamed.ask(b"key"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(unnamed.ask(b"other")?, b"unnamed");
+
+    for name in ["", "123", "a.b", "a/b"] {
+        assert!(matches!(
+            bitask::db::Bitask::open_named(temp.path(), name),
+            Err(bitask::db::OpenError::Other(
+                bitask::db::Error::InvalidName { .. }
+            ))
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_segment_stats() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1200)
+        .open()?;
+    assert!(db.segment_stats()?.is_empty());
+
+    // Each record is a 20 byte header, a 4 byte key and a 276 byte value, so files
+    // hold 4 records
+    for i in 0..8 {
+        db.put(format!("key{}", i).into_bytes(), vec![1u8; 276])?;
+    }
+    let stats = db.segment_stats()?;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].total_bytes, 4 * 300);
+    assert_eq!(stats[0].live_bytes, 4 * 300);
+    assert_eq!(stats[0].live_entries, 4);
+
+    // Overwrite two keys of the sealed file
+    db.put(b"key0".to_vec(), vec![2u8; 276])?;
+    db.put(b"key1".to_vec(), vec![2u8; 276])?;
+    let stats = db.segment_stats()?;
+    assert_eq!(stats[0].total_bytes, 4 * 300);
+    assert_eq!(stats[0].live_bytes, 2 * 300);
+    assert_eq!(stats[0].live_entries, 2);
+    Ok(())
+}
+
+#[test]
+fn test_compact_single_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir().unwrap();
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(1200)
+            .open()
+    };
+    let mut db = open()?;
+
+    // Each record is a 20 byte header, a 4 byte key and a 276 byte value, so files
+    // hold 4 records
+    for i in 0..16 {
+        db.put(format!("k{:03}", i).into_bytes(), vec![1u8; 276])?;
+    }
+    db.put(b"k000".to_vec(), vec![2u8; 276])?;
+    db.put(b"k001".to_vec(), vec![2u8; 276])?;
+
+    let stats = db.segment_stats()?;
+    assert!(stats.len() >= 3);
+    let target = stats[0];
+    assert_eq!(target.live_entries, 2);
+    let others = stats[1..]
+        .iter()
+        .map(|stat| {
+            let path = temp.path().join(format!("{}.log", stat.file_id));
+            Ok((path.clone(), std::fs::read(path)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let result = db.compact_file(target.file_id)?;
+    assert_eq!(result.files_compacted, 1);
+    assert_eq!(result.entries_written, 2);
+    assert_eq!(result.bytes_written, 2 * 300);
+    assert_eq!(result.bytes_reclaimed, 2 * 300);
+
+    assert!(!temp.path().join(format!("{}.log", target.file_id)).exists());
+    for (path, contents) in &others {
+        assert_eq!(&std::fs::read(path)?, contents, "{:?} changed", path);
+    }
+    assert!(matches!(
+        db.compact_file(target.file_id),
+        Err(bitask::db::Error::FileNotFound(_))
+    ));
+
+    let check = |db: &mut bitask::db::Bitask| -> anyhow::Result<()> {
+        for i in 0..16 {
+            let expected = if i < 2 { 2u8 } else { 1u8 };
+            assert_eq!(
+                db.ask(format!("k{:03}", i).as_bytes())?,
+                vec![expected; 276]
+            );
+        }
+        Ok(())
+    };
+    check(&mut db)?;
+    drop(db);
+    check(&mut open()?)?;
+    Ok(())
+}
+
+#[test]
+fn test_mock_clock_orders_records() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock.clone())
+        .open()?;
+    assert!(temp.path().join("1000.active.log").exists());
+
+    db.put(b"key".to_vec(), b"old".to_vec())?;
+    clock.set(2_000);
+    db.put(b"key".to_vec(), b"new".to_vec())?;
+
+    let first = db.read_record_at(1_000, 0)?;
+    let second = db.read_record_at(1_000, first.next_offset)?;
+    assert_eq!(first.timestamp, 1_000);
+    assert_eq!(second.timestamp, 2_000);
+
+    drop(db);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .open()?;
+    assert_eq!(db.ask(b"key")?, b"new");
+    Ok(())
+}
+
+#[test]
+fn test_summary() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .open()?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+
+    let summary = db.summary();
+    assert!(summary.starts_with("2 keys, 0 sealed files"), "{}", summary);
+    assert!(summary.contains("active file 1000"), "{}", summary);
+    Ok(())
+}
+
+#[test]
+fn test_put_if_absent() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    assert!(db.put_if_absent(b"key".to_vec(), b"first".to_vec())?);
+    assert!(!db.put_if_absent(b"key".to_vec(), b"second".to_vec())?);
+    assert_eq!(db.ask(b"key")?, b"first");
+
+    db.remove(b"key".to_vec())?;
+    assert!(db.put_if_absent(b"key".to_vec(), b"third".to_vec())?);
+    assert_eq!(db.ask(b"key")?, b"third");
+
+    assert!(matches!(
+        db.put_if_absent(Vec::new(), b"value".to_vec()),
+        Err(bitask::db::Error::InvalidEmptyKey)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_record_header_public_format() -> anyhow::Result<()> {
+    setup();
+    use bitask::db::{ChecksumKind, RecordHeader};
+
+    let header = RecordHeader::new(42, 1_000, 3, 0, 5);
+    let mut buffer = [0u8; RecordHeader::SIZE];
+    header.serialize(&mut buffer)?;
+    assert_eq!(RecordHeader::SIZE, 20);
+    assert_eq!(&buffer[4..12], &1_000u64.to_le_bytes());
+    assert_eq!(RecordHeader::deserialize(&buffer)?, header);
+
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(2_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .open()?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    db.remove(b"key".to_vec())?;
+    drop(db);
+
+    let bytes = std::fs::read(temp.path().join("2000.active.log"))?;
+    let header = RecordHeader::deserialize(&bytes)?;
+    assert_eq!(header.timestamp, 2_000);
+    assert_eq!(header.key_len, 3);
+    assert_eq!(header.value_size, 5);
+    assert_eq!(header.checksum_kind(), Some(ChecksumKind::default()));
+    assert!(!header.is_tombstone());
+
+    let tombstone = RecordHeader::deserialize(&bytes[RecordHeader::SIZE + 3 + 5..])?;
+    assert!(tombstone.is_tombstone());
+    Ok(())
+}
+
+#[test]
+fn test_merge_keeps_one_copy_per_key() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+
+    for round in 0..5 {
+        for i in 0..10 {
+            let key = format!("key{}", i).into_bytes();
+            db.put(key, format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    db.remove(b"key9".to_vec())?;
+    assert!(db.segment_stats()?.len() > 1);
+
+    let stats = db.merge()?;
+    assert_eq!(stats.entries_written, 9);
+
+    let segments = db.segment_stats()?;
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].live_entries, 9);
+    assert_eq!(segments[0].total_bytes, segments[0].live_bytes);
+    assert_eq!(stats.bytes_written, segments[0].total_bytes);
+
+    let merged_id = segments[0].file_id;
+    assert!(temp.path().join(format!("{}.hint", merged_id)).exists());
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for i in 0..9 {
+        let key = format!("key{}", i).into_bytes();
+        assert_eq!(db.ask(&key)?, format!("value{}-4", i).into_bytes());
+    }
+    assert!(matches!(
+        db.ask(b"key9"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_reopen_picks_up_external_file() -> anyhow::Result<()> {
+    setup();
+    let external = tempfile::tempdir()?;
+    {
+        let mut db = bitask::db::Bitask::builder()
+            .path(external.path())
+            .clock(bitask::db::MockClock::new(500))
+            .open()?;
+        db.put(b"external".to_vec(), b"from outside".to_vec())?;
+        db.put(b"shared".to_vec(), b"old".to_vec())?;
+    }
+
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .open()?;
+    db.put(b"shared".to_vec(), b"new".to_vec())?;
+    db.put(b"local".to_vec(), b"value".to_vec())?;
+
+    std::fs::copy(
+        external.path().join("500.active.log"),
+        temp.path().join("500.log"),
+    )?;
+    assert!(db.ask(b"external").is_err());
+
+    db.reopen()?;
+    assert_eq!(db.ask(b"external")?, b"from outside");
+    assert_eq!(db.ask(b"shared")?, b"new");
+    assert_eq!(db.ask(b"local")?, b"value");
+
+    db.put(b"after".to_vec(), b"reopen".to_vec())?;
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"external")?, b"from outside");
+    assert_eq!(db.ask(b"after")?, b"reopen");
+    Ok(())
+}
+
+#[test]
+fn test_list_keys_pagination() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for i in 0..7 {
+        db.put(format!("key{}", i).into_bytes(), b"value".to_vec())?;
+    }
+    let key = |i: usize| format!("key{}", i).into_bytes();
+
+    let page = db.list_keys(None, 3, false)?;
+    assert_eq!(page, vec![key(0), key(1), key(2)]);
+    let page = db.list_keys(page.last().map(Vec::as_slice), 3, false)?;
+    assert_eq!(page, vec![key(3), key(4), key(5)]);
+    let page = db.list_keys(page.last().map(Vec::as_slice), 3, false)?;
+    assert_eq!(page, vec![key(6)]);
+    assert!(db.list_keys(Some(&key(6)), 3, false)?.is_empty());
+
+    let page = db.list_keys(None, 3, true)?;
+    assert_eq!(page, vec![key(6), key(5), key(4)]);
+    let page = db.list_keys(page.last().map(Vec::as_slice), 3, true)?;
+    assert_eq!(page, vec![key(3), key(2), key(1)]);
+    let page = db.list_keys(page.last().map(Vec::as_slice), 3, true)?;
+    assert_eq!(page, vec![key(0)]);
+
+    assert!(db.list_keys(None, 0, false)?.is_empty());
+    Ok(())
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+fn check_compression_round_trip(kind: bitask::db::CompressionKind) -> anyhow::Result<()> {
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .compression(kind)
+        .open()?;
+
+    let value = b"all work and no play makes jack a dull boy. ".repeat(1000);
+    db.put(b"key".to_vec(), value.clone())?;
+    assert_eq!(db.ask(b"key")?, value);
+
+    let file_size = std::fs::metadata(temp.path().join("1000.active.log"))?.len();
+    assert!(
+        file_size < value.len() as u64 / 10,
+        "{} bytes on disk",
+        file_size
+    );
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, value);
+    Ok(())
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_lz4_compression_round_trip() -> anyhow::Result<()> {
+    setup();
+    check_compression_round_trip(bitask::db::CompressionKind::Lz4)
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_compression_round_trip() -> anyhow::Result<()> {
+    setup();
+    check_compression_round_trip(bitask::db::CompressionKind::Zstd)
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_compression_requires_feature() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .compression(bitask::db::CompressionKind::Zstd)
+        .open()?;
+    assert!(matches!(
+        db.put(b"key".to_vec(), b"value".to_vec()),
+        Err(bitask::db::Error::UnsupportedCompression { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_entry_counter() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    let inc = |value: &mut Vec<u8>| {
+        let count = u64::from_le_bytes(value.as_slice().try_into().unwrap());
+        *value = (count + 1).to_le_bytes().to_vec();
+    };
+
+    for _ in 0..5 {
+        db.entry(b"counter".to_vec())
+            .and_modify(inc)?
+            .or_insert(1u64.to_le_bytes().to_vec())?;
+    }
+    assert_eq!(db.ask(b"counter")?, 5u64.to_le_bytes());
+
+    match db.entry(b"counter".to_vec()) {
+        bitask::db::Entry::Occupied(entry) => {
+            assert_eq!(entry.remove()?, 5u64.to_le_bytes());
+        }
+        bitask::db::Entry::Vacant(_) => panic!("Expected an occupied entry"),
+    }
+    assert!(matches!(
+        db.entry(b"counter".to_vec()),
+        bitask::db::Entry::Vacant(_)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_ask_into_reuses_buffer() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"long".to_vec(), vec![7; 4096])?;
+    db.put(b"short".to_vec(), b"abc".to_vec())?;
+
+    let mut buf = Vec::new();
+    db.ask_into(b"long", &mut buf)?;
+    assert_eq!(buf, vec![7; 4096]);
+    let capacity = buf.capacity();
+
+    db.ask_into(b"short", &mut buf)?;
+    assert_eq!(buf, b"abc");
+    assert_eq!(buf.capacity(), capacity);
+
+    db.ask_into(b"long", &mut buf)?;
+    assert_eq!(buf, vec![7; 4096]);
+    assert_eq!(buf.capacity(), capacity);
+
+    assert!(matches!(
+        db.ask_into(b"missing", &mut buf),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_mem_storage_full_cycle() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let path = temp.path().join("db");
+    let storage = bitask::db::MemStorage::new();
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(&path)
+            .max_file_size(256)
+            .storage(storage.clone())
+            .open()
+    };
+
+    let mut db = open()?;
+    for i in 0..50 {
+        db.put(format!("key{}", i).into_bytes(), vec![i as u8; 16])?;
+    }
+    db.remove(b"key0".to_vec())?;
+    assert!(db.segment_stats()?.len() > 1);
+    db.compact()?;
+    assert_eq!(db.ask(b"key7")?, vec![7; 16]);
+
+    // A second writer is refused while the first holds the lock
+    assert!(matches!(open(), Err(bitask::db::OpenError::Locked)));
+    drop(db);
+
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"key0"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    for i in 1..50 {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, vec![i as u8; 16]);
+    }
+    drop(db);
+
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[test]
+fn test_remove_many() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for i in 0..20 {
+        db.put(format!("key{}", i).into_bytes(), b"value".to_vec())?;
+    }
+
+    assert!(matches!(
+        db.remove_many(vec![b"key0".to_vec(), Vec::new()]),
+        Err(bitask::db::Error::InvalidEmptyKey)
+    ));
+    assert_eq!(db.ask(b"key0")?, b"value");
+
+    let flushes = db.metrics().flushes;
+    let even = (0..20).step_by(2).map(|i| format!("key{}", i).into_bytes());
+    db.remove_many(even.collect())?;
+    assert_eq!(db.metrics().flushes, flushes + 1);
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for i in 0..20 {
+        let value = db.ask(format!("key{}", i).as_bytes());
+        if i % 2 == 0 {
+            assert!(matches!(value, Err(bitask::db::Error::KeyNotFound)));
+        } else {
+            assert_eq!(value?, b"value");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_ask_mmap_reads_sealed_value() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(512)
+        .open()?;
+    for i in 0..20u8 {
+        db.put(vec![b'k', i], vec![i; 100])?;
+    }
+    assert!(!db.segment_stats()?.is_empty());
+
+    // The first key was written before any rotation, so it's in a sealed file
+    let sealed = db.ask_mmap(&[b'k', 0])?;
+    assert!(sealed.is_mapped());
+    assert_eq!(&*sealed, &[0; 100][..]);
+
+    let active = db.ask_mmap(&[b'k', 19])?;
+    assert!(!active.is_mapped());
+    assert_eq!(&*active, &[19; 100][..]);
+
+    for i in 0..20u8 {
+        let value = db.ask_mmap(&[b'k', i])?.to_vec();
+        assert_eq!(value, db.ask(&[b'k', i])?);
+    }
+    assert!(matches!(
+        db.ask_mmap(b"missing"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_sees_buffered_writes() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .durability(bitask::db::Durability::Buffered)
+        .open()?;
+    db.put(b"key".to_vec(), b"old".to_vec())?;
+    db.sync()?;
+
+    // Both records stay in the write buffer
+    db.put(b"key".to_vec(), b"new".to_vec())?;
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+    let mut snapshot = db.snapshot()?;
+    assert_eq!(snapshot.ask(b"key")?, b"new");
+    assert_eq!(snapshot.ask(b"other")?, b"value");
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_ask_mmap_sees_buffered_writes() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .durability(bitask::db::Durability::Buffered)
+        .open()?;
+    db.put(b"key".to_vec(), b"old".to_vec())?;
+    db.sync()?;
+
+    // Both records stay in the write buffer
+    db.put(b"key".to_vec(), b"new".to_vec())?;
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+    assert_eq!(&*db.ask_mmap(b"key")?, b"new");
+    assert_eq!(&*db.ask_mmap(b"other")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_sync_interval() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .durability(bitask::db::Durability::SyncInterval(
+            std::time::Duration::from_millis(100),
+        ))
+        .clock(clock.clone())
+        .open()?;
+
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    clock.advance(50);
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    assert_eq!(db.metrics().syncs, 0);
+
+    // A read after the interval syncs, even without new writes
+    clock.advance(50);
+    assert_eq!(db.ask(b"key")?, b"value");
+    assert_eq!(db.metrics().syncs, 1);
+    db.ask(b"key")?;
+    assert_eq!(db.metrics().syncs, 1);
+
+    clock.advance(100);
+    db.put(b"key".to_vec(), b"other".to_vec())?;
+    assert_eq!(db.metrics().syncs, 2);
+
+    db.sync()?;
+    assert_eq!(db.metrics().syncs, 3);
+    Ok(())
+}
+
+#[test]
+fn test_records_since() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .clock(clock.clone())
+        .open()?;
+
+    db.put(b"a".to_vec(), b"1".to_vec())?;
+    clock.advance(1);
+    db.put(b"b".to_vec(), b"2".to_vec())?;
+    let cursor = 1_001;
+    clock.advance(1);
+    db.remove(b"a".to_vec())?;
+    clock.advance(1);
+    db.put(b"c".to_vec(), b"3".to_vec())?;
+    clock.advance(1);
+    db.put(b"b".to_vec(), b"4".to_vec())?;
+    assert!(!db.segment_stats()?.is_empty());
+
+    let records = db.records_since(cursor)?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        records,
+        vec![
+            (b"a".to_vec(), None),
+            (b"c".to_vec(), Some(b"3".to_vec())),
+            (b"b".to_vec(), Some(b"4".to_vec())),
+        ]
+    );
+
+    assert_eq!(db.records_since(0)?.count(), 5);
+    assert_eq!(db.records_since(1_004)?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_put_with_merge_keeps_larger_number() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    let keep_larger = |current: Option<&[u8]>, incoming: &[u8]| {
+        let decode = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+        match current {
+            Some(current) if decode(current) > decode(incoming) => current.to_vec(),
+            _ => incoming.to_vec(),
+        }
+    };
+
+    db.put_with_merge(b"max".to_vec(), 5u64.to_le_bytes().to_vec(), keep_larger)?;
+    assert_eq!(db.ask(b"max")?, 5u64.to_le_bytes());
+    db.put_with_merge(b"max".to_vec(), 3u64.to_le_bytes().to_vec(), keep_larger)?;
+    assert_eq!(db.ask(b"max")?, 5u64.to_le_bytes());
+    db.put_with_merge(b"max".to_vec(), 9u64.to_le_bytes().to_vec(), keep_larger)?;
+    assert_eq!(db.ask(b"max")?, 9u64.to_le_bytes());
+    Ok(())
+}
+
+#[test]
+fn test_multibyte_keys_around_rotation() -> anyhow::Result<()> {
+    setup();
+    let first_key = "clé-ü".as_bytes().to_vec();
+    let second_key = "键-ß".as_bytes().to_vec();
+    let max_file_size = 100;
+    // The first record ends just before, at and just past the rotation threshold
+    let first_record = bitask::db::RecordHeader::SIZE + first_key.len();
+    for value_len in (max_file_size - first_record - 2)..=(max_file_size - first_record + 2) {
+        let temp = tempfile::tempdir()?;
+        let open = || {
+            bitask::db::Bitask::builder()
+                .path(temp.path())
+                .max_file_size(max_file_size as u64)
+                .open()
+        };
+        let mut db = open()?;
+        db.put(first_key.clone(), vec![1; value_len])?;
+        db.put(second_key.clone(), vec![2; 7])?;
+        assert_eq!(db.ask(&first_key)?, vec![1; value_len]);
+        assert_eq!(db.ask(&second_key)?, vec![2; 7]);
+
+        drop(db);
+        let mut db = open()?;
+        assert_eq!(db.ask(&first_key)?, vec![1; value_len]);
+        assert_eq!(db.ask(&second_key)?, vec![2; 7]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_with_empty_active_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    // State left by a crash right after creating the active file, before the
+    // manifest or any record was written
+    std::fs::File::create(temp.path().join("1000.active.log"))?;
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert!(matches!(
+        db.ask(b"key"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    drop(db);
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    let record = db.read_record_at(1000, 0)?;
+    assert_eq!(record.key, b"key");
+    assert_eq!(record.value, Some(b"value".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn test_error_kind() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let missing = db.ask(b"missing").unwrap_err();
+    assert_eq!(missing.kind(), bitask::db::ErrorKind::NotFound);
+
+    let locked = bitask::db::Bitask::open(temp.path()).unwrap_err();
+    assert_eq!(locked.kind(), bitask::db::ErrorKind::Locked);
+
+    let io = bitask::db::Error::from(std::io::Error::other("disk on fire"));
+    assert_eq!(io.kind(), bitask::db::ErrorKind::Io);
+    let source = std::error::Error::source(&io).expect("io errors chain their source");
+    assert_eq!(source.to_string(), "disk on fire");
+    Ok(())
+}
+
+#[test]
+fn test_compact_to_fresh_directory() -> anyhow::Result<()> {
+    setup();
+    let source = tempfile::tempdir()?;
+    let dest = tempfile::tempdir()?;
+    let dest_path = dest.path().join("copy");
+    let mut db = bitask::db::Bitask::builder()
+        .path(source.path())
+        .max_file_size(256)
+        .open()?;
+
+    for round in 0..5 {
+        for i in 0..10 {
+            let key = format!("key{}", i).into_bytes();
+            db.put(key, format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    db.remove(b"key9".to_vec())?;
+    let source_size = get_dir_size(source.path())?;
+
+    db.compact_to(&dest_path)?;
+    assert!(get_dir_size(&dest_path)? < source_size);
+    assert!(matches!(
+        db.compact_to(&dest_path),
+        Err(bitask::db::Error::Io(_))
+    ));
+
+    // The original is untouched and still usable
+    assert_eq!(get_dir_size(source.path())?, source_size);
+    assert_eq!(db.ask(b"key0")?, b"value0-4");
+
+    let mut copy = bitask::db::Bitask::open(&dest_path)?;
+    let segments = copy.segment_stats()?;
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].live_entries, 9);
+    assert_eq!(segments[0].total_bytes, segments[0].live_bytes);
+    for i in 0..9 {
+        let key = format!("key{}", i).into_bytes();
+        assert_eq!(copy.ask(&key)?, format!("value{}-4", i).into_bytes());
+    }
+    assert!(matches!(
+        copy.ask(b"key9"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_timestamp_range() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock.clone())
+        .open()?;
+    assert_eq!(db.timestamp_range(), None);
+
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    clock.set(2_000);
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    clock.set(3_000);
+    db.put(b"key3".to_vec(), b"value3".to_vec())?;
+    assert_eq!(db.timestamp_range(), Some((1_000, 3_000)));
+
+    // Overwritten and removed values no longer count
+    clock.set(4_000);
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.remove(b"key3".to_vec())?;
+    assert_eq!(db.timestamp_range(), Some((2_000, 4_000)));
+    Ok(())
+}
+
+#[test]
+fn test_buffered_durability_reads_own_writes() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .durability(bitask::db::Durability::Buffered)
+        .open()?;
+
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    // Nothing reached the file yet
+    assert_eq!(db.metrics().flushes, 0);
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    assert_eq!(db.ask(b"key2")?, b"value2");
+    assert_eq!(db.metrics().flushes, 1);
+
+    db.remove(b"key1".to_vec())?;
+    db.put(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(matches!(
+        db.ask(b"key1"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(db.ask(b"key3")?, b"value3");
+
+    // Buffered writes are flushed when the database is closed
+    db.put(b"key4".to_vec(), b"value4".to_vec())?;
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key4")?, b"value4");
+    Ok(())
+}
+
+#[test]
+fn test_compact_keydir_memory() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let mut bound = None;
+    for round in 0..5 {
+        for i in 0..200 {
+            // Keys built with plenty of spare capacity
+            let mut key = Vec::with_capacity(256);
+            key.extend_from_slice(format!("key{}-{}", round, i).as_bytes());
+            db.put(key, b"value".to_vec())?;
+        }
+        for i in 0..150 {
+            db.remove(format!("key{}-{}", round, i).into_bytes())?;
+        }
+
+        let before = db.keydir_memory_bytes();
+        db.compact_keydir_memory();
+        let after = db.keydir_memory_bytes();
+        assert!(after < before, "{} >= {}", after, before);
+
+        // Each round adds the same number of live keys, so memory grows linearly
+        let per_round = *bound.get_or_insert(after);
+        assert!(after <= per_round * (round + 1));
+    }
+
+    assert_eq!(db.ask(b"key4-199")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_compact_dry_run() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+    assert_eq!(db.compact_dry_run()?, bitask::db::CompactionPlan::default());
+
+    for round in 0..5 {
+        for i in 0..10 {
+            let key = format!("key{}", i).into_bytes();
+            db.put(key, format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    let size_before = get_dir_size(temp.path())?;
+
+    let plan = db.compact_dry_run()?;
+    assert!(plan.file_ids.len() > 1);
+    assert!(plan.reclaimable_bytes > 0);
+    let stats = db.segment_stats()?;
+    let total: u64 = stats.iter().map(|stat| stat.total_bytes).sum();
+    assert_eq!(plan.estimated_output_bytes + plan.reclaimable_bytes, total);
+
+    // Nothing was written
+    assert_eq!(get_dir_size(temp.path())?, size_before);
+    assert_eq!(db.segment_stats()?, stats);
+    Ok(())
+}
+
+#[test]
+fn test_max_total_size_rejects_writes() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let limit = 300;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_total_size(limit)
+        .open()?;
+
+    let mut written = 0;
+    let error = loop {
+        let key = format!("key{}", written).into_bytes();
+        match db.put(key, b"value".to_vec()) {
+            Ok(()) => written += 1,
+            Err(e) => break e,
+        }
+        assert!(written < 100, "the size limit was never enforced");
+    };
+    assert!(matches!(
+        error,
+        bitask::db::Error::StorageFull { limit: 300, .. }
+    ));
+    assert_eq!(error.kind(), bitask::db::ErrorKind::Full);
+    // Records of `keyN` take 29 bytes, so ten fit in 300 bytes
+    assert_eq!(written, 10);
+
+    // Keys written before the limit are intact, and removals still go through
+    for i in 0..written {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, b"value");
+    }
+    db.remove(b"key0".to_vec())?;
+    Ok(())
+}
+
+#[test]
+fn test_max_total_size_evicts_oldest() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock.clone())
+        .max_total_size(300)
+        .size_limit_policy(bitask::db::SizeLimitPolicy::EvictOldest)
+        .open()?;
+
+    for i in 0..50 {
+        clock.advance(1);
+        db.put(format!("key{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+
+    // The newest keys survive, the oldest were evicted to make room
+    assert_eq!(db.ask(b"key49")?, b"value");
+    assert!(matches!(
+        db.ask(b"key00"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert!(
+        db.segment_stats()?
+            .iter()
+            .map(|s| s.total_bytes)
+            .sum::<u64>()
+            <= 300
+    );
+    Ok(())
+}
+
+#[test]
+fn test_max_total_size_evicts_least_recently_used() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    // Records of `keyNN` take 30 bytes, so ten fit
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_total_size(300)
+        .size_limit_policy(bitask::db::SizeLimitPolicy::EvictOldest)
+        .eviction(bitask::db::EvictionPolicy::LeastRecentlyUsed)
+        .open()?;
+
+    for i in 0..10 {
+        db.put(format!("key{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+    // Reading the two oldest keys makes them the most recently used
+    db.ask(b"key00")?;
+    db.ask_many(&[b"key01"]);
+
+    for i in 10..13 {
+        db.put(format!("key{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+
+    for key in ["key00", "key01", "key05", "key12"] {
+        assert_eq!(db.ask(key.as_bytes())?, b"value", "{}", key);
+    }
+    for key in ["key02", "key03", "key04"] {
+        assert!(
+            matches!(db.ask(key.as_bytes()), Err(bitask::db::Error::KeyNotFound)),
+            "{}",
+            key
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_with_lock() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(temp.path().join("db.lock"))?;
+    fs2::FileExt::lock_exclusive(&lock)?;
+
+    let mut db =
+        bitask::db::Bitask::open_with_lock(temp.path(), lock, bitask::db::Options::default())?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    assert!(matches!(
+        bitask::db::Bitask::open(temp.path()),
+        Err(bitask::db::OpenError::Locked)
+    ));
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_scan_by_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+
+    // Keys written out of key order, overwritten and removed across several files
+    for round in 0..3 {
+        for i in (0..20).rev() {
+            let key = format!("key{:02}", i).into_bytes();
+            db.put(key, format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    db.put(b"key05".to_vec(), b"latest".to_vec())?;
+    db.remove(b"key07".to_vec())?;
+    assert!(db.segment_stats()?.len() > 1);
+
+    let by_file = db.scan_by_file().collect::<Result<Vec<_>, _>>()?;
+    let mut snapshot = db.snapshot()?;
+    let by_key = snapshot.iter().collect::<Result<Vec<_>, _>>()?;
+
+    assert_eq!(by_file.len(), 19);
+    assert_ne!(by_file, by_key);
+    let mut sorted = by_file.clone();
+    sorted.sort();
+    assert_eq!(sorted, by_key);
+    assert!(by_file.contains(&(b"key05".to_vec(), b"latest".to_vec())));
+
+    // The latest write comes last, since it's at the end of the active file
+    assert_eq!(
+        by_file.last().map(|(key, _)| key.as_slice()),
+        Some(&b"key05"[..])
+    );
+    Ok(())
+}
+
+#[test]
+fn test_open_without_active_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+    for i in 0..30 {
+        db.put(format!("key{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+    let sealed = db.segment_stats()?;
+    assert!(!sealed.is_empty());
+    drop(db);
+
+    // Lose the active file, and with it the keys written since the last rotation
+    for entry in std::fs::read_dir(temp.path())? {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(".active.log") {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.segment_stats()?, sealed);
+    let live: usize = sealed.iter().map(|stat| stat.live_entries).sum();
+    for i in 0..live {
+        assert_eq!(db.ask(format!("key{:02}", i).as_bytes())?, b"value");
+    }
+
+    // The new active file takes writes and survives a reopen
+    db.put(b"new".to_vec(), b"value".to_vec())?;
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"new")?, b"value");
+    assert_eq!(db.segment_stats()?, sealed);
+    Ok(())
+}
+
+#[test]
+fn test_read_only_refresh() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+    db.put(b"key".to_vec(), b"value1".to_vec())?;
+    db.put(b"removed".to_vec(), b"value".to_vec())?;
+
+    let mut replica =
+        bitask::db::ReadOnlyBitask::open(temp.path(), bitask::db::Options::default())?;
+    assert_eq!(replica.ask(b"key")?, b"value1");
+    assert_eq!(replica.len(), 2);
+
+    // Appends, removals and rotations are seen after a refresh
+    db.put(b"key".to_vec(), b"value2".to_vec())?;
+    db.remove(b"removed".to_vec())?;
+    for i in 0..20 {
+        db.put(format!("key{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+    assert!(!db.segment_stats()?.is_empty());
+    assert_eq!(replica.ask(b"key")?, b"value1");
+    replica.refresh()?;
+    assert_eq!(replica.ask(b"key")?, b"value2");
+    assert!(matches!(
+        replica.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(replica.len(), 21);
+
+    // So is a compaction removing the files the replica read
+    db.merge()?;
+    db.put(b"key".to_vec(), b"value3".to_vec())?;
+    replica.refresh()?;
+    assert_eq!(replica.ask(b"key")?, b"value3");
+    assert_eq!(replica.ask(b"key19")?, b"value");
+    assert_eq!(replica.len(), 21);
+    Ok(())
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn test_read_only_watch() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    let options = bitask::db::Options {
+        watch: true,
+        ..bitask::db::Options::default()
+    };
+    let mut replica = bitask::db::ReadOnlyBitask::open(temp.path(), options)?;
+
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        match replica.ask(b"key") {
+            Ok(value) => {
+                assert_eq!(value, b"value");
+                break;
+            }
+            Err(bitask::db::Error::KeyNotFound) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_put_located() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .open()?;
+
+    let mut locations = Vec::new();
+    for i in 0..5 {
+        let key = format!("key{}", i).into_bytes();
+        let location = db.put_located(key.clone(), b"value".to_vec())?;
+        locations.push((key, location));
+    }
+    // Rotations moved later records to new files
+    assert_ne!(locations[0].1.file_id, locations[4].1.file_id);
+
+    for (key, location) in locations {
+        let record = db.read_record_at(location.file_id, location.offset)?;
+        assert_eq!(record.key, key);
+        assert_eq!(record.value.as_deref(), Some(&b"value"[..]));
+        assert_eq!(record.timestamp, location.timestamp);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_empty_values_are_stored_apart_from_removals() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+
+    // Rejected unless enabled
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert!(matches!(
+        db.put(b"member".to_vec(), Vec::new()),
+        Err(bitask::db::Error::InvalidEmptyValue)
+    ));
+    drop(db);
+
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .allow_empty_values(true)
+        .open()?;
+    db.put(b"member".to_vec(), Vec::new())?;
+    db.put(b"removed".to_vec(), Vec::new())?;
+    db.remove(b"removed".to_vec())?;
+    assert_eq!(db.ask(b"member")?, Vec::<u8>::new());
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    // Replaying the log tells the empty value and the removal apart
+    drop(db);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .allow_empty_values(true)
+        .open()?;
+    assert_eq!(db.ask(b"member")?, Vec::<u8>::new());
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    // And so does compaction
+    db.compact()?;
+    assert_eq!(db.ask(b"member")?, Vec::<u8>::new());
+    db.remove(b"member".to_vec())?;
+    assert!(matches!(
+        db.ask(b"member"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_bulk_ingest() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let count = 100_000u32;
+    let pairs = (0..count).map(|i| {
+        (
+            format!("key{}", i).into_bytes(),
+            format!("value{}", i).into_bytes(),
+        )
+    });
+    assert_eq!(db.bulk_ingest(pairs)?, count as usize);
+
+    let sample = (0..count).step_by(997).chain([count - 1]);
+    for i in sample.clone() {
+        assert_eq!(
+            db.ask(format!("key{}", i).as_bytes())?,
+            format!("value{}", i).into_bytes()
+        );
+    }
+
+    // The load survives a reopen
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    for i in sample {
+        assert_eq!(
+            db.ask(format!("key{}", i).as_bytes())?,
+            format!("value{}", i).into_bytes()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_compact_with_progress() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(16 * 1024)
+        .open()?;
+    for round in 0..2 {
+        for i in 0..2000 {
+            db.put(
+                format!("key{}", i).into_bytes(),
+                format!("value{}-{}", i, round).into_bytes(),
+            )?;
+        }
+    }
+
+    let mut updates = Vec::new();
+    let stats = db.compact_with_progress(|progress| updates.push(progress))?;
+    assert!(stats.files_compacted >= 2);
+
+    // Reported more than once, growing, and ending on the totals
+    assert!(updates.len() > 1);
+    assert!(updates.windows(2).all(
+        |pair| pair[0].entries_processed <= pair[1].entries_processed
+            && pair[0].bytes_written <= pair[1].bytes_written
+            && pair[0].files_remaining >= pair[1].files_remaining
+    ));
+    let last = updates.last().unwrap();
+    assert_eq!(last.entries_processed, stats.entries_written);
+    assert_eq!(last.bytes_written, stats.bytes_written);
+    assert_eq!(last.files_remaining, 0);
+
+    for i in (0..2000).step_by(199) {
+        assert_eq!(
+            db.ask(format!("key{}", i).as_bytes())?,
+            format!("value{}-1", i).into_bytes()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_repair_salvages_intact_records() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let src = temp.path().join("src");
+    let dest = temp.path().join("dest");
+
+    let mut db = bitask::db::Bitask::builder()
+        .path(&src)
+        .max_file_size(256)
+        .open()?;
+    let mut damaged = None;
+    for i in 0..30 {
+        let key = format!("key{}", i).into_bytes();
+        let location = db.put_located(key, format!("value{}", i).into_bytes())?;
+        if i == 10 {
+            damaged = Some(location);
+        }
+    }
+    db.remove(b"key20".to_vec())?;
+    drop(db);
+
+    // Overwrite the value of key10 in the middle of its (now sealed) file
+    let damaged = damaged.unwrap();
+    let path = src.join(format!("{}.log", damaged.file_id));
+    let mut bytes = std::fs::read(&path)?;
+    let value_at = damaged.offset as usize + bitask::db::RecordHeader::SIZE + b"key10".len();
+    bytes[value_at..value_at + 3].copy_from_slice(b"XXX");
+    std::fs::write(&path, bytes)?;
+
+    let report = bitask::db::Bitask::repair(&src, &dest)?;
+    assert_eq!(report.corrupt_regions, 1);
+    assert!(report.bytes_skipped > 0);
+    // 30 sets and a removal, less the damaged record
+    assert_eq!(report.records_salvaged, 30);
+    assert_eq!(report.keys_recovered, 28);
+
+    let mut repaired = bitask::db::Bitask::open(&dest)?;
+    for i in (0..30).filter(|&i| i != 10 && i != 20) {
+        assert_eq!(
+            repaired.ask(format!("key{}", i).as_bytes())?,
+            format!("value{}", i).into_bytes()
+        );
+    }
+    assert!(matches!(
+        repaired.ask(b"key10"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert!(matches!(
+        repaired.ask(b"key20"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    // The rebuilt directory isn't overwritten by a second repair
+    drop(repaired);
+    assert!(bitask::db::Bitask::repair(&src, &dest).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_file_ids_unique_within_a_millisecond() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(1)
+            .clock(bitask::db::MockClock::new(1_000))
+            .open()
+    };
+
+    // A file the handle doesn't track holds the ID the next rotation would pick
+    drop(open()?);
+    let stray = temp.path().join("1001.log");
+    std::fs::write(&stray, b"not ours")?;
+
+    // The clock is frozen, so every rotation happens within the same millisecond
+    let mut db = open()?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    db.put(b"key3".to_vec(), b"value3".to_vec())?;
+
+    let mut logs: Vec<_> = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".log"))
+        .collect();
+    logs.sort();
+    assert_eq!(
+        logs,
+        ["1000.log", "1001.log", "1002.log", "1003.active.log"]
+    );
+    assert_eq!(std::fs::read(&stray)?, b"not ours");
+    for i in 1..=3 {
+        assert_eq!(
+            db.ask(format!("key{}", i).as_bytes())?,
+            format!("value{}", i).into_bytes()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_history() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .clock(clock.clone())
+        .open()?;
+
+    for version in 1..=3 {
+        db.put(b"key".to_vec(), format!("value{}", version).into_bytes())?;
+        db.put(b"other".to_vec(), b"noise".to_vec())?;
+        clock.advance(10);
+    }
+    db.remove(b"key".to_vec())?;
+
+    assert_eq!(
+        db.history(b"key")?,
+        vec![
+            (1_000, Some(b"value1".to_vec())),
+            (1_010, Some(b"value2".to_vec())),
+            (1_020, Some(b"value3".to_vec())),
+            (1_030, None),
+        ]
+    );
+    assert!(db.history(b"missing")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_event_log() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .open()?;
+
+    db.put(b"a".to_vec(), b"1".to_vec())?;
+    db.put(b"b".to_vec(), b"1".to_vec())?;
+    db.put(b"a".to_vec(), b"2".to_vec())?;
+    db.remove(b"b".to_vec())?;
+    db.put(b"a".to_vec(), b"3".to_vec())?;
+    db.remove(b"missing".to_vec())?;
+
+    let events = db.event_log()?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(events.len(), 6);
+
+    let stream: Vec<_> = events
+        .iter()
+        .map(|event| (event.key.as_slice(), event.value.as_deref()))
+        .collect();
+    assert_eq!(
+        stream,
+        [
+            (&b"a"[..], Some(&b"1"[..])),
+            (b"b", Some(b"1")),
+            (b"a", Some(b"2")),
+            (b"b", None),
+            (b"a", Some(b"3")),
+            (b"missing", None),
+        ]
+    );
+
+    // Every record of `a` after the first links to the one it overwrites
+    let chain: Vec<_> = events.iter().filter(|event| event.key == b"a").collect();
+    assert_eq!(chain[0].previous, None);
+    assert_eq!(chain[1].previous, Some(chain[0].timestamp));
+    assert_eq!(chain[2].previous, Some(chain[1].timestamp));
+
+    // Physical order: file by file, offset by offset
+    assert!(events
+        .windows(2)
+        .all(|pair| (pair[0].file_id, pair[0].offset) < (pair[1].file_id, pair[1].offset)));
+    Ok(())
+}
+
+#[test]
+fn test_data_subdir() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .data_subdir("data")
+            .max_file_size(64)
+            .open()
+    };
+    let names = |dir: &std::path::Path| -> anyhow::Result<Vec<String>> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        Ok(names)
+    };
+
+    let mut db = open()?;
+    for i in 0..10 {
+        db.put(format!("key{}", i).into_bytes(), b"value".to_vec())?;
+    }
+    db.put(b"key0".to_vec(), b"updated".to_vec())?;
+    db.merge()?;
+
+    // Only the lock, the manifest and the data directory at the top
+    assert_eq!(names(temp.path())?, ["MANIFEST", "data", "db.lock"]);
+    let data = names(&temp.path().join("data"))?;
+    assert!(data.iter().any(|name| name.ends_with(".active.log")));
+    assert!(data.iter().any(|name| name.ends_with(".hint")));
+
+    drop(db);
+    let mut db = open()?;
+    assert_eq!(db.ask(b"key0")?, b"updated");
+    assert_eq!(db.ask(b"key9")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_file_summary() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let record = |key: &[u8], value: &[u8]| {
+        (bitask::db::RecordHeader::SIZE + key.len() + value.len()) as u64
+    };
+    let total_bytes =
+        record(b"key1", b"value1") * 4 + record(b"key1", b"updated") + record(b"key2", b"");
+    // Fits the 6 records below, so the next write seals the file
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(total_bytes)
+        .open()?;
+
+    // 4 sets, an overwrite and a removal
+    let file_id = db
+        .put_located(b"key1".to_vec(), b"value1".to_vec())?
+        .file_id;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    db.put(b"key3".to_vec(), b"value3".to_vec())?;
+    db.put(b"key1".to_vec(), b"updated".to_vec())?;
+    db.remove(b"key2".to_vec())?;
+    db.put(b"key4".to_vec(), b"value4".to_vec())?;
+    let next = db
+        .put_located(b"key5".to_vec(), b"value5".to_vec())?
+        .file_id;
+    assert_ne!(next, file_id);
+
+    let summary = db.file_summary(file_id)?;
+    assert_eq!(summary.file_id, file_id);
+    assert_eq!(summary.total_records, 6);
+    assert_eq!(summary.live_records, 3);
+    assert_eq!(summary.total_bytes, total_bytes);
+    assert_eq!(
+        summary.live_bytes,
+        record(b"key1", b"updated") + record(b"key3", b"value3") + record(b"key4", b"value4")
+    );
+
+    // The active file can be inspected too, unknown files can't
+    assert_eq!(db.file_summary(next)?.live_records, 1);
+    assert!(matches!(
+        db.file_summary(file_id + 1_000_000),
+        Err(bitask::db::Error::FileNotFound(_))
+    ));
+    Ok(())
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn test_register_metrics() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    let registry = prometheus::Registry::new();
+    db.register_metrics(&registry)?;
+
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    db.ask(b"key1")?;
+    assert!(db.ask(b"missing").is_err());
+    db.remove(b"key2".to_vec())?;
+
+    let value = |name: &str| {
+        registry
+            .gather()
+            .iter()
+            .find(|family| family.name() == name)
+            .map(|family| family.get_metric()[0].get_counter().get_value())
+    };
+    assert_eq!(value("bitask_puts_total"), Some(2.0));
+    assert_eq!(value("bitask_gets_total"), Some(2.0));
+    assert_eq!(value("bitask_get_hits_total"), Some(1.0));
+    assert_eq!(value("bitask_get_misses_total"), Some(1.0));
+    assert_eq!(value("bitask_removes_total"), Some(1.0));
+    assert_eq!(
+        value("bitask_bytes_written_total"),
+        Some(db.metrics().bytes_written as f64)
+    );
+
+    // The same names can't be registered twice
+    assert!(db.register_metrics(&registry).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_write_batch_is_atomic_on_crash() -> anyhow::Result<()> {
+    use bitask::db::{Bitask, Op};
+
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = Bitask::open(temp.path())?;
+    db.put(b"from".to_vec(), b"100".to_vec())?;
+    db.put(b"pending".to_vec(), b"yes".to_vec())?;
+
+    db.write_batch(vec![
+        Op::Put(b"from".to_vec(), b"90".to_vec()),
+        Op::Put(b"to".to_vec(), b"10".to_vec()),
+        Op::Remove(b"pending".to_vec()),
+    ])?;
+    assert_eq!(db.ask(b"from")?, b"90");
+    assert_eq!(db.ask(b"to")?, b"10");
+    assert!(db.ask(b"pending").is_err());
+    drop(db);
+
+    // A committed batch is replayed
+    let mut db = Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"from")?, b"90");
+    assert_eq!(db.ask(b"to")?, b"10");
+    assert!(db.ask(b"pending").is_err());
+
+    db.write_batch(vec![
+        Op::Put(b"from".to_vec(), b"0".to_vec()),
+        Op::Put(b"to".to_vec(), b"100".to_vec()),
+        Op::Put(b"new".to_vec(), b"key".to_vec()),
+        Op::Remove(b"from".to_vec()),
+    ])?;
+    drop(db);
+
+    // Crash before the commit marker is fully written
+    let active = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .expect("active file exists")
+        .path();
+    let file = std::fs::OpenOptions::new().write(true).open(&active)?;
+    file.set_len(file.metadata()?.len() - 1)?;
+    drop(file);
+
+    let mut db = Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"from")?, b"90");
+    assert_eq!(db.ask(b"to")?, b"10");
+    assert!(db.ask(b"new").is_err());
+
+    // The uncommitted batch is dropped, so later writes replay normally
+    db.put(b"after".to_vec(), b"crash".to_vec())?;
+    drop(db);
+    let mut db = Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"after")?, b"crash");
+    assert_eq!(db.ask(b"from")?, b"90");
+    assert!(db.ask(b"new").is_err());
+
+    // Invalid operations reject the whole batch
+    assert!(matches!(
+        db.write_batch(vec![
+            Op::Put(b"valid".to_vec(), b"value".to_vec()),
+            Op::Remove(Vec::new()),
+        ]),
+        Err(bitask::db::Error::InvalidEmptyKey)
+    ));
+    assert!(db.ask(b"valid").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_public_consts() -> anyhow::Result<()> {
+    use bitask::db::{consts, RecordHeader};
+
+    setup();
+    assert_eq!(consts::RECORD_HEADER_SIZE, 20);
+    assert_eq!(RecordHeader::SIZE, consts::RECORD_HEADER_SIZE);
+    assert_eq!(consts::MAX_KEY_SIZE, bitask::db::MAX_KEY_SIZE);
+    assert_eq!(
+        bitask::db::Options::default().max_file_size,
+        consts::MAX_ACTIVE_FILE_SIZE
+    );
+
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    assert!(temp.path().join(consts::LOCK_FILE_NAME).exists());
+    assert!(temp.path().join(consts::MANIFEST_FILE_NAME).exists());
+    Ok(())
+}
+
+#[test]
+fn test_compact_is_idempotent() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+    for round in 0..3 {
+        for i in 0..100 {
+            db.put(
+                format!("key{}", i).into_bytes(),
+                format!("value{}-{}", i, round).into_bytes(),
+            )?;
+        }
+    }
+
+    // Every file with its contents
+    let files = || -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let mut files = std::fs::read_dir(temp.path())?
+            .map(|entry| {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                Ok((name, std::fs::read(entry.path())?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        files.sort();
+        Ok(files)
+    };
+
+    let stats = db.compact_with_progress(|_| ())?;
+    assert!(stats.files_compacted >= 2);
+    let after_first = files()?;
+
+    let mut updates = 0;
+    let stats = db.compact_with_progress(|_| updates += 1)?;
+    assert_eq!(stats, bitask::db::CompactionStats::default());
+    assert_eq!(updates, 0);
+    db.compact()?;
+    assert_eq!(files()?, after_first);
+
+    assert_eq!(db.ask(b"key42")?, b"value42-2");
+    Ok(())
+}
+
+#[test]
+fn test_tombstones() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let clock = bitask::db::MockClock::new(1_000);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock.clone())
+        .max_file_size(64)
+        .open()?;
+
+    for key in [b"a", b"b", b"c", b"d"] {
+        db.put(key.to_vec(), b"value".to_vec())?;
+    }
+    clock.set(2_000);
+    db.remove(b"a".to_vec())?;
+    db.remove(b"c".to_vec())?;
+    clock.set(3_000);
+    db.remove(b"b".to_vec())?;
+    // Masked by a later set
+    clock.set(4_000);
+    db.put(b"c".to_vec(), b"again".to_vec())?;
+    // Removing a key that never existed still writes a tombstone
+    db.remove(b"z".to_vec())?;
+
+    let expected = vec![
+        (b"a".to_vec(), 2_000),
+        (b"b".to_vec(), 3_000),
+        (b"z".to_vec(), 4_000),
+    ];
+    assert_eq!(db.tombstones()?, expected);
+
+    drop(db);
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .clock(clock)
+        .open()?;
+    assert_eq!(db.tombstones()?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_open_rw_or_ro() -> anyhow::Result<()> {
+    use bitask::db::{Bitask, OpenedBitask};
+
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut writer = match Bitask::open_rw_or_ro(temp.path())? {
+        OpenedBitask::ReadWrite(db) => db,
+        OpenedBitask::ReadOnly(_) => panic!("expected a writer on a free database"),
+    };
+    writer.put(b"key".to_vec(), b"value".to_vec())?;
+
+    // The writer holds the lock, so only reading is possible
+    let OpenedBitask::ReadOnly(mut reader) = Bitask::open_rw_or_ro(temp.path())? else {
+        panic!("expected a read-only handle while the writer is open");
+    };
+    assert_eq!(reader.ask(b"key")?, b"value");
+
+    drop(reader);
+    drop(writer);
+    assert!(matches!(
+        Bitask::open_rw_or_ro(temp.path())?,
+        OpenedBitask::ReadWrite(_)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_rotate_before_exceeding_max_file_size() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1024)
+        .open()?;
+
+    let first = db.put_located(b"small".to_vec(), b"value".to_vec())?;
+    // 20 + 4 + 1000 bytes fit the limit alone, but not after the first record
+    let near_limit = db.put_located(b"near".to_vec(), vec![1u8; 1000])?;
+    assert_ne!(near_limit.file_id, first.file_id);
+    assert_eq!(near_limit.offset, 0);
+    assert_eq!(db.file_summary(first.file_id)?.total_bytes, 30);
+
+    // Exactly at the limit, so the next record goes to yet another file
+    let next = db.put_located(b"next".to_vec(), b"value".to_vec())?;
+    assert_ne!(next.file_id, near_limit.file_id);
+    assert_eq!(db.file_summary(near_limit.file_id)?.total_bytes, 1024);
+
+    // A record larger than the limit gets a file of its own
+    let oversized = db.put_located(b"large".to_vec(), vec![2u8; 2048])?;
+    assert_ne!(oversized.file_id, next.file_id);
+    assert_eq!(oversized.offset, 0);
+    let after = db.put_located(b"after".to_vec(), b"value".to_vec())?;
+    assert_ne!(after.file_id, oversized.file_id);
+
+    assert_eq!(db.ask(b"near")?, vec![1u8; 1000]);
+    assert_eq!(db.ask(b"large")?, vec![2u8; 2048]);
+    Ok(())
+}
+
+#[test]
+fn test_rename() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"draft".to_vec(), b"content".to_vec())?;
+    db.put(b"published".to_vec(), b"old".to_vec())?;
+
+    db.rename(b"draft", b"published".to_vec())?;
+    assert_eq!(db.ask(b"published")?, b"content");
+    assert!(matches!(
+        db.ask(b"draft"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    assert!(matches!(
+        db.rename(b"draft", b"other".to_vec()),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    db.rename(b"published", b"published".to_vec())?;
+    assert_eq!(db.ask(b"published")?, b"content");
+
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"published")?, b"content");
+    assert!(db.ask(b"draft").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_read_buffer_size() -> anyhow::Result<()> {
+    setup();
+    let large = (0..64 * 1024u32).map(|i| i as u8).collect::<Vec<_>>();
+    for read_buffer_size in [0, 64, 1024 * 1024] {
+        let temp = tempfile::tempdir()?;
+        let open = || {
+            bitask::db::Bitask::builder()
+                .path(temp.path())
+                .max_file_size(16 * 1024)
+                .read_buffer_size(read_buffer_size)
+                .paranoid_reads(true)
+                .open()
+        };
+        let mut db = open()?;
+        for i in 0..20 {
+            db.put(format!("small{}", i).into_bytes(), vec![i; 100])?;
+        }
+        db.put(b"large".to_vec(), large.clone())?;
+        db.put(b"after".to_vec(), b"value".to_vec())?;
+
+        // Values in sealed files and the active file, before and after reopening
+        for _ in 0..2 {
+            assert_eq!(db.ask(b"large")?, large);
+            assert_eq!(db.ask(b"after")?, b"value");
+            for i in (0..20).rev() {
+                assert_eq!(db.ask(format!("small{}", i).as_bytes())?, vec![i; 100]);
+            }
+            let mut snapshot = db.snapshot()?;
+            assert_eq!(snapshot.ask(b"large")?, large);
+            drop(snapshot);
+            drop(db);
+            db = open()?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_interleaved_writes_and_reads() -> anyhow::Result<()> {
+    setup();
+    for durability in [
+        bitask::db::Durability::Flush,
+        bitask::db::Durability::Buffered,
+    ] {
+        let temp = tempfile::tempdir()?;
+        let mut db = bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(2048)
+            .durability(durability)
+            .preallocate(true)
+            .open()?;
+
+        // Every read moves a reader over the active file between two writes to it
+        db.put(b"anchor".to_vec(), b"first".to_vec())?;
+        for i in 0..200u32 {
+            let key = format!("key{}", i).into_bytes();
+            db.put(key.clone(), format!("value{}", i).into_bytes())?;
+            assert_eq!(db.ask(&key)?, format!("value{}", i).into_bytes());
+            assert_eq!(db.ask(b"anchor")?, b"first");
+            if i % 3 == 0 {
+                db.remove(key.clone())?;
+                assert!(db.ask(&key).is_err());
+            }
+        }
+        drop(db);
+
+        let mut db = bitask::db::Bitask::open(temp.path())?;
+        assert_eq!(db.ask(b"anchor")?, b"first");
+        for i in 0..200u32 {
+            let value = db.ask(format!("key{}", i).as_bytes());
+            match i % 3 {
+                0 => assert!(value.is_err()),
+                _ => assert_eq!(value?, format!("value{}", i).into_bytes()),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_file_path_is_not_a_directory() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let file = temp.path().join("not_a_dir");
+    std::fs::write(&file, b"contents")?;
+
+    for path in [file.clone(), file.join("db")] {
+        match bitask::db::Bitask::open(&path) {
+            Err(bitask::db::OpenError::NotADirectory { path: reported }) => {
+                assert_eq!(reported, file);
+            }
+            other => panic!("expected NotADirectory, got {:?}", other),
+        }
+    }
+    let error = bitask::db::Bitask::open(&file).unwrap_err();
+    assert_eq!(error.kind(), bitask::db::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("is not a directory"));
+    assert_eq!(std::fs::read(&file)?, b"contents");
+    Ok(())
+}
+
+#[test]
+fn test_seal_active() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let written = db.put_located(b"key".to_vec(), b"value".to_vec())?;
+    let sealed = db.seal_active()?;
+    assert_eq!(sealed, written.file_id);
+    assert!(temp.path().join(format!("{}.log", sealed)).exists());
+    assert!(!temp.path().join(format!("{}.active.log", sealed)).exists());
+
+    let active: Vec<_> = std::fs::read_dir(temp.path())?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .collect();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].metadata()?.len(), 0);
+
+    // The new active file is empty, so there's nothing more to seal
+    let active_id = db.seal_active()?;
+    assert_ne!(active_id, sealed);
+    assert_eq!(db.seal_active()?, active_id);
+
+    assert_eq!(db.ask(b"key")?, b"value");
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_open_error_locked() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let _db = bitask::db::Bitask::open(temp.path())?;
+
+    let error =
+        match bitask::db::Bitask::open_with_options(temp.path(), bitask::db::Options::default()) {
+            Err(error @ bitask::db::OpenError::Locked) => error,
+            other => panic!("expected OpenError::Locked, got {:?}", other.map(|_| ())),
+        };
+    assert_eq!(error.kind(), bitask::db::ErrorKind::Locked);
+
+    // Converts into the operational error for callers mixing both
+    let error: bitask::db::Error = error.into();
+    assert!(matches!(error, bitask::db::Error::WriterLock));
+    Ok(())
+}
+
+#[test]
+fn test_hash_keys_with_long_keys() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let key = |i: usize| format!("https://example.com/{}/{}", "path/".repeat(60), i).into_bytes();
+    let open = |dir: &std::path::Path, hash_keys: bool| {
+        bitask::db::Bitask::builder()
+            .path(dir)
+            .max_file_size(64 * 1024)
+            .hash_keys(hash_keys)
+            .open()
+    };
+
+    let mut plain = open(&temp.path().join("plain"), false)?;
+    let mut hashed = open(&temp.path().join("hashed"), true)?;
+    for db in [&mut plain, &mut hashed] {
+        for i in 0..200 {
+            db.put(key(i), format!("value{}", i).into_bytes())?;
+        }
+        for i in 0..50 {
+            db.put(key(i), format!("updated{}", i).into_bytes())?;
+        }
+        db.remove_many((150..200).map(key).collect())?;
+    }
+    assert!(hashed.keydir_memory_bytes() * 5 < plain.keydir_memory_bytes());
+
+    let check = |db: &mut bitask::db::Bitask| -> anyhow::Result<()> {
+        assert_eq!(db.scan_by_file().count(), 150);
+        assert_eq!(db.ask(&key(7))?, b"updated7");
+        assert_eq!(db.ask(&key(120))?, b"value120");
+        assert!(matches!(
+            db.ask(&key(170)),
+            Err(bitask::db::Error::KeyNotFound)
+        ));
+        assert!(matches!(
+            db.ask(&key(999)),
+            Err(bitask::db::Error::KeyNotFound)
+        ));
+        Ok(())
+    };
+    check(&mut hashed)?;
+
+    // Compaction moves the records the slots point at, reopening rebuilds the slots
+    hashed.compact()?;
+    check(&mut hashed)?;
+    let pairs: Vec<_> = hashed.scan_by_file().collect::<Result<_, _>>()?;
+    assert_eq!(pairs.len(), 150);
+    assert!(pairs.contains(&(key(120), b"value120".to_vec())));
+    drop(hashed);
+    let mut hashed = open(&temp.path().join("hashed"), true)?;
+    check(&mut hashed)?;
+
+    // Operations needing key order are refused
+    assert!(matches!(
+        hashed.first_key(),
+        Err(bitask::db::Error::KeysHashed)
+    ));
+    assert!(matches!(
+        hashed.last_key(),
+        Err(bitask::db::Error::KeysHashed)
+    ));
+    assert!(matches!(
+        hashed.list_keys(None, 10, false),
+        Err(bitask::db::Error::KeysHashed)
+    ));
+    assert!(matches!(
+        hashed.snapshot(),
+        Err(bitask::db::Error::KeysHashed)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_ask_reader_streams_large_value() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    let value: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    db.put(b"large".to_vec(), value.clone())?;
+    db.put(b"after".to_vec(), b"next".to_vec())?;
+
+    use std::io::Read;
+
+    let mut reader = db.ask_reader(b"large")?;
+    let mut read = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        read.extend_from_slice(&chunk[..n]);
+    }
+    drop(reader);
+    assert_eq!(read.len(), value.len());
+    assert!(read == value);
+
+    let mut small = String::new();
+    db.ask_reader(b"after")?.read_to_string(&mut small)?;
+    assert_eq!(small, "next");
+    assert!(matches!(
+        db.ask_reader(b"missing"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+/// Filesystem storage counting directory syncs.
+#[derive(Debug, Clone, Default)]
+struct DirSyncCounter(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+impl bitask::db::Storage for DirSyncCounter {
+    fn create_dir_all(&self, dir: &Path) -> std::io::Result<()> {
+        bitask::db::FsStorage.create_dir_all(dir)
+    }
+
+    fn list(&self, dir: &Path) -> std::io::Result<Vec<String>> {
+        bitask::db::FsStorage.list(dir)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        bitask::db::FsStorage.exists(path)
+    }
+
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn bitask::db::Segment>> {
+        bitask::db::FsStorage.open(path)
+    }
+
+    fn create(&self, path: &Path) -> std::io::Result<Box<dyn bitask::db::Segment>> {
+        bitask::db::FsStorage.create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        bitask::db::FsStorage.rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        bitask::db::FsStorage.remove(path)
+    }
+
+    fn try_lock(&self, path: &Path) -> std::io::Result<Option<bitask::db::StorageLock>> {
+        bitask::db::FsStorage.try_lock(path)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> std::io::Result<()> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        bitask::db::FsStorage.sync_dir(dir)
+    }
+}
+
+#[test]
+fn test_rotation_syncs_directory() -> anyhow::Result<()> {
+    use bitask::db::{Durability, Storage};
+    use std::sync::atomic::Ordering;
+
+    setup();
+    let temp = tempfile::tempdir()?;
+    let storage = DirSyncCounter::default();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .durability(Durability::Sync)
+        .storage(storage.clone())
+        .open()?;
+
+    let first = db.put_located(b"key1".to_vec(), vec![1; 40])?;
+    let synced = storage.0.load(Ordering::SeqCst);
+    db.put(b"key2".to_vec(), vec![2; 40])?;
+    assert!(storage.0.load(Ordering::SeqCst) > synced);
+
+    // The active file was renamed and a new one created, leaving no orphan behind
+    let names: Vec<String> = storage
+        .list(temp.path())?
+        .into_iter()
+        .filter(|name| name.ends_with(".log"))
+        .collect();
+    assert!(names.contains(&format!("{}.log", first.file_id)));
+    assert!(!names.contains(&format!("{}.active.log", first.file_id)));
+    assert_eq!(
+        names
+            .iter()
+            .filter(|name| name.ends_with(".active.log"))
+            .count(),
+        1
+    );
+
+    db.put(b"key2".to_vec(), vec![3; 40])?;
+    let synced = storage.0.load(Ordering::SeqCst);
+    db.compact()?;
+    assert!(storage.0.load(Ordering::SeqCst) > synced);
+    drop(db);
+
+    // Without syncing writes, directories aren't synced either
+    let storage = DirSyncCounter::default();
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(64)
+        .storage(storage.clone())
+        .open()?;
+    db.put(b"key3".to_vec(), vec![3; 40])?;
+    db.put(b"key4".to_vec(), vec![4; 40])?;
+    db.compact()?;
+    assert_eq!(storage.0.load(Ordering::SeqCst), 0);
+    assert_eq!(db.ask(b"key1")?, vec![1; 40]);
+    Ok(())
+}
+
+#[test]
+fn test_exists() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    assert!(!bitask::db::Bitask::exists(temp.path()));
+    assert!(!bitask::db::Bitask::exists(temp.path().join("missing")));
+
+    let db = bitask::db::Bitask::open(temp.path())?;
+    // Checking doesn't need the lock the open database holds
+    assert!(bitask::db::Bitask::exists(temp.path()));
+    drop(db);
+    assert!(bitask::db::Bitask::exists(temp.path()));
+    Ok(())
+}
+
+#[test]
+fn test_compaction_with_interleaved_writes() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(512)
+            .open()
+    };
+    let key = |i: usize| format!("key{:03}", i).into_bytes();
+
+    let mut db = open()?;
+    for round in 0..3 {
+        for i in 0..60 {
+            db.put(key(i), format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    let mut compaction = db.begin_compaction()?.expect("enough sealed files");
+    assert!(db.begin_compaction()?.is_none());
+
+    // Writes go on between beginning, running and finishing the compaction
+    let mut expected = std::collections::BTreeMap::new();
+    for i in 0..60 {
+        expected.insert(key(i), format!("value{}-2", i).into_bytes());
+    }
+    for i in (0..60).step_by(3) {
+        db.put(key(i), b"overwritten".to_vec())?;
+        expected.insert(key(i), b"overwritten".to_vec());
+    }
+    compaction.run()?;
+    for i in (1..60).step_by(3) {
+        db.remove(key(i))?;
+        expected.remove(&key(i));
+    }
+    for i in 60..80 {
+        db.put(key(i), b"new".to_vec())?;
+        expected.insert(key(i), b"new".to_vec());
+    }
+    let stats = db.finish_compaction(compaction)?;
+    assert!(stats.files_compacted >= 2);
+    // Entries still in the active file when the compaction began aren't copied
+    assert!((1..60).contains(&stats.entries_written));
+
+    let check = |db: &mut bitask::db::Bitask| -> anyhow::Result<()> {
+        for i in 0..80 {
+            match expected.get(&key(i)) {
+                Some(value) => assert_eq!(&db.ask(&key(i))?, value),
+                None => assert!(matches!(
+                    db.ask(&key(i)),
+                    Err(bitask::db::Error::KeyNotFound)
+                )),
+            }
+        }
+        Ok(())
+    };
+    check(&mut db)?;
+    db.compact()?;
+    check(&mut db)?;
+    drop(db);
+
+    let mut db = open()?;
+    check(&mut db)?;
+    let leftovers = std::fs::read_dir(temp.path())?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".compacting"))
+        .count();
+    assert_eq!(leftovers, 0);
+    Ok(())
+}
+
+#[test]
+fn test_dropped_compaction_leaves_database_unchanged() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .max_file_size(256)
+            .open()
+    };
+
+    let mut db = open()?;
+    for i in 0..30 {
+        db.put(format!("key{}", i).into_bytes(), vec![i as u8; 32])?;
+    }
+    let sealed = db.segment_stats()?.len();
+    let mut compaction = db.begin_compaction()?.expect("enough sealed files");
+    compaction.run()?;
+    db.put(b"key0".to_vec(), b"changed".to_vec())?;
+    drop(compaction);
+
+    assert!(db.segment_stats()?.len() >= sealed);
+    assert_eq!(db.ask(b"key0")?, b"changed");
+    assert_eq!(db.ask(b"key29")?, vec![29; 32]);
+    assert!(db.begin_compaction()?.is_some());
+    drop(db);
+
+    let names: Vec<String> = std::fs::read_dir(temp.path())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    assert!(!names.iter().any(|name| name.ends_with(".compacting")));
+
+    let mut db = open()?;
+    assert_eq!(db.ask(b"key0")?, b"changed");
+    for i in 1..30 {
+        assert_eq!(db.ask(format!("key{}", i).as_bytes())?, vec![i as u8; 32]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_close_releases_lock() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .durability(bitask::db::Durability::Buffered)
+            .preallocate(true)
+            .open()
+    };
+
+    let mut db = open()?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    assert!(matches!(open(), Err(bitask::db::OpenError::Locked)));
+    db.close()?;
+    assert!(!temp.path().join("db.lock").exists());
+
+    let mut db = open()?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+    db.close()?;
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"other")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_compact_files_merges_only_given_files() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(256)
+        .open()?;
+
+    for round in 0..2 {
+        for i in 0..12 {
+            db.put(
+                format!("key{:02}", i).into_bytes(),
+                format!("value{}-{}", i, round).into_bytes(),
+            )?;
+        }
+    }
+    let stats = db.segment_stats()?;
+    assert!(stats.len() >= 3);
+    let (first, second, third) = (stats[0].file_id, stats[1].file_id, stats[2].file_id);
+    let third_path = temp.path().join(format!("{}.log", third));
+    let third_bytes = std::fs::read(&third_path)?;
+
+    let active = db
+        .put_located(b"key00".to_vec(), b"latest".to_vec())?
+        .file_id;
+    assert!(matches!(
+        db.compact_files(&[first, active]),
+        Err(bitask::db::Error::ActiveFile { file_id }) if file_id == active
+    ));
+    assert!(matches!(
+        db.compact_files(&[first, 42]),
+        Err(bitask::db::Error::FileNotFound(_))
+    ));
+    assert!(temp.path().join(format!("{}.log", first)).exists());
+
+    let merged = db.compact_files(&[first, second, first])?;
+    assert_eq!(merged.files_compacted, 2);
+    assert!(!temp.path().join(format!("{}.log", first)).exists());
+    assert!(!temp.path().join(format!("{}.log", second)).exists());
+    assert_eq!(std::fs::read(&third_path)?, third_bytes);
+
+    let file_ids: Vec<u64> = db
+        .segment_stats()?
+        .iter()
+        .map(|stat| stat.file_id)
+        .collect();
+    assert!(file_ids.contains(&third));
+    assert_eq!(db.ask(b"key00")?, b"latest");
+    for i in 1..12 {
+        assert_eq!(
+            db.ask(format!("key{:02}", i).as_bytes())?,
+            format!("value{}-1", i).into_bytes()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_compact_file_keeps_removal_of_older_value() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || bitask::db::Bitask::builder().path(temp.path()).open();
+    let mut db = open()?;
+
+    db.put(b"removed".to_vec(), b"old".to_vec())?;
+    db.put(b"kept".to_vec(), b"value".to_vec())?;
+    db.seal_active()?;
+    db.remove(b"removed".to_vec())?;
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+    let with_removal = db.seal_active()?;
+
+    let stats = db.compact_file(with_removal)?;
+    assert_eq!(stats.entries_written, 1);
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+
+    drop(db);
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(db.ask(b"kept")?, b"value");
+    assert_eq!(db.ask(b"other")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_compact_files_keeps_removal_of_older_value() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || bitask::db::Bitask::builder().path(temp.path()).open();
+    let mut db = open()?;
+
+    db.put(b"removed".to_vec(), b"old".to_vec())?;
+    let oldest = db.seal_active()?;
+    db.remove(b"removed".to_vec())?;
+    db.put(b"a".to_vec(), b"value".to_vec())?;
+    let second = db.seal_active()?;
+    db.put(b"b".to_vec(), b"value".to_vec())?;
+    let third = db.seal_active()?;
+
+    assert!(matches!(
+        db.compact_files(&[second, 7]),
+        Err(bitask::db::Error::FileNotFound(_))
+    ));
+    db.compact_files(&[second, third])?;
+    assert!(temp.path().join(format!("{}.log", oldest)).exists());
+
+    drop(db);
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(db.ask(b"a")?, b"value");
+    assert_eq!(db.ask(b"b")?, b"value");
+
+    // Once every sealed file is merged, the tombstone is dropped for good
+    db.compact()?;
+    assert!(db.tombstones()?.is_empty());
+    drop(db);
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_merges_wait_for_pending_compaction() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    // A frozen clock gives every record the same timestamp, so only the order files
+    // are replayed in tells the removal is newer than the frozen value
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .hash_keys(true)
+            .clock(bitask::db::MockClock::new(1_000))
+            .open()
+    };
+    let mut db = open()?;
+
+    db.put(b"removed".to_vec(), b"frozen".to_vec())?;
+    db.seal_active()?;
+    db.put(b"kept".to_vec(), b"value".to_vec())?;
+    let sealed = db.seal_active()?;
+
+    let mut compaction = db.begin_compaction()?.expect("enough sealed files");
+    db.remove(b"removed".to_vec())?;
+    db.seal_active()?;
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+    db.seal_active()?;
+
+    // Merging the file holding the tombstone would drop it
+    db.compact()?;
+    assert_eq!(
+        db.compact_with_progress(|_| ())?,
+        bitask::db::CompactionStats::default()
+    );
+    for result in [
+        db.compact_file(sealed),
+        db.compact_files(&[sealed]),
+        db.merge(),
+    ] {
+        assert!(matches!(result, Err(bitask::db::Error::CompactionPending)));
+    }
+
+    compaction.run()?;
+    db.finish_compaction(compaction)?;
+    drop(db);
+    let mut db = open()?;
+    assert!(matches!(
+        db.ask(b"removed"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(db.ask(b"kept")?, b"value");
+    assert_eq!(db.ask(b"other")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_compaction_output_gets_unused_file_id() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let open = || {
+        bitask::db::Bitask::builder()
+            .path(temp.path())
+            .clock(bitask::db::MockClock::new(1_000))
+            .open()
+    };
+
+    // The clock is frozen, so the clock alone would give the merged file the ID of
+    // a file that already exists
+    let mut db = open()?;
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    assert_eq!(db.seal_active()?, 1_000);
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    assert_eq!(db.seal_active()?, 1_001);
+    let stray = temp.path().join("1003.compacting");
+    std::fs::write(&stray, b"not ours")?;
+
+    db.compact()?;
+    let file_ids: Vec<u64> = db
+        .segment_stats()?
+        .iter()
+        .map(|stat| stat.file_id)
+        .collect();
+    assert_eq!(file_ids, [1_004]);
+    assert_eq!(std::fs::read(&stray)?, b"not ours");
+
+    drop(db);
+    let mut db = open()?;
+    assert_eq!(db.ask(b"key1")?, b"value1");
+    assert_eq!(db.ask(b"key2")?, b"value2");
+    Ok(())
+}
+
+#[test]
+fn test_open_keeps_data_past_zeroed_header() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    drop(db);
+
+    let active = std::fs::read_dir(temp.path())?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .expect("active file exists")
+        .path();
+    let records = std::fs::read(&active)?;
+
+    // Zeros alone are left as they are without preallocation
+    let mut contents = records.clone();
+    contents.extend_from_slice(&[0; 64]);
+    std::fs::write(&active, &contents)?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    assert_eq!(std::fs::metadata(&active)?.len(), contents.len() as u64);
+    drop(db);
+
+    // A zeroed header followed by data isn't space a crash leaves behind
+    contents.extend_from_slice(b"more data");
+    std::fs::write(&active, &contents)?;
+    assert!(matches!(
+        bitask::db::Bitask::open(temp.path()),
+        Err(bitask::db::OpenError::Corrupt(bitask::db::Error::CorruptedEntry { position, .. }))
+            if position == records.len() as u64
+    ));
+    assert_eq!(std::fs::read(&active)?, contents);
+    Ok(())
+}
+
+#[test]
+fn test_extend_reports_failure_on_sync() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+
+    db.extend(vec![
+        (b"key1".to_vec(), b"value1".to_vec()),
+        (Vec::new(), b"value".to_vec()),
+    ]);
+    db.extend(vec![(b"key2".to_vec(), Vec::new())]);
+    db.extend(vec![(b"key3".to_vec(), b"value3".to_vec())]);
+    assert!(matches!(
+        db.ask(b"key1"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    assert_eq!(db.ask(b"key3")?, b"value3");
+
+    // The first failure is returned once, then syncing succeeds again
+    assert!(matches!(db.sync(), Err(bitask::db::Error::InvalidEmptyKey)));
+    db.sync()?;
+
+    db.extend(vec![(Vec::new(), b"value".to_vec())]);
+    assert!(matches!(
+        db.close(),
+        Err(bitask::db::Error::InvalidEmptyKey)
+    ));
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key3")?, b"value3");
+    Ok(())
+}
+
+#[test]
+fn test_put_and_remove_reject_oversized_key() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+
+    let key = vec![b'k'; bitask::db::MAX_KEY_SIZE + 1];
+    assert!(matches!(
+        db.put(key.clone(), b"value".to_vec()),
+        Err(bitask::db::Error::KeyTooLarge { .. })
+    ));
+    assert!(matches!(
+        db.remove(key),
+        Err(bitask::db::Error::KeyTooLarge { .. })
+    ));
+
+    // Nothing was written, so the log still replays
+    drop(db);
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    Ok(())
+}
+
+#[test]
+fn test_crash_with_snapshot_leaves_no_merged_file_behind() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1)
+        .open()?;
+    db.put(b"k".to_vec(), b"value".to_vec())?;
+    db.remove(b"k".to_vec())?;
+    db.put(b"other".to_vec(), b"value".to_vec())?;
+
+    // The snapshot pins the file holding the value while compaction drops the tombstone
+    let snapshot = db.snapshot()?;
+    db.compact()?;
+    assert!(matches!(db.ask(b"k"), Err(bitask::db::Error::KeyNotFound)));
+
+    // A crash never drops the snapshot, and a lost manifest makes open scan the files
+    std::mem::forget(snapshot);
+    drop(db);
+    std::fs::remove_file(temp.path().join("MANIFEST"))?;
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert!(matches!(db.ask(b"k"), Err(bitask::db::Error::KeyNotFound)));
+    assert_eq!(db.ask(b"other")?, b"value");
+    let leftovers = std::fs::read_dir(temp.path())?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".retired"))
+        .count();
+    assert_eq!(leftovers, 0);
+    Ok(())
+}
+
+#[test]
+fn test_repair_drops_uncommitted_batch() -> anyhow::Result<()> {
+    use bitask::db::{Bitask, Op};
+
+    setup();
+    let temp = tempfile::tempdir()?;
+    let src = temp.path().join("src");
+    let dest = temp.path().join("dest");
+
+    let mut db = Bitask::open(&src)?;
+    db.put(b"from".to_vec(), b"100".to_vec())?;
+    db.write_batch(vec![
+        Op::Put(b"from".to_vec(), b"90".to_vec()),
+        Op::Put(b"to".to_vec(), b"10".to_vec()),
+    ])?;
+    db.write_batch(vec![
+        Op::Put(b"from".to_vec(), b"0".to_vec()),
+        Op::Put(b"to".to_vec(), b"100".to_vec()),
+        Op::Put(b"new".to_vec(), b"key".to_vec()),
+    ])?;
+    drop(db);
+
+    // Crash before the commit marker of the second batch is fully written
+    let active = std::fs::read_dir(&src)?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().ends_with(".active.log"))
+        .expect("active file exists")
+        .path();
+    let file = std::fs::OpenOptions::new().write(true).open(&active)?;
+    file.set_len(file.metadata()?.len() - 1)?;
+    drop(file);
+
+    let report = Bitask::repair(&src, &dest)?;
+    // The first put and the committed batch
+    assert_eq!(report.records_salvaged, 3);
+
+    let mut repaired = Bitask::open(&dest)?;
+    assert_eq!(repaired.ask(b"from")?, b"90");
+    assert_eq!(repaired.ask(b"to")?, b"10");
+    assert!(matches!(
+        repaired.ask(b"new"),
+        Err(bitask::db::Error::KeyNotFound)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_removals_rotate_active_file() -> anyhow::Result<()> {
+    setup();
+    let temp = tempfile::tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(100)
+        .open()?;
+
+    // Tombstones of `keyNN` take 25 bytes, so four fit in a file
+    for i in 0..20 {
+        db.remove(format!("key{:02}", i).into_bytes())?;
+    }
+    db.remove_many(
+        (20..24)
+            .map(|i| format!("key{:02}", i).into_bytes())
+            .collect(),
+    )?;
+    drop(db);
+
+    let mut sealed = 0;
+    for entry in std::fs::read_dir(temp.path())? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".log") {
+            assert!(entry.metadata()?.len() <= 100, "{} is too large", name);
+            sealed += usize::from(!name.ends_with(".active.log"));
+        }
+    }
+    assert_eq!(sealed, 5);
+    Ok(())
+}
+
 fn get_dir_size(path: impl AsRef<Path>) -> anyhow::Result<u64> {
     let mut total_size = 0;
     for entry in std::fs::read_dir(path)? {
@@ -0,0 +1,90 @@
+use std::sync::{Mutex, Once};
+
+use tempfile::tempdir;
+
+/// Logger that keeps every record in memory so tests can inspect them
+struct CapturingLogger {
+    records: Mutex<Vec<(log::Level, String)>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    records: Mutex::new(Vec::new()),
+};
+static INIT: Once = Once::new();
+
+fn setup() {
+    INIT.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+}
+
+fn logged(level: log::Level, needle: &str) -> bool {
+    LOGGER
+        .records
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(l, message)| *l == level && message.contains(needle))
+}
+
+#[test]
+fn test_lifecycle_events_are_logged() -> anyhow::Result<()> {
+    setup();
+    let temp = tempdir()?;
+    let mut db = bitask::db::Bitask::builder()
+        .path(temp.path())
+        .max_file_size(1)
+        .open()?;
+    assert!(logged(log::Level::Info, "Opened database"));
+
+    // The second put finds the active file over the limit and rotates
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+    db.put(b"key2".to_vec(), b"value2".to_vec())?;
+    assert!(logged(log::Level::Debug, "Rotated active file"));
+
+    drop(db);
+    assert!(logged(log::Level::Info, "Closed database"));
+    Ok(())
+}
+
+#[test]
+fn test_skipped_records_are_logged() -> anyhow::Result<()> {
+    setup();
+    let temp = tempdir()?;
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    db.put(b"key".to_vec(), b"value".to_vec())?;
+    drop(db);
+
+    // A header promising more bytes than the file holds, as a crash mid-write leaves
+    let active = std::fs::read_dir(temp.path())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .find(|path| path.to_string_lossy().ends_with(".active.log"))
+        .expect("active file");
+    let mut file = std::fs::OpenOptions::new().append(true).open(&active)?;
+    std::io::Write::write_all(&mut file, &[0xff; 24])?;
+    drop(file);
+
+    let mut db = bitask::db::Bitask::open(temp.path())?;
+    assert_eq!(db.ask(b"key")?, b"value");
+    assert!(logged(log::Level::Warn, "Ignoring torn record"));
+    assert!(logged(log::Level::Warn, "Dropping 24 bytes past"));
+    Ok(())
+}
@@ -67,6 +67,24 @@ fn test_process_lock_contention() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_process_lock_exit_code() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db_path = temp.path();
+
+    // Hold the database open
+    let _db = bitask::db::Bitask::open(db_path)?;
+
+    // The CLI reports the lock with EX_TEMPFAIL so scripts can retry
+    let output = command_ask(db_path, "foo")?;
+    assert_eq!(output.status.code(), Some(75));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("database is locked by another process")
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_process_recovery_after_crash() -> anyhow::Result<()> {
     let temp = tempdir()?;
@@ -270,3 +288,206 @@ fn test_compaction_with_cli() -> anyhow::Result<()> {
     println!("Test completed successfully");
     Ok(())
 }
+
+#[test]
+fn test_max_file_size_env() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db_path = temp.path();
+
+    let put = |key: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "put", "--key", key, "--value", "bar"])
+            .env("BITASK_PATH", db_path.to_str().unwrap())
+            .env("BITASK_MAX_FILE_SIZE", "1")
+            .output()
+    };
+
+    // The second put finds the active file over the limit and rotates
+    assert!(put("foo")?.status.success());
+    assert!(put("foo2")?.status.success());
+
+    let sealed_files = std::fs::read_dir(db_path)?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(".log") && !name.contains(".active.log")
+        })
+        .count();
+    assert_eq!(
+        sealed_files, 1,
+        "Expected a rotation with a tiny max file size"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_max_file_size_env() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let output = Command::new("cargo")
+        .args(["run", "--", "put", "--key", "foo", "--value", "bar"])
+        .env("BITASK_PATH", temp.path().to_str().unwrap())
+        .env("BITASK_MAX_FILE_SIZE", "tiny")
+        .output()?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("BITASK_MAX_FILE_SIZE"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compact_dry_run_with_cli() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db_path = temp.path();
+
+    let mut db = bitask::db::Bitask::builder()
+        .path(db_path)
+        .max_file_size(256)
+        .open()?;
+    for round in 0..5 {
+        for i in 0..10 {
+            let key = format!("key{}", i).into_bytes();
+            db.put(key, format!("value{}-{}", i, round).into_bytes())?;
+        }
+    }
+    drop(db);
+    let files_before = std::fs::read_dir(db_path)?.count();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "compact", "--dry-run"])
+        .env("BITASK_PATH", db_path.to_str().unwrap())
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("bytes reclaimable"), "{}", stdout);
+    assert!(!stdout.starts_with("0 files"), "{}", stdout);
+
+    // Nothing was compacted
+    assert_eq!(std::fs::read_dir(db_path)?.count(), files_before);
+
+    Ok(())
+}
+
+#[test]
+fn test_get_several_keys_with_cli() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db_path = temp.path();
+
+    let mut db = bitask::db::Bitask::open(db_path)?;
+    db.put(b"foo".to_vec(), b"bar".to_vec())?;
+    db.put(b"baz".to_vec(), b"qux".to_vec())?;
+    drop(db);
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "get", "--key", "foo", "--key", "missing", "--key", "baz",
+        ])
+        .env("BITASK_PATH", db_path.to_str().unwrap())
+        .output()?;
+
+    // Found values are printed in order, the missing key is reported and fails the run
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bar\nqux\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Key not found: missing"), "{}", stderr);
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_key_fails_before_opening_with_cli() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db_path = temp.path();
+
+    // Hold the lock, so a run that opened the database would fail on it instead
+    let _db = bitask::db::Bitask::open(db_path)?;
+
+    for args in [
+        vec!["put", "--key", "", "--value", "value"],
+        vec!["ask", "--key", ""],
+        vec!["remove", "--key", ""],
+    ] {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(&args)
+            .env("BITASK_PATH", db_path.to_str().unwrap())
+            .output()?;
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("empty key not allowed"), "{}", stderr);
+        assert!(!stderr.contains("Only one writer"), "{}", stderr);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_with_cli() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let src = temp.path().join("src");
+    let dest = temp.path().join("dest");
+
+    let mut db = bitask::db::Bitask::open(&src)?;
+    db.put(b"foo".to_vec(), b"bar".to_vec())?;
+    drop(db);
+
+    // Garbage after the last record
+    let active = std::fs::read_dir(&src)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.to_string_lossy().ends_with(".active.log"))
+        .unwrap();
+    let mut file = std::fs::OpenOptions::new().append(true).open(active)?;
+    std::io::Write::write_all(&mut file, b"garbage")?;
+    drop(file);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "repair", "--dest", dest.to_str().unwrap()])
+        .env("BITASK_PATH", src.to_str().unwrap())
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 keys recovered"), "{}", stdout);
+    assert!(stdout.contains("7 bytes skipped"), "{}", stdout);
+
+    let mut repaired = bitask::db::Bitask::open(&dest)?;
+    assert_eq!(repaired.ask(b"foo")?, b"bar");
+
+    Ok(())
+}
+
+#[test]
+fn test_path_flag_with_cli() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let flag_path = temp.path().join("flag");
+    let env_path = temp.path().join("env");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--path", flag_path.to_str().unwrap()])
+        .args(["put", "--key", "foo", "--value", "bar"])
+        .env_remove("BITASK_PATH")
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+
+    // --path wins over the environment variable, and works after the subcommand
+    let output = Command::new("cargo")
+        .args(["run", "--", "ask", "--key", "foo"])
+        .args(["--path", flag_path.to_str().unwrap()])
+        .env("BITASK_PATH", env_path.to_str().unwrap())
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "bar");
+    assert!(!env_path.exists());
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "ask", "--key", "foo"])
+        .env_remove("BITASK_PATH")
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--path or set BITASK_PATH"), "{}", stderr);
+
+    Ok(())
+}
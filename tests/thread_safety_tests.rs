@@ -108,3 +108,104 @@ fn test_concurrent_mixed_operations() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_shared_concurrent_reads() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db = bitask::db::SharedBitask::open(temp.path())?;
+
+    // Setup test data
+    db.put(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let db_clone = db.clone();
+        let handle = thread::spawn(move || {
+            let value = db_clone.ask(b"key1").unwrap();
+            assert_eq!(value, b"value1");
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_shared_concurrent_writes() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db = bitask::db::SharedBitask::open(temp.path())?;
+    let mut handles = vec![];
+
+    // Create multiple writer threads
+    for i in 0..10 {
+        let db_clone = db.clone();
+        let handle = thread::spawn(move || {
+            let key = format!("key{}", i).into_bytes();
+            let value = format!("value{}", i).into_bytes();
+            db_clone.put(key, value).unwrap();
+        });
+        handles.push(handle);
+    }
+
+    // Wait for all writes to complete
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Verify all writes succeeded
+    for i in 0..10 {
+        let key = format!("key{}", i).into_bytes();
+        let expected = format!("value{}", i).into_bytes();
+        assert_eq!(db.ask(&key)?, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_shared_concurrent_mixed_operations() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let db = bitask::db::SharedBitask::open(temp.path())?;
+    let mut handles = vec![];
+
+    // Setup initial data
+    db.put(b"shared_key".to_vec(), b"initial_value".to_vec())?;
+
+    // Create threads that do mixed operations
+    for i in 0..10 {
+        let db_clone = db.clone();
+        let handle = thread::spawn(move || {
+            if i % 2 == 0 {
+                // Even threads write
+                let key = format!("key{}", i).into_bytes();
+                let value = format!("value{}", i).into_bytes();
+                db_clone.put(key, value).unwrap();
+            } else {
+                // Odd threads read and remove their own key
+                let _ = db_clone.ask(b"shared_key").unwrap();
+                db_clone.remove(format!("key{}", i).into_bytes()).unwrap();
+            }
+        });
+        handles.push(handle);
+    }
+
+    // Wait for all operations to complete
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    db.compact()?;
+
+    // Verify writes succeeded
+    for i in (0..10).step_by(2) {
+        let key = format!("key{}", i).into_bytes();
+        let expected = format!("value{}", i).into_bytes();
+        assert_eq!(db.ask(&key)?, expected);
+    }
+
+    Ok(())
+}